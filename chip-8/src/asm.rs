@@ -0,0 +1,753 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::disasm::Instruction;
+use crate::interpreter::Interpreter;
+
+// How deep constants can refer to other constants before being considered recursive
+const MAX_SYMBOL_DEPTH: usize = 64;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AsmErrorKind {
+    UnknownMnemonic(String),
+    InvalidOperands(String),
+    InvalidExpression(String),
+    UnknownSymbol(String),
+    DuplicateSymbol(String),
+    RecursiveSymbol(String),
+    OutOfRange(i64),
+    DivisionByZero,
+    ProgramTooLarge(usize),
+}
+
+/// An assembly error and the line (starting at 1) it was found on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub line: usize,
+    pub kind: AsmErrorKind,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: ", self.line)?;
+
+        match &self.kind {
+            AsmErrorKind::UnknownMnemonic(mnemonic) => write!(f, "unknown mnemonic {}", mnemonic),
+            AsmErrorKind::InvalidOperands(mnemonic) => {
+                write!(f, "invalid operands for {}", mnemonic)
+            }
+            AsmErrorKind::InvalidExpression(text) => write!(f, "invalid expression {:?}", text),
+            AsmErrorKind::UnknownSymbol(name) => write!(f, "unknown symbol {}", name),
+            AsmErrorKind::DuplicateSymbol(name) => write!(f, "{} is already defined", name),
+            AsmErrorKind::RecursiveSymbol(name) => {
+                write!(f, "{} is defined in terms of itself", name)
+            }
+            AsmErrorKind::OutOfRange(value) => write!(f, "value {} is out of range", value),
+            AsmErrorKind::DivisionByZero => write!(f, "division by zero"),
+            AsmErrorKind::ProgramTooLarge(size) => {
+                write!(f, "program is {} bytes, past the end of the memory", size)
+            }
+        }
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Expr {
+    Number(i64),
+    Symbol(String),
+    // `$`, the address of the current statement
+    Here,
+    Unary(char, Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Number(i64),
+    Symbol(String),
+    Here,
+    Operator(&'static str),
+    Open,
+    Close,
+}
+
+const OPERATORS: [&str; 13] = [
+    "<<", ">>", "+", "-", "*", "/", "%", "&", "|", "^", "~", "(", ")",
+];
+
+fn is_symbol_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '.'
+}
+
+fn parse_number(text: &str) -> Option<i64> {
+    let lower = text.to_ascii_lowercase();
+
+    if let Some(hex) = lower.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()
+    } else if let Some(binary) = lower.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()
+    } else {
+        lower.parse().ok()
+    }
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, AsmErrorKind> {
+    let invalid = || AsmErrorKind::InvalidExpression(text.trim().to_string());
+    let mut tokens = Vec::new();
+    let mut rest = text.trim_start();
+
+    while let Some(c) = rest.chars().next() {
+        if c.is_ascii_digit() {
+            let end = rest
+                .find(|c: char| !is_symbol_char(c))
+                .unwrap_or(rest.len());
+            tokens.push(Token::Number(
+                parse_number(&rest[..end]).ok_or_else(invalid)?,
+            ));
+            rest = &rest[end..];
+        } else if is_symbol_char(c) {
+            let end = rest
+                .find(|c: char| !is_symbol_char(c))
+                .unwrap_or(rest.len());
+            tokens.push(Token::Symbol(rest[..end].to_string()));
+            rest = &rest[end..];
+        } else if c == '$' {
+            tokens.push(Token::Here);
+            rest = &rest[1..];
+        } else {
+            let operator = OPERATORS
+                .iter()
+                .find(|operator| rest.starts_with(*operator))
+                .ok_or_else(invalid)?;
+            tokens.push(match *operator {
+                "(" => Token::Open,
+                ")" => Token::Close,
+                operator => Token::Operator(operator),
+            });
+            rest = &rest[operator.len()..];
+        }
+
+        rest = rest.trim_start();
+    }
+
+    Ok(tokens)
+}
+
+/// Precedence climbing over the tokens, lowest precedence first.
+struct ExprParser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+const PRECEDENCE: [&[&str]; 6] = [
+    &["|"],
+    &["^"],
+    &["&"],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+impl ExprParser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn binary(&mut self, level: usize) -> Option<Expr> {
+        if level == PRECEDENCE.len() {
+            return self.unary();
+        }
+
+        let mut left = self.binary(level + 1)?;
+
+        while let Some(&Token::Operator(operator)) = self.peek() {
+            if !PRECEDENCE[level].contains(&operator) {
+                break;
+            }
+
+            self.position += 1;
+            let right = self.binary(level + 1)?;
+            left = Expr::Binary(operator, Box::new(left), Box::new(right));
+        }
+
+        Some(left)
+    }
+
+    fn unary(&mut self) -> Option<Expr> {
+        let token = self.peek()?.clone();
+        self.position += 1;
+
+        match token {
+            Token::Number(value) => Some(Expr::Number(value)),
+            Token::Symbol(name) => Some(Expr::Symbol(name)),
+            Token::Here => Some(Expr::Here),
+            Token::Operator("-") => Some(Expr::Unary('-', Box::new(self.unary()?))),
+            Token::Operator("~") => Some(Expr::Unary('~', Box::new(self.unary()?))),
+            Token::Operator("+") => self.unary(),
+            Token::Open => {
+                let expr = self.binary(0)?;
+                match self.peek() {
+                    Some(Token::Close) => {
+                        self.position += 1;
+                        Some(expr)
+                    }
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+fn parse_expr(text: &str) -> Result<Expr, AsmErrorKind> {
+    let mut parser = ExprParser {
+        tokens: tokenize(text)?,
+        position: 0,
+    };
+
+    match parser.binary(0) {
+        Some(expr) if parser.position == parser.tokens.len() => Ok(expr),
+        _ => Err(AsmErrorKind::InvalidExpression(text.trim().to_string())),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Operand {
+    V(u8),
+    // XO-CHIP `V2-V5`
+    VRange(u8, u8),
+    I,
+    // `[I]`
+    IndirectI,
+    Dt,
+    St,
+    K,
+    F,
+    Hf,
+    B,
+    R,
+    // XO-CHIP `LONG NNNN`
+    Long(Expr),
+    Expr(Expr),
+}
+
+fn parse_register(text: &str) -> Option<u8> {
+    let digit = text.strip_prefix('V')?;
+
+    match digit.len() {
+        1 => u8::from_str_radix(digit, 16).ok(),
+        _ => None,
+    }
+}
+
+fn parse_operand(text: &str) -> Result<Operand, AsmErrorKind> {
+    let upper = text.to_ascii_uppercase();
+
+    if let Some(register) = parse_register(&upper) {
+        return Ok(Operand::V(register));
+    }
+
+    if let Some((x, y)) = upper.split_once('-') {
+        if let (Some(x), Some(y)) = (parse_register(x.trim()), parse_register(y.trim())) {
+            return Ok(Operand::VRange(x, y));
+        }
+    }
+
+    match upper.as_str() {
+        "I" => Ok(Operand::I),
+        "[I]" => Ok(Operand::IndirectI),
+        "DT" => Ok(Operand::Dt),
+        "ST" => Ok(Operand::St),
+        "K" => Ok(Operand::K),
+        "F" => Ok(Operand::F),
+        "HF" => Ok(Operand::Hf),
+        "B" => Ok(Operand::B),
+        "R" => Ok(Operand::R),
+        _ => match upper.strip_prefix("LONG ") {
+            Some(_) => Ok(Operand::Long(parse_expr(&text[5..])?)),
+            None => Ok(Operand::Expr(parse_expr(text)?)),
+        },
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Statement {
+    Instruction(String, Vec<Operand>),
+    Bytes(Vec<Expr>),
+    Words(Vec<Expr>),
+}
+
+impl Statement {
+    fn size(&self) -> usize {
+        match self {
+            Statement::Instruction(_, operands) => {
+                match operands
+                    .iter()
+                    .any(|operand| matches!(operand, Operand::Long(_)))
+                {
+                    true => 4,
+                    false => 2,
+                }
+            }
+            Statement::Bytes(values) => values.len(),
+            Statement::Words(values) => values.len() * 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Symbol {
+    Label(u16),
+    // The expression and the address of its definition, for `$`
+    Constant(Expr, u16),
+}
+
+struct Assembler {
+    symbols: HashMap<String, Symbol>,
+}
+
+impl Assembler {
+    fn define(&mut self, name: &str, symbol: Symbol) -> Result<(), AsmErrorKind> {
+        if self.symbols.contains_key(name) {
+            return Err(AsmErrorKind::DuplicateSymbol(name.to_string()));
+        }
+
+        self.symbols.insert(name.to_string(), symbol);
+        Ok(())
+    }
+
+    fn eval(&self, expr: &Expr, here: u16, depth: usize) -> Result<i64, AsmErrorKind> {
+        match expr {
+            Expr::Number(value) => Ok(*value),
+            Expr::Here => Ok(here as i64),
+            Expr::Symbol(name) => match self.symbols.get(name) {
+                Some(Symbol::Label(address)) => Ok(*address as i64),
+                Some(Symbol::Constant(_, _)) if depth >= MAX_SYMBOL_DEPTH => {
+                    Err(AsmErrorKind::RecursiveSymbol(name.clone()))
+                }
+                Some(Symbol::Constant(expr, address)) => self.eval(expr, *address, depth + 1),
+                None => Err(AsmErrorKind::UnknownSymbol(name.clone())),
+            },
+            Expr::Unary(operator, expr) => {
+                let value = self.eval(expr, here, depth)?;
+                match operator {
+                    '-' => Ok(value.wrapping_neg()),
+                    _ => Ok(!value),
+                }
+            }
+            Expr::Binary(operator, left, right) => {
+                let left = self.eval(left, here, depth)?;
+                let right = self.eval(right, here, depth)?;
+
+                match *operator {
+                    "+" => Ok(left.wrapping_add(right)),
+                    "-" => Ok(left.wrapping_sub(right)),
+                    "*" => Ok(left.wrapping_mul(right)),
+                    "/" | "%" if right == 0 => Err(AsmErrorKind::DivisionByZero),
+                    "/" => Ok(left.wrapping_div(right)),
+                    "%" => Ok(left.wrapping_rem(right)),
+                    "&" => Ok(left & right),
+                    "|" => Ok(left | right),
+                    "^" => Ok(left ^ right),
+                    "<<" => Ok(left.checked_shl(right as u32).unwrap_or(0)),
+                    _ => Ok(left.checked_shr(right as u32).unwrap_or(0)),
+                }
+            }
+        }
+    }
+
+    /// Evaluate `expr` and make sure it fits in `min..=max`.
+    fn value(&self, expr: &Expr, here: u16, min: i64, max: i64) -> Result<i64, AsmErrorKind> {
+        let value = self.eval(expr, here, 0)?;
+
+        match (min..=max).contains(&value) {
+            true => Ok(value),
+            false => Err(AsmErrorKind::OutOfRange(value)),
+        }
+    }
+
+    fn address(&self, expr: &Expr, here: u16) -> Result<u16, AsmErrorKind> {
+        Ok(self.value(expr, here, 0, 0xFFF)? as u16)
+    }
+
+    // Bytes may be written signed, -1 being 0xFF
+    fn byte(&self, expr: &Expr, here: u16) -> Result<u8, AsmErrorKind> {
+        Ok(self.value(expr, here, -0x80, 0xFF)? as u8)
+    }
+
+    fn nibble(&self, expr: &Expr, here: u16) -> Result<u8, AsmErrorKind> {
+        Ok(self.value(expr, here, 0, 0xF)? as u8)
+    }
+
+    fn word(&self, expr: &Expr, here: u16) -> Result<u16, AsmErrorKind> {
+        Ok(self.value(expr, here, -0x8000, 0xFFFF)? as u16)
+    }
+
+    /// Turn a mnemonic and its operands into the instruction, plus the address of a long load.
+    fn instruction(
+        &self,
+        mnemonic: &str,
+        operands: &[Operand],
+        here: u16,
+    ) -> Result<(Instruction, Option<u16>), AsmErrorKind> {
+        use Operand::*;
+
+        let instruction = match (mnemonic, operands) {
+            ("CLS", []) => Instruction::Cls,
+            ("RET", []) => Instruction::Ret,
+            ("SCR", []) => Instruction::Scr,
+            ("SCL", []) => Instruction::Scl,
+            ("EXIT", []) => Instruction::Exit,
+            ("LOW", []) => Instruction::Low,
+            ("HIGH", []) => Instruction::High,
+            ("AUDIO", []) => Instruction::Audio,
+            ("SCD", [Expr(n)]) => Instruction::Scd(self.nibble(n, here)?),
+            ("SCU", [Expr(n)]) => Instruction::Scu(self.nibble(n, here)?),
+            ("PLANE", [Expr(n)]) => Instruction::Plane(self.nibble(n, here)?),
+            ("JP", [Expr(nnn)]) => Instruction::Jp(self.address(nnn, here)?),
+            ("JP", [V(0), Expr(nnn)]) => Instruction::JpV0Nnn(self.address(nnn, here)?),
+            ("CALL", [Expr(nnn)]) => Instruction::Call(self.address(nnn, here)?),
+            ("SE", [V(x), Expr(kk)]) => Instruction::SeVxKk(*x, self.byte(kk, here)?),
+            ("SE", [V(x), V(y)]) => Instruction::SeVxVy(*x, *y),
+            ("SNE", [V(x), Expr(kk)]) => Instruction::SneVxKk(*x, self.byte(kk, here)?),
+            ("SNE", [V(x), V(y)]) => Instruction::SneVxVy(*x, *y),
+            ("LD", [V(x), Expr(kk)]) => Instruction::LdVxKk(*x, self.byte(kk, here)?),
+            ("LD", [V(x), V(y)]) => Instruction::LdVxVy(*x, *y),
+            ("LD", [I, Expr(nnn)]) => Instruction::LdINnn(self.address(nnn, here)?),
+            ("LD", [I, Long(nnnn)]) => {
+                return Ok((Instruction::LdILong, Some(self.word(nnnn, here)?)));
+            }
+            ("LD", [V(x), Dt]) => Instruction::LdVxDt(*x),
+            ("LD", [V(x), K]) => Instruction::LdVxK(*x),
+            ("LD", [Dt, V(x)]) => Instruction::LdDtVx(*x),
+            ("LD", [St, V(x)]) => Instruction::LdStVx(*x),
+            ("LD", [F, V(x)]) => Instruction::LdFVx(*x),
+            ("LD", [Hf, V(x)]) => Instruction::LdHfVx(*x),
+            ("LD", [B, V(x)]) => Instruction::LdBVx(*x),
+            ("LD", [IndirectI, V(x)]) => Instruction::LdIVx(*x),
+            ("LD", [V(x), IndirectI]) => Instruction::LdVxI(*x),
+            ("LD", [R, V(x)]) => Instruction::LdRVx(*x),
+            ("LD", [V(x), R]) => Instruction::LdVxR(*x),
+            ("LD", [IndirectI, VRange(x, y)]) => Instruction::LdIVxVy(*x, *y),
+            ("LD", [VRange(x, y), IndirectI]) => Instruction::LdVxVyI(*x, *y),
+            ("ADD", [V(x), Expr(kk)]) => Instruction::AddVxKk(*x, self.byte(kk, here)?),
+            ("ADD", [V(x), V(y)]) => Instruction::AddVxVy(*x, *y),
+            ("ADD", [I, V(x)]) => Instruction::AddIVx(*x),
+            ("OR", [V(x), V(y)]) => Instruction::OrVxVy(*x, *y),
+            ("AND", [V(x), V(y)]) => Instruction::AndVxVy(*x, *y),
+            ("XOR", [V(x), V(y)]) => Instruction::XorVxVy(*x, *y),
+            ("SUB", [V(x), V(y)]) => Instruction::SubVxVy(*x, *y),
+            ("SUBN", [V(x), V(y)]) => Instruction::SubnVxVy(*x, *y),
+            // Vy only matters without the shift quirk, shifting Vx in place by default
+            ("SHR", [V(x)]) => Instruction::ShrVxVy(*x, *x),
+            ("SHR", [V(x), V(y)]) => Instruction::ShrVxVy(*x, *y),
+            ("SHL", [V(x)]) => Instruction::ShlVxVy(*x, *x),
+            ("SHL", [V(x), V(y)]) => Instruction::ShlVxVy(*x, *y),
+            ("RND", [V(x), Expr(kk)]) => Instruction::RndVxKk(*x, self.byte(kk, here)?),
+            ("DRW", [V(x), V(y), Expr(n)]) => Instruction::DrwVxVyN(*x, *y, self.nibble(n, here)?),
+            ("SKP", [V(x)]) => Instruction::SkpVx(*x),
+            ("SKNP", [V(x)]) => Instruction::SknpVx(*x),
+            ("PITCH", [V(x)]) => Instruction::PitchVx(*x),
+            (
+                "CLS" | "RET" | "SCR" | "SCL" | "EXIT" | "LOW" | "HIGH" | "AUDIO" | "SCD" | "SCU"
+                | "PLANE" | "JP" | "CALL" | "SE" | "SNE" | "LD" | "ADD" | "OR" | "AND" | "XOR"
+                | "SUB" | "SUBN" | "SHR" | "SHL" | "RND" | "DRW" | "SKP" | "SKNP" | "PITCH",
+                _,
+            ) => return Err(AsmErrorKind::InvalidOperands(mnemonic.to_string())),
+            _ => return Err(AsmErrorKind::UnknownMnemonic(mnemonic.to_string())),
+        };
+
+        Ok((instruction, None))
+    }
+}
+
+/// Split `label: rest` into its label, if the line starts with one.
+fn split_label(text: &str) -> Option<(&str, &str)> {
+    let (label, rest) = text.split_once(':')?;
+    let label = label.trim();
+
+    match !label.is_empty() && label.chars().all(is_symbol_char) {
+        true => Some((label, rest)),
+        false => None,
+    }
+}
+
+fn split_operands(text: &str) -> Vec<&str> {
+    match text.trim() {
+        "" => Vec::new(),
+        text => text.split(',').map(str::trim).collect(),
+    }
+}
+
+/// Where each source line with an instruction ended up, from `assemble_with_source_map()`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SourceMap {
+    // (line, address) ordered by both, lines starting at 1
+    entries: Vec<(usize, u16)>,
+}
+
+impl SourceMap {
+    pub fn entries(&self) -> &[(usize, u16)] {
+        &self.entries
+    }
+
+    /// The line of the instruction at `address`.
+    pub fn line(&self, address: u16) -> Option<usize> {
+        self.entries
+            .iter()
+            .find(|(_, start)| *start == address)
+            .map(|(line, _)| *line)
+    }
+
+    /// The first instruction at or after `line`, with the line it is on.
+    pub fn resolve(&self, line: usize) -> Option<(usize, u16)> {
+        self.entries
+            .iter()
+            .find(|(start, _)| *start >= line)
+            .copied()
+    }
+}
+
+/// Assemble Cowgod-syntax source into a ROM loaded at 0x200.
+///
+/// Mnemonics and registers are case insensitive, comments start with `;`.
+/// Each line holds any number of `label:` followed by an instruction,
+/// a `db`/`dw` directive with comma separated values, or a `NAME equ value` constant.
+/// Values are expressions over numbers (`12`, `0x0C`, `0b1100`), labels, constants
+/// and `$` for the address of the line, with the usual C operators.
+pub fn assemble(source: &str) -> Result<Vec<u8>, AsmError> {
+    assemble_with_source_map(source).map(|(rom, _)| rom)
+}
+
+/// Like `assemble()`, also telling which line each instruction comes from.
+pub fn assemble_with_source_map(source: &str) -> Result<(Vec<u8>, SourceMap), AsmError> {
+    let mut assembler = Assembler {
+        symbols: HashMap::new(),
+    };
+    let mut statements = Vec::new();
+    let mut constants = Vec::new();
+    let mut address = Interpreter::PROGRAM_START as usize;
+
+    // First pass: parse every line and give each label its address
+    for (index, line) in source.lines().enumerate() {
+        let number = index + 1;
+        let error = |kind| AsmError { line: number, kind };
+        let mut text = line.split(';').next().unwrap_or("").trim();
+
+        while let Some((label, rest)) = split_label(text) {
+            assembler
+                .define(label, Symbol::Label(address as u16))
+                .map_err(error)?;
+            text = rest.trim();
+        }
+
+        if text.is_empty() {
+            continue;
+        }
+
+        let (word, rest) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+        let rest = rest.trim();
+
+        if let Some((directive, value)) = rest.split_once(char::is_whitespace) {
+            if directive.eq_ignore_ascii_case("equ") {
+                let expr = parse_expr(value).map_err(error)?;
+                assembler
+                    .define(word, Symbol::Constant(expr, address as u16))
+                    .map_err(error)?;
+                constants.push((number, word.to_string()));
+                continue;
+            }
+        }
+
+        let mnemonic = word.to_ascii_uppercase();
+        let parse_all = |operands: Vec<&str>| -> Result<Vec<Expr>, AsmError> {
+            operands
+                .into_iter()
+                .map(|operand| parse_expr(operand).map_err(error))
+                .collect()
+        };
+
+        let statement = match mnemonic.as_str() {
+            "DB" => Statement::Bytes(parse_all(split_operands(rest))?),
+            "DW" => Statement::Words(parse_all(split_operands(rest))?),
+            _ => Statement::Instruction(
+                mnemonic,
+                split_operands(rest)
+                    .into_iter()
+                    .map(parse_operand)
+                    .collect::<Result<_, _>>()
+                    .map_err(error)?,
+            ),
+        };
+
+        let here = address as u16;
+        address += statement.size();
+        statements.push((number, here, statement));
+    }
+
+    // Constants are checked where they are defined, whether they are used or not
+    for (number, name) in constants {
+        assembler
+            .eval(&Expr::Symbol(name), 0, 0)
+            .map_err(|kind| AsmError { line: number, kind })?;
+    }
+
+    let size = address - Interpreter::PROGRAM_START as usize;
+    if address > 0x10000 {
+        return Err(AsmError {
+            line: statements.last().map_or(0, |(number, _, _)| *number),
+            kind: AsmErrorKind::ProgramTooLarge(size),
+        });
+    }
+
+    // Second pass: every symbol is known, encode the statements
+    let mut rom = Vec::with_capacity(size);
+    let mut source_map = SourceMap::default();
+
+    for (number, here, statement) in statements {
+        let error = |kind| AsmError { line: number, kind };
+
+        match statement {
+            Statement::Instruction(mnemonic, operands) => {
+                source_map.entries.push((number, here));
+                let (instruction, long) = assembler
+                    .instruction(&mnemonic, &operands, here)
+                    .map_err(error)?;
+                rom.extend_from_slice(&instruction.encode().to_be_bytes());
+                if let Some(address) = long {
+                    rom.extend_from_slice(&address.to_be_bytes());
+                }
+            }
+            Statement::Bytes(values) => {
+                for value in values.iter() {
+                    rom.push(assembler.byte(value, here).map_err(error)?);
+                }
+            }
+            Statement::Words(values) => {
+                for value in values.iter() {
+                    let word = assembler.word(value, here).map_err(error)?;
+                    rom.extend_from_slice(&word.to_be_bytes());
+                }
+            }
+        }
+    }
+
+    Ok((rom, source_map))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{assemble, assemble_with_source_map, AsmError, AsmErrorKind};
+    use crate::interpreter::Interpreter;
+
+    #[test]
+    fn test_assemble() {
+        let rom = assemble(
+            "
+            SPEED equ 3 * 2 - 1     ; constants can be expressions
+            start:  LD V3, 0xAA
+                    ld v1, SPEED
+                    DRW V0, V1, 5
+                    LD [I], V3-V5
+                    LD I, LONG sprite
+                    JP start
+            sprite: db 0b11110000, -1
+                    dw 0x1234, $
+            ",
+        )
+        .unwrap();
+
+        assert_eq!(
+            rom,
+            [
+                0x63, 0xAA, 0x61, 0x05, 0xD0, 0x15, 0x53, 0x52, 0xF0, 0x00, 0x02, 0x0E, 0x12, 0x00,
+                0xF0, 0xFF, 0x12, 0x34, 0x02, 0x10,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_run_assembled() {
+        let rom = assemble(
+            "
+                    LD V0, 0
+            loop:   ADD V0, 1
+                    SE V0, COUNT
+                    JP loop
+            done:   JP done
+            COUNT   equ 10
+            ",
+        )
+        .unwrap();
+
+        let mut interpreter = Interpreter::new();
+        interpreter.load_rom(&rom).unwrap();
+        for _ in 0..40 {
+            interpreter.cycle().unwrap();
+        }
+
+        assert_eq!(interpreter.v[0], 10);
+        assert_eq!(interpreter.pc, 0x208);
+    }
+
+    #[test]
+    fn test_source_map() {
+        let (_, source_map) = assemble_with_source_map(
+            "; sprite drawing
+            start:  LD I, sprite
+
+                    DRW V0, V1, 1
+                    JP start
+            sprite: db 0xFF
+                    CLS
+            ",
+        )
+        .unwrap();
+
+        assert_eq!(
+            source_map.entries(),
+            [(2, 0x200), (4, 0x202), (5, 0x204), (7, 0x207)]
+        );
+        assert_eq!(source_map.line(0x204), Some(5));
+        assert_eq!(source_map.line(0x206), None);
+        assert_eq!(source_map.resolve(3), Some((4, 0x202)));
+        assert_eq!(source_map.resolve(6), Some((7, 0x207)));
+        assert_eq!(source_map.resolve(8), None);
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |line, kind| Err(AsmError { line, kind });
+
+        assert_eq!(
+            assemble("CLS\nFOO V0"),
+            error(2, AsmErrorKind::UnknownMnemonic("FOO".to_string()))
+        );
+        assert_eq!(
+            assemble("OR V0, 1"),
+            error(1, AsmErrorKind::InvalidOperands("OR".to_string()))
+        );
+        assert_eq!(
+            assemble("\n\nJP nowhere"),
+            error(3, AsmErrorKind::UnknownSymbol("nowhere".to_string()))
+        );
+        assert_eq!(
+            assemble("a:\na: CLS"),
+            error(2, AsmErrorKind::DuplicateSymbol("a".to_string()))
+        );
+        assert_eq!(
+            assemble("LD V0, 256"),
+            error(1, AsmErrorKind::OutOfRange(256))
+        );
+        assert_eq!(
+            assemble("A equ B\nB equ A"),
+            error(1, AsmErrorKind::RecursiveSymbol("A".to_string()))
+        );
+        assert_eq!(
+            assemble("db 1 +"),
+            error(1, AsmErrorKind::InvalidExpression("1 +".to_string()))
+        );
+    }
+}
@@ -0,0 +1,254 @@
+//! The beeper and XO-CHIP audio patterns, as PCM samples for the host to play.
+
+use std::f32::consts::TAU;
+#[cfg(not(target_arch = "wasm32"))]
+use std::io::{self, Write};
+
+use crate::interpreter::variant::Variant;
+use crate::interpreter::Interpreter;
+
+/// How long the volume takes to go from silent to full and back, so the tone doesn't
+/// start or stop with a click.
+const RAMP_SECONDS: f32 = 0.005;
+
+/// Bits per second an XO-CHIP audio pattern plays at: 4000 for the default pitch of 64,
+/// doubling every 48 steps.
+pub fn pattern_rate(pitch: u8) -> f32 {
+    4000.0 * 2f32.powf((pitch as f32 - 64.0) / 48.0)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Waveform {
+    Square,
+    Sine,
+}
+
+/// Generates the tone heard while the sound timer is running: a beep, or the audio pattern
+/// of an XO-CHIP program once it loaded one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Audio {
+    pub sample_rate: u32,
+    pub waveform: Waveform,
+    /// From 0 to 1.
+    pub volume: f32,
+    /// Pitch of the beep in Hz.
+    pub frequency: f32,
+    // Position in the current period, from 0 to 1
+    phase: f32,
+    // Gain applied by the envelope, from 0 to 1
+    level: f32,
+}
+
+impl Audio {
+    pub const DEFAULT_FREQUENCY: f32 = 440.0;
+
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            sample_rate: sample_rate.max(1),
+            waveform: Waveform::Square,
+            volume: 0.25,
+            frequency: Audio::DEFAULT_FREQUENCY,
+            phase: 0.0,
+            level: 0.0,
+        }
+    }
+
+    /// Samples in frame number `frame`, frames being 1/60 of a second.
+    /// Going through `frame_len(0)`, `frame_len(1)`... keeps in sync with the timers even
+    /// when the sample rate isn't a multiple of 60.
+    pub fn frame_len(&self, frame: u64) -> usize {
+        let rate = self.sample_rate as u64;
+        ((frame + 1) * rate / 60 - frame * rate / 60) as usize
+    }
+
+    /// Fill `buffer` with what `interpreter` sounds like for that long while its sound timer
+    /// is running, and silence otherwise.
+    ///
+    /// An XO-CHIP pattern is played from its most significant bit, a 1 being high and a 0
+    /// low. A pattern of zeros is taken as none loaded, which beeps.
+    pub fn render(&mut self, interpreter: &Interpreter, buffer: &mut [f32]) {
+        let beeping = interpreter.stimer > 0;
+        let pattern = interpreter.audio_pattern;
+
+        if interpreter.variant == Variant::XoChip && pattern.iter().any(|byte| *byte != 0) {
+            let bits = pattern.len() * 8;
+            let frequency = pattern_rate(interpreter.pitch) / bits as f32;
+
+            self.render_wave(beeping, frequency, buffer, |phase| {
+                let bit = (phase * bits as f32) as usize % bits;
+                match pattern[bit / 8] >> (7 - bit % 8) & 1 {
+                    1 => 1.0,
+                    _ => -1.0,
+                }
+            });
+        } else {
+            let waveform = self.waveform;
+
+            self.render_wave(beeping, self.frequency, buffer, |phase| match waveform {
+                Waveform::Square if phase < 0.5 => 1.0,
+                Waveform::Square => -1.0,
+                Waveform::Sine => (phase * TAU).sin(),
+            });
+        }
+    }
+
+    /// Play `wave`, which gives the signal at a position from 0 to 1 in its period.
+    fn render_wave(
+        &mut self,
+        beeping: bool,
+        frequency: f32,
+        buffer: &mut [f32],
+        wave: impl Fn(f32) -> f32,
+    ) {
+        let step = frequency / self.sample_rate as f32;
+        let ramp = 1.0 / (RAMP_SECONDS * self.sample_rate as f32);
+        let target = if beeping { 1.0 } else { 0.0 };
+
+        for sample in buffer.iter_mut() {
+            self.level = match self.level < target {
+                true => (self.level + ramp).min(target),
+                false => (self.level - ramp).max(target),
+            };
+
+            if self.level == 0.0 {
+                // Start the next beep at the beginning of a period
+                self.phase = 0.0;
+                *sample = 0.0;
+                continue;
+            }
+
+            *sample = wave(self.phase) * self.volume * self.level;
+            self.phase = (self.phase + step).fract();
+        }
+    }
+}
+
+/// Write mono `samples` as a 16-bit PCM WAV file.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn write_wav(mut writer: impl Write, sample_rate: u32, samples: &[f32]) -> io::Result<()> {
+    let data_len = samples.len() as u32 * 2;
+
+    writer.write_all(b"RIFF")?;
+    writer.write_all(&(36 + data_len).to_le_bytes())?;
+    writer.write_all(b"WAVEfmt ")?;
+    writer.write_all(&16u32.to_le_bytes())?;
+    // PCM, mono
+    writer.write_all(&1u16.to_le_bytes())?;
+    writer.write_all(&1u16.to_le_bytes())?;
+    writer.write_all(&sample_rate.to_le_bytes())?;
+    writer.write_all(&(sample_rate * 2).to_le_bytes())?;
+    // Bytes per frame, bits per sample
+    writer.write_all(&2u16.to_le_bytes())?;
+    writer.write_all(&16u16.to_le_bytes())?;
+    writer.write_all(b"data")?;
+    writer.write_all(&data_len.to_le_bytes())?;
+
+    for sample in samples {
+        let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+        writer.write_all(&value.to_le_bytes())?;
+    }
+
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{pattern_rate, write_wav, Audio, Waveform};
+    use crate::interpreter::variant::Variant;
+    use crate::interpreter::Interpreter;
+
+    #[test]
+    fn test_beep() {
+        let mut audio = Audio::new(48_000);
+        audio.volume = 0.5;
+        let mut interpreter = Interpreter::new();
+        assert_eq!(audio.frame_len(0), 800);
+
+        let mut silence = vec![1.0; 800];
+        audio.render(&interpreter, &mut silence);
+        assert!(silence.iter().all(|sample| *sample == 0.0));
+
+        interpreter.stimer = 2;
+        let mut beep = vec![0.0; 800];
+        audio.render(&interpreter, &mut beep);
+        // Ramping up over 240 samples instead of jumping to full volume
+        assert!(beep[0] > 0.0 && beep[0] < 0.01);
+        assert!(beep[100] < 0.5);
+        assert_eq!(beep[240], 0.5);
+        // 440 Hz at 48 kHz: a little over 109 samples per period, half of it low
+        assert_eq!(beep[300], -0.5);
+        assert_eq!(beep.iter().fold(0.0f32, |max, s| max.max(s.abs())), 0.5);
+
+        interpreter.stimer = 0;
+        let mut release = vec![0.0; 800];
+        audio.render(&interpreter, &mut release);
+        assert!(release[0].abs() > 0.4);
+        assert!(release[240..].iter().all(|sample| *sample == 0.0));
+
+        audio.waveform = Waveform::Sine;
+        interpreter.stimer = 1;
+        audio.render(&interpreter, &mut beep);
+        assert_eq!(beep[0], 0.0);
+        assert!(beep.iter().all(|sample| sample.abs() <= 0.5));
+    }
+
+    #[test]
+    fn test_pattern() {
+        assert_eq!(pattern_rate(64), 4000.0);
+        assert_eq!(pattern_rate(112), 8000.0);
+        assert_eq!(pattern_rate(16), 2000.0);
+
+        let mut audio = Audio::new(8000);
+        audio.volume = 1.0;
+        let mut interpreter = Interpreter::new();
+        interpreter.variant = Variant::XoChip;
+        interpreter.stimer = 1;
+        // High for the first 64 bits, low for the other 64
+        interpreter.audio_pattern = [
+            0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0, 0, 0, 0, 0, 0, 0, 0,
+        ];
+
+        // Two samples per bit at the default pitch
+        let mut samples = vec![0.0; 512];
+        audio.render(&interpreter, &mut samples);
+        assert!(samples[40..128].iter().all(|sample| *sample == 1.0));
+        assert!(samples[128..256].iter().all(|sample| *sample == -1.0));
+        assert!(samples[256..384].iter().all(|sample| *sample == 1.0));
+
+        // An octave higher, one sample per bit
+        interpreter.pitch = 112;
+        interpreter.audio_pattern[0] = 0b0111_1111;
+        audio.render(&interpreter, &mut samples);
+        assert_eq!(samples[..2], [-1.0, 1.0]);
+        assert!(samples[64..128].iter().all(|sample| *sample == -1.0));
+
+        // Without a pattern, the usual beep
+        interpreter.audio_pattern = [0; 16];
+        audio.render(&interpreter, &mut samples);
+        assert_eq!(samples[0], 1.0);
+        assert_eq!(samples[10], -1.0);
+    }
+
+    #[test]
+    fn test_frame_len() {
+        let audio = Audio::new(44_100);
+        let total: usize = (0..60).map(|frame| audio.frame_len(frame)).sum();
+        assert_eq!(total, 44_100);
+
+        let audio = Audio::new(22_050);
+        let lengths: Vec<_> = (0..4).map(|frame| audio.frame_len(frame)).collect();
+        assert_eq!(lengths, [367, 368, 367, 368]);
+    }
+
+    #[test]
+    fn test_write_wav() {
+        let mut wav = Vec::new();
+        write_wav(&mut wav, 8000, &[0.0, 1.0, -1.0]).unwrap();
+
+        assert_eq!(wav.len(), 44 + 6);
+        assert_eq!(&wav[..4], b"RIFF");
+        assert_eq!(wav[4..8], 42u32.to_le_bytes());
+        assert_eq!(wav[24..28], 8000u32.to_le_bytes());
+        assert_eq!(wav[44..], [0x00, 0x00, 0xFF, 0x7F, 0x01, 0x80]);
+    }
+}
@@ -0,0 +1,7 @@
+//! Debug Adapter Protocol server on stdin and stdout, for editors to launch.
+
+use std::io;
+
+fn main() -> io::Result<()> {
+    chip_8::dap::run(io::BufReader::new(io::stdin()), io::stdout())
+}
@@ -0,0 +1,308 @@
+//! Run a ROM headless for a number of frames and print the state it ends in, so
+//! regressions can be caught by diffing the output, or play it in the terminal.
+
+mod tui;
+
+use std::env;
+use std::fmt::Write;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::process;
+use std::time::Duration;
+
+use chip_8::audio::{self, Audio};
+use chip_8::interpreter::debugger::Register;
+use chip_8::interpreter::error::ExecError;
+use chip_8::interpreter::quirks::Quirks;
+use chip_8::interpreter::timing::Timing;
+use chip_8::interpreter::variant::Variant;
+use chip_8::interpreter::Interpreter;
+
+const USAGE: &str = "\
+usage: chip8 [options] <rom.ch8>
+
+options:
+    --frames <n>       frames to run, 60 by default or until Ctrl-C with --tui
+    --ipf <n>          instructions per frame, 10 by default
+    --timing <name>    fixed (default) for --ipf instructions per frame, or vip for the
+                       speed of the COSMAC VIP
+    --variant <name>   chip8 (default), schip or xochip
+    --quirks <name>    default, vip, chip48, schip or xochip, the variant's by default
+    --tui              play in the terminal instead of printing the final state
+    --key-timeout <ms> how long a key stays down once typed with --tui, 150 by default
+    --wav <path>       write what the ROM sounds like to a WAV file";
+
+const WAV_SAMPLE_RATE: u32 = 44_100;
+
+#[derive(Debug, PartialEq)]
+struct Options {
+    rom: String,
+    frames: Option<usize>,
+    instructions_per_frame: usize,
+    timing: Timing,
+    variant: Variant,
+    quirks: Quirks,
+    tui: bool,
+    key_timeout: Duration,
+    wav: Option<String>,
+}
+
+fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Options, String> {
+    let mut rom = None;
+    let mut frames = None;
+    let mut instructions_per_frame = 10;
+    let mut timing = Timing::Fixed;
+    let mut variant = Variant::Chip8;
+    let mut quirks = None;
+    let mut tui = false;
+    let mut key_timeout = Duration::from_millis(150);
+    let mut wav = None;
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if !arg.starts_with("--") {
+            if rom.replace(arg).is_some() {
+                return Err("only one ROM can be run".to_string());
+            }
+            continue;
+        }
+
+        if arg == "--tui" {
+            tui = true;
+            continue;
+        }
+
+        let value = args
+            .next()
+            .ok_or_else(|| format!("{} needs a value", arg))?;
+        let number = || {
+            value
+                .parse::<usize>()
+                .map_err(|_| format!("{} expects a number, not {}", arg, value))
+        };
+
+        match arg.as_str() {
+            "--frames" => frames = Some(number()?),
+            "--ipf" => instructions_per_frame = number()?,
+            "--timing" => {
+                timing =
+                    Timing::from_name(&value).ok_or_else(|| format!("unknown timing {}", value))?
+            }
+            "--variant" => {
+                variant = Variant::from_name(&value)
+                    .ok_or_else(|| format!("unknown variant {}", value))?
+            }
+            "--quirks" => {
+                quirks = Some(
+                    Quirks::from_name(&value).ok_or_else(|| format!("unknown quirks {}", value))?,
+                )
+            }
+            "--key-timeout" => key_timeout = Duration::from_millis(number()? as u64),
+            "--wav" => wav = Some(value),
+            _ => return Err(format!("unknown option {}", arg)),
+        }
+    }
+
+    Ok(Options {
+        rom: rom.ok_or("no ROM to run")?,
+        frames,
+        instructions_per_frame,
+        timing,
+        variant,
+        quirks: quirks.unwrap_or_else(|| Quirks::for_variant(variant)),
+        tui,
+        key_timeout,
+        wav,
+    })
+}
+
+/// 64-bit FNV-1a, stable across platforms and Rust versions unlike `DefaultHasher`.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+fn report(interpreter: &Interpreter, frames: usize) -> String {
+    let mut text = String::new();
+    let _ = writeln!(text, "frames: {}", frames);
+
+    let registers: Vec<_> = (0..)
+        .map_while(Register::from_id)
+        .map(|register| match register {
+            Register::I | Register::Pc => {
+                format!("{}={:#05X}", register, register.read(interpreter))
+            }
+            _ => format!("{}={:#04X}", register, register.read(interpreter)),
+        })
+        .collect();
+    let _ = writeln!(text, "{}", registers[..16].join(" "));
+    let _ = writeln!(text, "{}", registers[16..].join(" "));
+
+    let stack: Vec<_> = interpreter.stack[..interpreter.sp as usize]
+        .iter()
+        .map(|address| format!("{:#05X}", address))
+        .collect();
+    let _ = writeln!(text, "stack: [{}]", stack.join(", "));
+    let _ = writeln!(text, "memory: {:016x}", fnv1a(&interpreter.memory));
+
+    let screen = &interpreter.screen;
+    let _ = writeln!(text, "screen: {}x{}", screen.width(), screen.height());
+    text.push_str(&screen.render_text());
+
+    text
+}
+
+/// Run the frames asked for, recording the sound in `samples` if there is an `audio`.
+/// Returns how many frames ran, and the error that stopped the last one if any.
+fn run_headless(
+    interpreter: &mut Interpreter,
+    options: &Options,
+    mut audio: Option<&mut Audio>,
+    samples: &mut Vec<f32>,
+) -> (usize, Option<ExecError>) {
+    let frames = options.frames.unwrap_or(60);
+
+    for frame in 0..frames {
+        let result = interpreter.run_frame_with(|interpreter| {
+            if let Some(audio) = audio.as_mut() {
+                let start = samples.len();
+                samples.resize(start + audio.frame_len(frame as u64), 0.0);
+                audio.render(interpreter, &mut samples[start..]);
+            }
+        });
+
+        match result {
+            Ok(result) if result.halted => return (frame + 1, None),
+            Ok(_) => {}
+            Err(error) => return (frame, Some(error)),
+        }
+    }
+
+    (frames, None)
+}
+
+fn main() {
+    let options = parse_args(env::args().skip(1)).unwrap_or_else(|error| {
+        eprintln!("{}\n\n{}", error, USAGE);
+        process::exit(2);
+    });
+
+    let rom = fs::read(&options.rom).unwrap_or_else(|error| {
+        eprintln!("{}: {}", options.rom, error);
+        process::exit(2);
+    });
+
+    let mut interpreter = Interpreter::new();
+    interpreter.variant = options.variant;
+    interpreter.quirks = options.quirks;
+    interpreter.instructions_per_frame = options.instructions_per_frame;
+    interpreter.timing = options.timing;
+    if let Err(error) = interpreter.load_rom(&rom) {
+        eprintln!("{}: {}", options.rom, error);
+        process::exit(2);
+    }
+
+    if options.tui {
+        let played = tui::run(&mut interpreter, options.frames, options.key_timeout);
+        if let Err(error) = played {
+            eprintln!("{}", error);
+            process::exit(1);
+        }
+        return;
+    }
+
+    let mut audio = options.wav.as_ref().map(|_| Audio::new(WAV_SAMPLE_RATE));
+    let mut samples = Vec::new();
+    let (frames, error) = run_headless(&mut interpreter, &options, audio.as_mut(), &mut samples);
+
+    if let Some(path) = &options.wav {
+        let written = File::create(path)
+            .and_then(|file| audio::write_wav(BufWriter::new(file), WAV_SAMPLE_RATE, &samples));
+        if let Err(error) = written {
+            eprintln!("{}: {}", path, error);
+            process::exit(2);
+        }
+    }
+
+    print!("{}", report(&interpreter, frames));
+    if let Some(error) = error {
+        eprintln!("frame {}: {}", frames, error);
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{fnv1a, parse_args, report, Options};
+    use chip_8::interpreter::quirks::Quirks;
+    use chip_8::interpreter::timing::Timing;
+    use chip_8::interpreter::variant::Variant;
+    use chip_8::interpreter::Interpreter;
+    use std::time::Duration;
+
+    fn args(text: &str) -> Vec<String> {
+        text.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn test_parse_args() {
+        assert_eq!(
+            parse_args(args("--frames 5 pong.ch8 --variant schip")),
+            Ok(Options {
+                rom: "pong.ch8".to_string(),
+                frames: Some(5),
+                instructions_per_frame: 10,
+                timing: Timing::Fixed,
+                variant: Variant::SuperChip,
+                quirks: Quirks::SUPER_CHIP,
+                tui: false,
+                key_timeout: Duration::from_millis(150),
+                wav: None,
+            })
+        );
+        assert_eq!(
+            parse_args(args("--tui a.ch8 --key-timeout 80")).map(|options| (
+                options.tui,
+                options.frames,
+                options.key_timeout
+            )),
+            Ok((true, None, Duration::from_millis(80)))
+        );
+        assert_eq!(
+            parse_args(args("--quirks vip --variant xochip a.ch8")).map(|options| options.quirks),
+            Ok(Quirks::COSMAC_VIP)
+        );
+        assert_eq!(
+            parse_args(args("--timing vip a.ch8")).map(|options| options.timing),
+            Ok(Timing::CosmacVip)
+        );
+        assert!(parse_args(args("--ipf fast a.ch8")).is_err());
+        assert!(parse_args(args("--frames")).is_err());
+        assert!(parse_args(args("--frames 1")).is_err());
+    }
+
+    #[test]
+    fn test_report() {
+        assert_eq!(fnv1a(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv1a(b"a"), 0xaf63_dc4c_8601_ec8c);
+
+        let mut interpreter = Interpreter::new();
+        // LD V3, 0xAA; LD I, 0x123; CALL 0x206
+        interpreter
+            .load_rom(&[0x63, 0xAA, 0xA1, 0x23, 0x22, 0x06])
+            .unwrap();
+        for _ in 0..3 {
+            interpreter.cycle().unwrap();
+        }
+
+        let text = report(&interpreter, 1);
+        let lines: Vec<_> = text.lines().collect();
+        assert_eq!(lines[0], "frames: 1");
+        assert!(lines[1].starts_with("V0=0x00 V1=0x00 V2=0x00 V3=0xAA V4=0x00"));
+        assert_eq!(lines[2], "I=0x123 PC=0x206 SP=0x01 DT=0x00 ST=0x00");
+        assert_eq!(lines[3], "stack: [0x206]");
+        assert_eq!(lines[5], "screen: 64x32");
+        assert_eq!(lines.len(), 6 + 32);
+    }
+}
@@ -0,0 +1,261 @@
+//! Play a ROM in the terminal, for when there is no browser at hand.
+//!
+//! The screen is drawn with half-block characters, two pixel rows per character.
+//! Terminals only send key presses, so a key is released once it hasn't been
+//! received for a while, auto-repeat keeping it down while held.
+
+use std::io::{self, Read, Write};
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use chip_8::interpreter::screen::Screen;
+use chip_8::interpreter::Interpreter;
+
+const FRAME: Duration = Duration::from_micros(16_667);
+
+/// Ctrl-C, which raw mode hands to us instead of killing the process.
+const QUIT: u8 = 0x03;
+
+/// The keypad key for a typed character, laid out like the web view:
+///
+/// ```text
+/// 1 2 3 4      1 2 3 C
+/// Q W E R  ->  4 5 6 D
+/// A S D F      7 8 9 E
+/// Z X C V      A 0 B F
+/// ```
+fn keypad_key(byte: u8) -> Option<usize> {
+    let key = match byte.to_ascii_lowercase() {
+        b'1' => 0x1,
+        b'2' => 0x2,
+        b'3' => 0x3,
+        b'4' => 0xC,
+        b'q' => 0x4,
+        b'w' => 0x5,
+        b'e' => 0x6,
+        b'r' => 0xD,
+        b'a' => 0x7,
+        b's' => 0x8,
+        b'd' => 0x9,
+        b'f' => 0xE,
+        b'z' => 0xA,
+        b'x' => 0x0,
+        b'c' => 0xB,
+        b'v' => 0xF,
+        _ => return None,
+    };
+
+    Some(key)
+}
+
+/// The visible pixels, the upper one of each pair in the top half of the character.
+/// Pixels lit on any plane are drawn.
+fn render(screen: &Screen) -> String {
+    let (width, height) = (screen.width(), screen.height());
+    let lit = |x: usize, y: usize| y < height && screen.pixels[x + y * width] != 0;
+    let mut text = String::new();
+
+    for y in (0..height).step_by(2) {
+        for x in 0..width {
+            text.push(match (lit(x, y), lit(x, y + 1)) {
+                (true, true) => '█',
+                (true, false) => '▀',
+                (false, true) => '▄',
+                (false, false) => ' ',
+            });
+        }
+        // Raw mode doesn't go back to the start of the line by itself
+        text.push_str("\r\n");
+    }
+
+    text
+}
+
+/// When each keypad key was last received.
+#[derive(Debug, Default)]
+struct HeldKeys {
+    pressed: [Option<Instant>; 16],
+}
+
+impl HeldKeys {
+    fn press(&mut self, interpreter: &mut Interpreter, key: usize, now: Instant) {
+        self.pressed[key] = Some(now);
+        interpreter.keypad.set_down(key);
+    }
+
+    /// Release the keys not received for `timeout`.
+    fn release(&mut self, interpreter: &mut Interpreter, now: Instant, timeout: Duration) {
+        for (key, pressed) in self.pressed.iter_mut().enumerate() {
+            if pressed.is_some_and(|at| now.duration_since(at) >= timeout) {
+                *pressed = None;
+                interpreter.keypad.set_up(key);
+            }
+        }
+    }
+}
+
+fn stty(args: &[&str]) -> io::Result<String> {
+    let output = Command::new("stty")
+        .args(args)
+        .stdin(Stdio::inherit())
+        .output()?;
+
+    match output.status.success() {
+        true => Ok(String::from_utf8_lossy(&output.stdout).trim().to_string()),
+        false => Err(io::Error::other(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        )),
+    }
+}
+
+/// The terminal in raw mode with the cursor hidden, put back as it was when dropped.
+struct RawTerminal {
+    settings: String,
+}
+
+impl RawTerminal {
+    fn enable() -> io::Result<Self> {
+        let settings = stty(&["-g"])?;
+        stty(&["raw", "-echo"])?;
+        print!("\x1b[?25l\x1b[2J");
+
+        Ok(Self { settings })
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        let _ = stty(&[&self.settings]);
+        print!("\x1b[?25h\r\n");
+        let _ = io::stdout().flush();
+    }
+}
+
+/// Run until Ctrl-C, the program exits or `frames` frames went by.
+pub fn run(
+    interpreter: &mut Interpreter,
+    frames: Option<usize>,
+    key_timeout: Duration,
+) -> Result<(), String> {
+    let terminal = RawTerminal::enable().map_err(|error| format!("stty: {}", error))?;
+    let (sender, receiver) = mpsc::channel();
+
+    thread::spawn(move || {
+        for byte in io::stdin().lock().bytes() {
+            match byte {
+                Ok(byte) if sender.send(byte).is_ok() => {}
+                _ => break,
+            }
+        }
+    });
+
+    let mut held = HeldKeys::default();
+    let mut drawn = String::new();
+    let mut next_frame = Instant::now();
+    let mut stdout = io::stdout();
+    let mut result = Ok(());
+
+    for _ in 0..frames.unwrap_or(usize::MAX) {
+        let now = Instant::now();
+        held.release(interpreter, now, key_timeout);
+
+        loop {
+            match receiver.try_recv() {
+                Ok(QUIT) | Err(TryRecvError::Disconnected) => {
+                    drop(terminal);
+                    return result;
+                }
+                Ok(byte) => {
+                    if let Some(key) = keypad_key(byte) {
+                        held.press(interpreter, key, now);
+                    }
+                }
+                Err(TryRecvError::Empty) => break,
+            }
+        }
+
+        if let Err(error) = interpreter.run_frame() {
+            result = Err(error.to_string());
+        }
+
+        let screen = render(&interpreter.screen);
+        if screen != drawn {
+            let _ = write!(stdout, "\x1b[H{}Ctrl-C to quit", screen);
+            let _ = stdout.flush();
+            drawn = screen;
+        }
+
+        if result.is_err() || interpreter.has_exited() {
+            break;
+        }
+
+        next_frame += FRAME;
+        match next_frame.checked_duration_since(Instant::now()) {
+            Some(wait) => thread::sleep(wait),
+            // Too slow to keep up, don't try to catch up
+            None => next_frame = Instant::now(),
+        }
+    }
+
+    drop(terminal);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{keypad_key, render, HeldKeys};
+    use chip_8::interpreter::screen::{PixelState, Screen};
+    use chip_8::interpreter::Interpreter;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_render() {
+        let mut screen = Screen::new();
+        screen.update_pixel((0, 0), 1, PixelState::On);
+        screen.update_pixel((1, 1), 1, PixelState::On);
+        screen.update_pixel((2, 0), 2, PixelState::On);
+        screen.update_pixel((2, 1), 1, PixelState::On);
+
+        let text = render(&screen);
+        let lines: Vec<_> = text.split("\r\n").collect();
+        assert_eq!(lines.len(), 16 + 1);
+        assert_eq!(lines[0], format!("▀▄█{}", " ".repeat(61)));
+        assert_eq!(lines[15], " ".repeat(64));
+    }
+
+    #[test]
+    fn test_keys() {
+        assert_eq!(keypad_key(b'1'), Some(0x1));
+        assert_eq!(keypad_key(b'R'), Some(0xD));
+        assert_eq!(keypad_key(b'x'), Some(0x0));
+        assert_eq!(keypad_key(b'v'), Some(0xF));
+        assert_eq!(keypad_key(b'5'), None);
+
+        let mut interpreter = Interpreter::new();
+        let mut held = HeldKeys::default();
+        let start = Instant::now();
+        let timeout = Duration::from_millis(100);
+
+        held.press(&mut interpreter, 0x5, start);
+        held.release(&mut interpreter, start + Duration::from_millis(50), timeout);
+        assert!(interpreter.keypad.is_pressed(0x5));
+
+        // Auto-repeat keeps the key down
+        held.press(&mut interpreter, 0x5, start + Duration::from_millis(80));
+        held.release(
+            &mut interpreter,
+            start + Duration::from_millis(150),
+            timeout,
+        );
+        assert!(interpreter.keypad.is_pressed(0x5));
+
+        held.release(
+            &mut interpreter,
+            start + Duration::from_millis(180),
+            timeout,
+        );
+        assert!(!interpreter.keypad.is_pressed(0x5));
+    }
+}
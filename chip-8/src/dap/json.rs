@@ -0,0 +1,320 @@
+//! Just enough JSON for the protocol messages.
+
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    /// Members in the order they were written, looked up by a linear scan.
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    pub fn object(members: Vec<(&str, Value)>) -> Value {
+        Value::Object(
+            members
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value))
+                .collect(),
+        )
+    }
+
+    /// The member named `key`, `Null` if missing or not an object.
+    pub fn get(&self, key: &str) -> &Value {
+        match self {
+            Value::Object(members) => members
+                .iter()
+                .find(|(name, _)| name == key)
+                .map_or(&Value::Null, |(_, value)| value),
+            _ => &Value::Null,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Value::Number(value) if value.fract() == 0.0 => Some(*value as i64),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> &[Value] {
+        match self {
+            Value::Array(values) => values,
+            _ => &[],
+        }
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Value::Bool(value)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(value: i64) -> Self {
+        Value::Number(value as f64)
+    }
+}
+
+impl From<usize> for Value {
+    fn from(value: usize) -> Self {
+        Value::Number(value as f64)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Value::String(value.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Value::String(value)
+    }
+}
+
+impl From<Vec<Value>> for Value {
+    fn from(values: Vec<Value>) -> Self {
+        Value::Array(values)
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter<'_>, text: &str) -> fmt::Result {
+    write!(f, "\"")?;
+
+    for c in text.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+
+    write!(f, "\"")
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Null => write!(f, "null"),
+            Value::Bool(value) => write!(f, "{}", value),
+            Value::Number(value) if value.is_finite() => write!(f, "{}", value),
+            Value::Number(_) => write!(f, "null"),
+            Value::String(text) => write_string(f, text),
+            Value::Array(values) => {
+                write!(f, "[")?;
+                for (index, value) in values.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                write!(f, "]")
+            }
+            Value::Object(members) => {
+                write!(f, "{{")?;
+                for (index, (key, value)) in members.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.text[self.position..]
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.position += rest.len() - rest.trim_start().len();
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_whitespace();
+        let found = self.rest().starts_with(token);
+        if found {
+            self.position += token.len();
+        }
+
+        found
+    }
+
+    fn value(&mut self) -> Option<Value> {
+        self.skip_whitespace();
+
+        match self.rest().chars().next()? {
+            '{' => self.object(),
+            '[' => self.array(),
+            '"' => self.string().map(Value::String),
+            't' if self.eat("true") => Some(Value::Bool(true)),
+            'f' if self.eat("false") => Some(Value::Bool(false)),
+            'n' if self.eat("null") => Some(Value::Null),
+            _ => self.number(),
+        }
+    }
+
+    fn object(&mut self) -> Option<Value> {
+        self.eat("{");
+        let mut members = Vec::new();
+
+        if self.eat("}") {
+            return Some(Value::Object(members));
+        }
+
+        loop {
+            self.skip_whitespace();
+            let key = self.string()?;
+            if !self.eat(":") {
+                return None;
+            }
+            members.push((key, self.value()?));
+
+            if self.eat("}") {
+                return Some(Value::Object(members));
+            } else if !self.eat(",") {
+                return None;
+            }
+        }
+    }
+
+    fn array(&mut self) -> Option<Value> {
+        self.eat("[");
+        let mut values = Vec::new();
+
+        if self.eat("]") {
+            return Some(Value::Array(values));
+        }
+
+        loop {
+            values.push(self.value()?);
+
+            if self.eat("]") {
+                return Some(Value::Array(values));
+            } else if !self.eat(",") {
+                return None;
+            }
+        }
+    }
+
+    fn string(&mut self) -> Option<String> {
+        let mut chars = self.rest().strip_prefix('"')?.char_indices();
+        let mut text = String::new();
+
+        while let Some((index, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.position += index + 2;
+                    return Some(text);
+                }
+                '\\' => match chars.next()?.1 {
+                    'n' => text.push('\n'),
+                    'r' => text.push('\r'),
+                    't' => text.push('\t'),
+                    'b' => text.push('\u{8}'),
+                    'f' => text.push('\u{c}'),
+                    'u' => {
+                        let digits: String = (0..4)
+                            .filter_map(|_| chars.next())
+                            .map(|(_, c)| c)
+                            .collect();
+                        let unit = u16::from_str_radix(&digits, 16).ok()?;
+                        // Surrogate pairs aren't worth it for paths and expressions
+                        text.push(char::from_u32(unit as u32).unwrap_or('\u{FFFD}'));
+                    }
+                    c => text.push(c),
+                },
+                c => text.push(c),
+            }
+        }
+
+        None
+    }
+
+    fn number(&mut self) -> Option<Value> {
+        let rest = self.rest();
+        let len = rest
+            .find(|c: char| !(c.is_ascii_digit() || "+-.eE".contains(c)))
+            .unwrap_or(rest.len());
+        let value = rest[..len].parse().ok()?;
+        self.position += len;
+
+        Some(Value::Number(value))
+    }
+}
+
+/// Parse a whole JSON document.
+pub fn parse(text: &str) -> Option<Value> {
+    let mut parser = Parser { text, position: 0 };
+    let value = parser.value()?;
+    parser.skip_whitespace();
+
+    match parser.rest() {
+        "" => Some(value),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, Value};
+
+    #[test]
+    fn test_roundtrip() {
+        let text = r#"{"seq":1,"type":"request","arguments":{"lines":[3,-4.5,1e2],"path":"a \"b\"\né","ok":true,"none":null}}"#;
+        let value = parse(text).unwrap();
+
+        assert_eq!(value.get("seq").as_i64(), Some(1));
+        let arguments = value.get("arguments");
+        assert_eq!(
+            arguments.get("lines").as_array(),
+            [
+                Value::Number(3.0),
+                Value::Number(-4.5),
+                Value::Number(100.0)
+            ]
+        );
+        assert_eq!(arguments.get("path").as_str(), Some("a \"b\"\né"));
+        assert_eq!(arguments.get("ok").as_bool(), Some(true));
+        assert_eq!(arguments.get("missing"), &Value::Null);
+
+        assert_eq!(parse(&value.to_string()), Some(value));
+        assert_eq!(parse("[1, 2"), None);
+        assert_eq!(parse("{} {}"), None);
+    }
+}
@@ -0,0 +1,823 @@
+//! A Debug Adapter Protocol server, so editors speaking DAP can debug ROMs.
+//!
+//! `launch` takes the `program` to debug: a `.ch8` ROM, an Octo `.8o` source or
+//! Cowgod-syntax assembly for anything else. Only assembly gets a source map, so it is
+//! the only one breakpoints can be set on by line. An optional `variant` (`chip8`,
+//! `schip` or `xochip`) picks the instruction set and its quirks, `stopOnEntry` stops
+//! before the first instruction.
+
+mod json;
+
+use std::convert::TryFrom;
+use std::fs;
+use std::io::{self, BufRead, ErrorKind, Write};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use self::json::Value;
+use crate::asm::{assemble_with_source_map, SourceMap};
+use crate::disasm::{disassemble, Instruction};
+use crate::interpreter::debugger::{Debugger, Register, StopReason};
+use crate::interpreter::quirks::Quirks;
+use crate::interpreter::variant::Variant;
+use crate::interpreter::Interpreter;
+use crate::octo;
+
+/// The interpreter is single threaded, the client still needs a thread to talk about.
+const THREAD_ID: i64 = 1;
+
+const REGISTERS_REFERENCE: i64 = 1;
+const MEMORY_REFERENCE: i64 = 2;
+
+/// Bytes per line of the memory view.
+const MEMORY_ROW: usize = 16;
+
+/// Instructions executed between two timer ticks, like the web view does.
+const CYCLES_PER_FRAME: usize = 10;
+
+const FRAME: Duration = Duration::from_micros(16_667);
+
+/// Largest message accepted, far more than any request needs.
+const MAX_MESSAGE_LEN: usize = 1 << 20;
+
+/// Read one `Content-Length` framed message, `None` at the end of the input.
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<Value>> {
+    let mut length = None;
+
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+
+        let header = header.trim();
+        if header.is_empty() {
+            if length.is_some() {
+                break;
+            }
+            continue;
+        }
+
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("Content-Length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+
+    let length = length.unwrap_or(0);
+    if length > MAX_MESSAGE_LEN {
+        return Err(io::Error::new(ErrorKind::InvalidData, "message too large"));
+    }
+
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+
+    std::str::from_utf8(&body)
+        .ok()
+        .and_then(json::parse)
+        .map(Some)
+        .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "invalid message"))
+}
+
+fn write_message(output: &mut impl Write, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut text = String::with_capacity(bytes.len().div_ceil(3) * 4);
+
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0u32, |group, (index, byte)| {
+            group | (*byte as u32) << (16 - 8 * index)
+        });
+
+        for index in 0..4 {
+            if index <= chunk.len() {
+                text.push(ALPHABET[(group >> (18 - 6 * index)) as usize & 0x3F] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+
+    text
+}
+
+fn parse_address(text: &str) -> Option<usize> {
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+/// What the program runs until, besides breakpoints.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Run {
+    Continue,
+    /// Back from the subroutine being stepped over.
+    Return {
+        pc: u16,
+        sp: u8,
+    },
+    /// Out of the current subroutine.
+    Out {
+        sp: u8,
+    },
+}
+
+impl Run {
+    fn done(&self, interpreter: &Interpreter) -> bool {
+        match *self {
+            Run::Continue => false,
+            Run::Return { pc, sp } => interpreter.pc == pc && interpreter.sp == sp,
+            Run::Out { sp } => interpreter.sp < sp,
+        }
+    }
+}
+
+struct Adapter<W: Write> {
+    output: W,
+    seq: usize,
+    interpreter: Interpreter,
+    debugger: Debugger,
+    /// The program path, and the source map when it was assembled.
+    program: Option<(String, Option<SourceMap>)>,
+    stop_on_entry: bool,
+    running: Option<Run>,
+    /// Cycles since the last timer tick.
+    cycles: usize,
+    /// Events and their body, sent after the response to the current request.
+    events: Vec<(&'static str, Value)>,
+}
+
+/// Serve a client talking on `input` and `output` until it disconnects.
+pub fn run<R: BufRead + Send + 'static, W: Write>(input: R, output: W) -> io::Result<()> {
+    let (sender, receiver) = mpsc::channel();
+
+    // Requests such as `pause` have to get through while the program runs
+    thread::spawn(move || {
+        let mut input = input;
+        while let Ok(Some(message)) = read_message(&mut input) {
+            if sender.send(message).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut adapter = Adapter {
+        output,
+        seq: 0,
+        interpreter: Interpreter::new(),
+        debugger: Debugger::new(),
+        program: None,
+        stop_on_entry: false,
+        running: None,
+        cycles: 0,
+        events: Vec::new(),
+    };
+
+    adapter.serve(receiver)
+}
+
+impl<W: Write> Adapter<W> {
+    fn serve(&mut self, receiver: Receiver<Value>) -> io::Result<()> {
+        loop {
+            if self.running.is_none() {
+                match receiver.recv() {
+                    Ok(message) if self.handle(&message)? => continue,
+                    _ => return Ok(()),
+                }
+            }
+
+            let deadline = Instant::now() + FRAME;
+            self.run_frame()?;
+
+            // Answer whatever arrives during the rest of the frame
+            while self.running.is_some() {
+                let timeout = match deadline.checked_duration_since(Instant::now()) {
+                    Some(timeout) => timeout,
+                    None => break,
+                };
+
+                match receiver.recv_timeout(timeout) {
+                    Ok(message) => {
+                        if !self.handle(&message)? {
+                            return Ok(());
+                        }
+                    }
+                    Err(RecvTimeoutError::Timeout) => break,
+                    Err(RecvTimeoutError::Disconnected) => return Ok(()),
+                }
+            }
+        }
+    }
+
+    fn send(&mut self, mut message: Vec<(&str, Value)>) -> io::Result<()> {
+        self.seq += 1;
+        message.insert(0, ("seq", self.seq.into()));
+        write_message(&mut self.output, &Value::object(message))
+    }
+
+    fn event(&mut self, event: &'static str, body: Value) {
+        self.events.push((event, body));
+    }
+
+    fn flush_events(&mut self) -> io::Result<()> {
+        for (event, body) in std::mem::take(&mut self.events) {
+            self.send(vec![
+                ("type", "event".into()),
+                ("event", event.into()),
+                ("body", body),
+            ])?;
+        }
+
+        Ok(())
+    }
+
+    /// Answer a request, returns false once the client is done with us.
+    fn handle(&mut self, request: &Value) -> io::Result<bool> {
+        let command = request.get("command").as_str().unwrap_or("");
+        let arguments = request.get("arguments");
+
+        let result = match command {
+            "initialize" => Ok(Value::object(vec![
+                ("supportsConfigurationDoneRequest", true.into()),
+                ("supportsReadMemoryRequest", true.into()),
+                ("supportsTerminateRequest", true.into()),
+            ])),
+            "launch" => self.launch(arguments),
+            "setBreakpoints" => self.set_breakpoints(arguments),
+            "configurationDone" => {
+                if self.stop_on_entry {
+                    self.stopped("entry", None);
+                } else {
+                    self.resume(Run::Continue);
+                }
+                Ok(Value::Null)
+            }
+            "threads" => Ok(Value::object(vec![(
+                "threads",
+                vec![Value::object(vec![
+                    ("id", THREAD_ID.into()),
+                    ("name", "CHIP-8".into()),
+                ])]
+                .into(),
+            )])),
+            "stackTrace" => Ok(self.stack_trace()),
+            "scopes" => Ok(self.scopes()),
+            "variables" => self.variables(arguments),
+            "readMemory" => self.read_memory(arguments),
+            "continue" => {
+                self.resume(Run::Continue);
+                Ok(Value::object(vec![("allThreadsContinued", true.into())]))
+            }
+            "next" => {
+                let pc = self.interpreter.pc as usize;
+                let opcode = match self.interpreter.memory.get(pc..pc + 2) {
+                    Some(bytes) => (bytes[0] as u16) << 8 | bytes[1] as u16,
+                    None => 0,
+                };
+
+                match disassemble(opcode, self.interpreter.variant) {
+                    Instruction::Call(_) => self.resume(Run::Return {
+                        pc: self.interpreter.pc.wrapping_add(2),
+                        sp: self.interpreter.sp,
+                    }),
+                    _ => self.step(),
+                }
+                Ok(Value::Null)
+            }
+            "stepIn" => {
+                self.step();
+                Ok(Value::Null)
+            }
+            "stepOut" => {
+                match self.interpreter.sp {
+                    0 => self.step(),
+                    sp => self.resume(Run::Out { sp }),
+                }
+                Ok(Value::Null)
+            }
+            "pause" => {
+                if self.running.take().is_some() {
+                    self.stopped("pause", None);
+                }
+                Ok(Value::Null)
+            }
+            "terminate" => {
+                self.running = None;
+                self.event("terminated", Value::object(Vec::new()));
+                Ok(Value::Null)
+            }
+            "disconnect" => Ok(Value::Null),
+            _ => Err(format!("unsupported request {}", command)),
+        };
+
+        let mut response = vec![
+            ("type", Value::from("response")),
+            ("request_seq", request.get("seq").clone()),
+            ("command", command.into()),
+            ("success", result.is_ok().into()),
+        ];
+        match result {
+            Ok(body) => response.push(("body", body)),
+            Err(message) => response.push(("message", message.into())),
+        }
+        self.send(response)?;
+        self.flush_events()?;
+
+        Ok(command != "disconnect")
+    }
+
+    fn launch(&mut self, arguments: &Value) -> Result<Value, String> {
+        let path = arguments
+            .get("program")
+            .as_str()
+            .ok_or("no program to launch")?
+            .to_string();
+        let variant = match arguments.get("variant").as_str() {
+            Some(name) => Variant::from_name(name).ok_or(format!("unknown variant {}", name))?,
+            None => Variant::Chip8,
+        };
+
+        let bytes = fs::read(&path).map_err(|error| format!("{}: {}", path, error))?;
+        let extension = Path::new(&path)
+            .extension()
+            .and_then(|extension| extension.to_str());
+        let (rom, source_map) = match extension {
+            Some("ch8") => (bytes, None),
+            Some("8o") => {
+                let source = String::from_utf8_lossy(&bytes);
+                let rom = octo::compile(&source, variant).map_err(|error| error.to_string())?;
+                (rom, None)
+            }
+            _ => {
+                let source = String::from_utf8_lossy(&bytes);
+                let (rom, source_map) =
+                    assemble_with_source_map(&source).map_err(|error| error.to_string())?;
+                (rom, Some(source_map))
+            }
+        };
+
+        self.interpreter.variant = variant;
+        self.interpreter.quirks = Quirks::for_variant(variant);
+        self.interpreter
+            .load_rom(&rom)
+            .map_err(|error| error.to_string())?;
+        self.program = Some((path, source_map));
+        self.stop_on_entry = arguments.get("stopOnEntry").as_bool().unwrap_or(false);
+
+        // Breakpoints can only be resolved now that there is a source map
+        self.event("initialized", Value::Null);
+
+        Ok(Value::Null)
+    }
+
+    fn set_breakpoints(&mut self, arguments: &Value) -> Result<Value, String> {
+        let source_map = self.program.as_ref().and_then(|(_, map)| map.as_ref());
+        let mut breakpoints = Vec::new();
+        let mut addresses = Vec::new();
+
+        for breakpoint in arguments.get("breakpoints").as_array() {
+            let line = breakpoint.get("line").as_i64().unwrap_or(0).max(0) as usize;
+
+            match source_map.and_then(|map| map.resolve(line)) {
+                Some((line, address)) => {
+                    addresses.push(address);
+                    breakpoints.push(Value::object(vec![
+                        ("verified", true.into()),
+                        ("line", line.into()),
+                        ("instructionReference", format!("{:#05X}", address).into()),
+                    ]));
+                }
+                None => breakpoints.push(Value::object(vec![
+                    ("verified", false.into()),
+                    ("message", "no instruction at or after this line".into()),
+                ])),
+            }
+        }
+
+        // There is a single source, its breakpoints are all of them
+        self.debugger.clear_breakpoints();
+        for address in addresses {
+            self.debugger.add_breakpoint(address, None);
+        }
+
+        Ok(Value::object(vec![("breakpoints", breakpoints.into())]))
+    }
+
+    /// A frame executing `address`, inside the subroutine at `entry` if not the main program.
+    fn frame(&self, id: usize, address: u16, entry: Option<u16>) -> Value {
+        let name = match entry {
+            Some(entry) => format!("{:#05X}", entry),
+            None => "main".to_string(),
+        };
+        let mut frame = vec![
+            ("id", Value::from(id)),
+            ("name", name.into()),
+            ("column", 1usize.into()),
+            (
+                "instructionPointerReference",
+                format!("{:#05X}", address).into(),
+            ),
+        ];
+
+        let located = self.program.as_ref().and_then(|(path, map)| {
+            let line = map.as_ref()?.line(address)?;
+            Some((path, line))
+        });
+        match located {
+            Some((path, line)) => {
+                frame.push(("line", line.into()));
+                frame.push((
+                    "source",
+                    Value::object(vec![("path", path.as_str().into())]),
+                ));
+            }
+            None => {
+                frame.push(("line", 0usize.into()));
+                frame.push(("presentationHint", "subtle".into()));
+            }
+        }
+
+        Value::object(frame)
+    }
+
+    fn stack_trace(&self) -> Value {
+        let interpreter = &self.interpreter;
+        let sp = (interpreter.sp as usize).min(interpreter.stack.len());
+        // The subroutine each frame is in, from the call that entered it
+        let entry = |depth: usize| {
+            let call = interpreter.stack[sp.checked_sub(depth + 1)?].wrapping_sub(2) as usize;
+            let bytes = interpreter.memory.get(call..call + 2)?;
+            match disassemble(
+                (bytes[0] as u16) << 8 | bytes[1] as u16,
+                interpreter.variant,
+            ) {
+                Instruction::Call(nnn) => Some(nnn),
+                _ => None,
+            }
+        };
+
+        let mut frames = vec![self.frame(0, interpreter.pc, entry(0))];
+        for depth in 1..=sp {
+            // The caller is on the CALL, just before where it returns
+            let call = interpreter.stack[sp - depth].wrapping_sub(2);
+            frames.push(self.frame(depth, call, entry(depth)));
+        }
+
+        Value::object(vec![
+            ("totalFrames", frames.len().into()),
+            ("stackFrames", frames.into()),
+        ])
+    }
+
+    fn scopes(&self) -> Value {
+        let rows = self.interpreter.memory.len().div_ceil(MEMORY_ROW);
+
+        Value::object(vec![(
+            "scopes",
+            vec![
+                Value::object(vec![
+                    ("name", "Registers".into()),
+                    ("variablesReference", REGISTERS_REFERENCE.into()),
+                    ("expensive", false.into()),
+                ]),
+                Value::object(vec![
+                    ("name", "Memory".into()),
+                    ("variablesReference", MEMORY_REFERENCE.into()),
+                    ("indexedVariables", rows.into()),
+                    ("expensive", true.into()),
+                ]),
+            ]
+            .into(),
+        )])
+    }
+
+    fn variables(&self, arguments: &Value) -> Result<Value, String> {
+        let variables = match arguments.get("variablesReference").as_i64() {
+            Some(REGISTERS_REFERENCE) => (0..)
+                .map_while(Register::from_id)
+                .map(|register| {
+                    let value = register.read(&self.interpreter);
+                    let mut variable = vec![
+                        ("name", Value::from(register.to_string())),
+                        ("variablesReference", 0usize.into()),
+                    ];
+
+                    match register {
+                        Register::I | Register::Pc => {
+                            variable.push(("value", format!("{:#05X}", value).into()));
+                            variable.push(("memoryReference", format!("{:#05X}", value).into()));
+                        }
+                        _ => variable.push(("value", format!("{:#04X}", value).into())),
+                    }
+
+                    Value::object(variable)
+                })
+                .collect(),
+            Some(MEMORY_REFERENCE) => {
+                let memory = &self.interpreter.memory;
+                let start = arguments.get("start").as_i64().unwrap_or(0).max(0) as usize;
+                let count = match arguments.get("count").as_i64().unwrap_or(0).max(0) as usize {
+                    0 => usize::MAX,
+                    count => count,
+                };
+
+                memory
+                    .chunks(MEMORY_ROW)
+                    .enumerate()
+                    .skip(start)
+                    .take(count)
+                    .map(|(row, bytes)| {
+                        let address = row * MEMORY_ROW;
+                        let hex: Vec<String> =
+                            bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+
+                        Value::object(vec![
+                            ("name", format!("{:#05X}", address).into()),
+                            ("value", hex.join(" ").into()),
+                            ("variablesReference", 0usize.into()),
+                            ("memoryReference", format!("{:#05X}", address).into()),
+                        ])
+                    })
+                    .collect()
+            }
+            _ => return Err("unknown variables reference".to_string()),
+        };
+
+        Ok(Value::object(vec![("variables", Value::Array(variables))]))
+    }
+
+    fn read_memory(&self, arguments: &Value) -> Result<Value, String> {
+        let memory = &self.interpreter.memory;
+        let reference = arguments.get("memoryReference").as_str().unwrap_or("");
+        let offset = arguments.get("offset").as_i64().unwrap_or(0);
+        let start = parse_address(reference)
+            .and_then(|address| i64::try_from(address).ok())
+            .and_then(|address| address.checked_add(offset))
+            .ok_or("invalid memory reference")?;
+        let count = arguments.get("count").as_i64().unwrap_or(0).max(0) as usize;
+
+        let start = start.clamp(0, memory.len() as i64) as usize;
+        let bytes = &memory[start..start.saturating_add(count).min(memory.len())];
+
+        Ok(Value::object(vec![
+            ("address", format!("{:#05X}", start).into()),
+            ("data", base64(bytes).into()),
+            ("unreadableBytes", (count - bytes.len()).into()),
+        ]))
+    }
+
+    fn stopped(&mut self, reason: &str, text: Option<String>) {
+        let mut body = vec![
+            ("reason", Value::from(reason)),
+            ("threadId", THREAD_ID.into()),
+            ("allThreadsStopped", true.into()),
+        ];
+        if let Some(text) = text {
+            body.push(("description", text.as_str().into()));
+            body.push(("text", text.into()));
+        }
+
+        self.event("stopped", Value::object(body));
+    }
+
+    /// Tell the client why the program stopped, false if it didn't.
+    fn report(&mut self, reason: StopReason) -> bool {
+        match reason {
+            StopReason::CycleLimit => return false,
+            StopReason::Stepped => self.stopped("step", None),
+            StopReason::Breakpoint(_) => self.stopped("breakpoint", None),
+            StopReason::Watchpoint(hit) => self.stopped("data breakpoint", Some(hit.to_string())),
+            StopReason::Error(error) => self.stopped("exception", Some(error.to_string())),
+            StopReason::Exited => {
+                self.event("exited", Value::object(vec![("exitCode", 0usize.into())]));
+                self.event("terminated", Value::object(Vec::new()));
+            }
+        }
+
+        true
+    }
+
+    fn step(&mut self) {
+        let reason = self.debugger.step(&mut self.interpreter);
+        self.cycles += 1;
+        self.report(reason);
+    }
+
+    /// Run in the background of the requests, from the current instruction even if it
+    /// has a breakpoint we didn't stop on.
+    fn resume(&mut self, run: Run) {
+        let reason = self.debugger.step(&mut self.interpreter);
+        self.cycles += 1;
+
+        if reason != StopReason::Stepped {
+            self.report(reason);
+        } else if run.done(&self.interpreter) {
+            self.stopped("step", None);
+        } else {
+            self.running = Some(run);
+        }
+    }
+
+    fn run_frame(&mut self) -> io::Result<()> {
+        let run = match self.running {
+            Some(run) => run,
+            None => return Ok(()),
+        };
+
+        while self.cycles < CYCLES_PER_FRAME {
+            let reason = self.debugger.run_until_break(&mut self.interpreter, 1);
+            self.cycles += 1;
+
+            if self.report(reason) {
+                self.running = None;
+                break;
+            } else if run.done(&self.interpreter) {
+                self.running = None;
+                self.stopped("step", None);
+                break;
+            }
+        }
+
+        if self.cycles >= CYCLES_PER_FRAME {
+            self.interpreter.tick();
+            self.cycles = 0;
+        }
+
+        self.flush_events()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::json::{self, Value};
+    use super::{base64, read_message, run};
+    use std::io::{BufRead, Cursor};
+    use std::path::PathBuf;
+
+    // Deletes the file when the test ends, even on a failed assertion
+    struct TempFile(PathBuf);
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    fn request(seq: usize, command: &str, arguments: &str) -> String {
+        let body = format!(
+            r#"{{"seq":{},"type":"request","command":"{}","arguments":{}}}"#,
+            seq, command, arguments
+        );
+        format!("Content-Length: {}\r\n\r\n{}", body.len(), body)
+    }
+
+    fn messages(output: Vec<u8>) -> Vec<Value> {
+        let mut output = Cursor::new(output);
+        let mut messages = Vec::new();
+        while let Some(message) = read_message(&mut output).unwrap() {
+            messages.push(message);
+        }
+        assert!(output.fill_buf().unwrap().is_empty());
+
+        messages
+    }
+
+    #[test]
+    fn test_base64() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+    }
+
+    #[test]
+    fn test_message_too_large() {
+        let mut input = Cursor::new(b"Content-Length: 99999999999\r\n\r\n{}".to_vec());
+        let error = read_message(&mut input).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_session() {
+        let file =
+            TempFile(std::env::temp_dir().join(format!("chip8-dap-{}.asm", std::process::id())));
+        std::fs::write(
+            &file.0,
+            "; count to three in a subroutine
+                    LD I, 0x300
+            loop:   CALL count
+                    SE V0, 3
+                    JP loop
+            done:   JP done
+
+            count:  ADD V0, 1
+                    LD [I], V0
+                    RET
+            ",
+        )
+        .unwrap();
+        let path = file.0.to_str().unwrap().replace('\\', "\\\\");
+
+        let input: String = [
+            request(1, "initialize", r#"{"adapterID":"chip8"}"#),
+            request(2, "launch", &format!(r#"{{"program":"{}"}}"#, path)),
+            request(
+                3,
+                "setBreakpoints",
+                r#"{"breakpoints":[{"line":8},{"line":7},{"line":20}]}"#,
+            ),
+            request(4, "configurationDone", "{}"),
+            request(5, "stackTrace", r#"{"threadId":1}"#),
+            request(6, "variables", r#"{"variablesReference":1}"#),
+            request(7, "stepOut", r#"{"threadId":1}"#),
+            request(8, "readMemory", r#"{"memoryReference":"0x300","count":2}"#),
+            request(
+                9,
+                "readMemory",
+                r#"{"memoryReference":"0x7FFFFFFFFFFFFFFF","offset":1,"count":2}"#,
+            ),
+            request(
+                10,
+                "readMemory",
+                r#"{"memoryReference":"0xFFFFFFFFFFFFFFFF","count":2}"#,
+            ),
+            request(11, "disconnect", "{}"),
+        ]
+        .concat();
+        let mut output = Vec::new();
+        run(Cursor::new(input.into_bytes()), &mut output).unwrap();
+
+        let messages = messages(output);
+        let find = |kind: &str, name: &str| -> Vec<&Value> {
+            let key = if kind == "event" { "event" } else { "command" };
+            messages
+                .iter()
+                .filter(|message| {
+                    message.get("type").as_str() == Some(kind)
+                        && message.get(key).as_str() == Some(name)
+                })
+                .collect()
+        };
+
+        assert_eq!(find("event", "initialized").len(), 1);
+        let breakpoints = find("response", "setBreakpoints")[0]
+            .get("body")
+            .get("breakpoints")
+            .to_string();
+        assert_eq!(
+            breakpoints,
+            r#"[{"verified":true,"line":8,"instructionReference":"0x20A"},{"verified":true,"line":8,"instructionReference":"0x20A"},{"verified":false,"message":"no instruction at or after this line"}]"#
+        );
+
+        let stopped: Vec<_> = find("event", "stopped")
+            .iter()
+            .map(|event| event.get("body").get("reason").as_str().unwrap())
+            .collect();
+        assert_eq!(stopped, ["breakpoint", "step"]);
+
+        let frames = find("response", "stackTrace")[0]
+            .get("body")
+            .get("stackFrames");
+        let lines: Vec<_> = frames
+            .as_array()
+            .iter()
+            .map(|frame| {
+                (
+                    frame.get("name").as_str().unwrap(),
+                    frame.get("line").as_i64().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(lines, [("0x20A", 8), ("main", 3)]);
+
+        let variables = find("response", "variables")[0]
+            .get("body")
+            .get("variables");
+        assert_eq!(variables.as_array().len(), 21);
+        assert_eq!(variables.as_array()[0].get("value").as_str(), Some("0x00"));
+        assert_eq!(
+            variables.as_array()[16].get("value").as_str(),
+            Some("0x300")
+        );
+
+        let memory = find("response", "readMemory");
+        assert_eq!(memory[0].get("body").get("data").as_str(), Some("AQA="));
+        // Addresses that overflow are refused, not wrapped
+        for response in &memory[1..] {
+            assert_eq!(response.get("success"), &Value::Bool(false));
+        }
+        assert_eq!(
+            json::parse(&find("response", "disconnect")[0].get("success").to_string()),
+            Some(Value::Bool(true))
+        );
+    }
+}
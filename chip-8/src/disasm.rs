@@ -0,0 +1,401 @@
+use std::fmt;
+
+use crate::interpreter::variant::Variant;
+use crate::interpreter::Interpreter;
+
+/// A decoded opcode, named after the Cowgod mnemonic it disassembles to.
+///
+/// Registers are stored as their index (X in VX), addresses and bytes as they
+/// appear in the opcode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    Scd(u8),
+    Scu(u8),
+    Cls,
+    Ret,
+    Scr,
+    Scl,
+    Exit,
+    Low,
+    High,
+    Jp(u16),
+    Call(u16),
+    SeVxKk(u8, u8),
+    SneVxKk(u8, u8),
+    SeVxVy(u8, u8),
+    LdIVxVy(u8, u8),
+    LdVxVyI(u8, u8),
+    LdVxKk(u8, u8),
+    AddVxKk(u8, u8),
+    LdVxVy(u8, u8),
+    OrVxVy(u8, u8),
+    AndVxVy(u8, u8),
+    XorVxVy(u8, u8),
+    AddVxVy(u8, u8),
+    SubVxVy(u8, u8),
+    ShrVxVy(u8, u8),
+    SubnVxVy(u8, u8),
+    ShlVxVy(u8, u8),
+    SneVxVy(u8, u8),
+    LdINnn(u16),
+    JpV0Nnn(u16),
+    RndVxKk(u8, u8),
+    DrwVxVyN(u8, u8, u8),
+    SkpVx(u8),
+    SknpVx(u8),
+    /// XO-CHIP F000 NNNN, the address being the word following the opcode.
+    LdILong,
+    Plane(u8),
+    Audio,
+    LdVxDt(u8),
+    LdVxK(u8),
+    LdDtVx(u8),
+    LdStVx(u8),
+    AddIVx(u8),
+    LdFVx(u8),
+    LdHfVx(u8),
+    LdBVx(u8),
+    PitchVx(u8),
+    LdIVx(u8),
+    LdVxI(u8),
+    LdRVx(u8),
+    LdVxR(u8),
+    /// Anything the variant doesn't know, most likely data.
+    Unknown(u16),
+}
+
+impl Instruction {
+    /// Size in bytes of the instruction in memory.
+    pub fn size(&self) -> usize {
+        match self {
+            Instruction::LdILong => 4,
+            _ => 2,
+        }
+    }
+
+    /// The opcode `disassemble()` decodes back into this instruction.
+    pub fn encode(&self) -> u16 {
+        let xy = |n: u16, x: u8, y: u8, m: u16| n << 12 | (x as u16) << 8 | (y as u16) << 4 | m;
+        let xkk = |n: u16, x: u8, kk: u8| n << 12 | (x as u16) << 8 | kk as u16;
+        let fx = |x: u8, kk: u16| 0xF000 | (x as u16) << 8 | kk;
+
+        match *self {
+            Instruction::Scd(n) => 0x00C0 | n as u16,
+            Instruction::Scu(n) => 0x00D0 | n as u16,
+            Instruction::Cls => 0x00E0,
+            Instruction::Ret => 0x00EE,
+            Instruction::Scr => 0x00FB,
+            Instruction::Scl => 0x00FC,
+            Instruction::Exit => 0x00FD,
+            Instruction::Low => 0x00FE,
+            Instruction::High => 0x00FF,
+            Instruction::Jp(nnn) => 0x1000 | nnn,
+            Instruction::Call(nnn) => 0x2000 | nnn,
+            Instruction::SeVxKk(x, kk) => xkk(0x3, x, kk),
+            Instruction::SneVxKk(x, kk) => xkk(0x4, x, kk),
+            Instruction::SeVxVy(x, y) => xy(0x5, x, y, 0x0),
+            Instruction::LdIVxVy(x, y) => xy(0x5, x, y, 0x2),
+            Instruction::LdVxVyI(x, y) => xy(0x5, x, y, 0x3),
+            Instruction::LdVxKk(x, kk) => xkk(0x6, x, kk),
+            Instruction::AddVxKk(x, kk) => xkk(0x7, x, kk),
+            Instruction::LdVxVy(x, y) => xy(0x8, x, y, 0x0),
+            Instruction::OrVxVy(x, y) => xy(0x8, x, y, 0x1),
+            Instruction::AndVxVy(x, y) => xy(0x8, x, y, 0x2),
+            Instruction::XorVxVy(x, y) => xy(0x8, x, y, 0x3),
+            Instruction::AddVxVy(x, y) => xy(0x8, x, y, 0x4),
+            Instruction::SubVxVy(x, y) => xy(0x8, x, y, 0x5),
+            Instruction::ShrVxVy(x, y) => xy(0x8, x, y, 0x6),
+            Instruction::SubnVxVy(x, y) => xy(0x8, x, y, 0x7),
+            Instruction::ShlVxVy(x, y) => xy(0x8, x, y, 0xE),
+            Instruction::SneVxVy(x, y) => xy(0x9, x, y, 0x0),
+            Instruction::LdINnn(nnn) => 0xA000 | nnn,
+            Instruction::JpV0Nnn(nnn) => 0xB000 | nnn,
+            Instruction::RndVxKk(x, kk) => xkk(0xC, x, kk),
+            Instruction::DrwVxVyN(x, y, n) => xy(0xD, x, y, n as u16),
+            Instruction::SkpVx(x) => xkk(0xE, x, 0x9E),
+            Instruction::SknpVx(x) => xkk(0xE, x, 0xA1),
+            Instruction::LdILong => 0xF000,
+            Instruction::Plane(n) => fx(n, 0x01),
+            Instruction::Audio => 0xF002,
+            Instruction::LdVxDt(x) => fx(x, 0x07),
+            Instruction::LdVxK(x) => fx(x, 0x0A),
+            Instruction::LdDtVx(x) => fx(x, 0x15),
+            Instruction::LdStVx(x) => fx(x, 0x18),
+            Instruction::AddIVx(x) => fx(x, 0x1E),
+            Instruction::LdFVx(x) => fx(x, 0x29),
+            Instruction::LdHfVx(x) => fx(x, 0x30),
+            Instruction::LdBVx(x) => fx(x, 0x33),
+            Instruction::PitchVx(x) => fx(x, 0x3A),
+            Instruction::LdIVx(x) => fx(x, 0x55),
+            Instruction::LdVxI(x) => fx(x, 0x65),
+            Instruction::LdRVx(x) => fx(x, 0x75),
+            Instruction::LdVxR(x) => fx(x, 0x85),
+            Instruction::Unknown(opcode) => opcode,
+        }
+    }
+}
+
+/// Decode a single opcode as `variant` understands it.
+pub fn disassemble(opcode: u16, variant: Variant) -> Instruction {
+    let n1 = ((opcode & 0xF000) >> 12) as u8;
+    let n2 = ((opcode & 0x0F00) >> 8) as u8;
+    let n3 = ((opcode & 0x00F0) >> 4) as u8;
+    let n4 = (opcode & 0x000F) as u8;
+
+    let nnn = opcode & 0x0FFF;
+    let kk = (opcode & 0x00FF) as u8;
+
+    let (x, y, n) = (n2, n3, n4);
+    let schip = variant != Variant::Chip8;
+    let xo = variant == Variant::XoChip;
+
+    match (n1, n2, n3, n4) {
+        (0x00, 0x00, 0x0C, _) if schip => Instruction::Scd(n),
+        (0x00, 0x00, 0x0D, _) if xo => Instruction::Scu(n),
+        (0x00, 0x00, 0x0e, 0x00) => Instruction::Cls,
+        (0x00, 0x00, 0x0e, 0x0e) => Instruction::Ret,
+        (0x00, 0x00, 0x0F, 0x0B) if schip => Instruction::Scr,
+        (0x00, 0x00, 0x0F, 0x0C) if schip => Instruction::Scl,
+        (0x00, 0x00, 0x0F, 0x0D) if schip => Instruction::Exit,
+        (0x00, 0x00, 0x0F, 0x0E) if schip => Instruction::Low,
+        (0x00, 0x00, 0x0F, 0x0F) if schip => Instruction::High,
+        (0x01, _, _, _) => Instruction::Jp(nnn),
+        (0x02, _, _, _) => Instruction::Call(nnn),
+        (0x03, _, _, _) => Instruction::SeVxKk(x, kk),
+        (0x04, _, _, _) => Instruction::SneVxKk(x, kk),
+        (0x05, _, _, 0x00) => Instruction::SeVxVy(x, y),
+        (0x05, _, _, 0x02) if xo => Instruction::LdIVxVy(x, y),
+        (0x05, _, _, 0x03) if xo => Instruction::LdVxVyI(x, y),
+        (0x06, _, _, _) => Instruction::LdVxKk(x, kk),
+        (0x07, _, _, _) => Instruction::AddVxKk(x, kk),
+        (0x08, _, _, 0x00) => Instruction::LdVxVy(x, y),
+        (0x08, _, _, 0x01) => Instruction::OrVxVy(x, y),
+        (0x08, _, _, 0x02) => Instruction::AndVxVy(x, y),
+        (0x08, _, _, 0x03) => Instruction::XorVxVy(x, y),
+        (0x08, _, _, 0x04) => Instruction::AddVxVy(x, y),
+        (0x08, _, _, 0x05) => Instruction::SubVxVy(x, y),
+        (0x08, _, _, 0x06) => Instruction::ShrVxVy(x, y),
+        (0x08, _, _, 0x07) => Instruction::SubnVxVy(x, y),
+        (0x08, _, _, 0x0E) => Instruction::ShlVxVy(x, y),
+        (0x09, _, _, 0x00) => Instruction::SneVxVy(x, y),
+        (0x0A, _, _, _) => Instruction::LdINnn(nnn),
+        (0x0B, _, _, _) => Instruction::JpV0Nnn(nnn),
+        (0x0C, _, _, _) => Instruction::RndVxKk(x, kk),
+        (0x0D, _, _, _) => Instruction::DrwVxVyN(x, y, n),
+        (0x0E, _, 0x09, 0x0E) => Instruction::SkpVx(x),
+        (0x0E, _, 0x0A, 0x01) => Instruction::SknpVx(x),
+        (0x0F, 0x00, 0x00, 0x00) if xo => Instruction::LdILong,
+        (0x0F, _, 0x00, 0x01) if xo => Instruction::Plane(n2),
+        (0x0F, 0x00, 0x00, 0x02) if xo => Instruction::Audio,
+        (0x0F, _, 0x00, 0x07) => Instruction::LdVxDt(x),
+        (0x0F, _, 0x00, 0x0A) => Instruction::LdVxK(x),
+        (0x0F, _, 0x01, 0x05) => Instruction::LdDtVx(x),
+        (0x0F, _, 0x01, 0x08) => Instruction::LdStVx(x),
+        (0x0F, _, 0x01, 0x0E) => Instruction::AddIVx(x),
+        (0x0F, _, 0x02, 0x09) => Instruction::LdFVx(x),
+        (0x0F, _, 0x03, 0x00) if schip => Instruction::LdHfVx(x),
+        (0x0F, _, 0x03, 0x03) => Instruction::LdBVx(x),
+        (0x0F, _, 0x03, 0x0A) if xo => Instruction::PitchVx(x),
+        (0x0F, _, 0x05, 0x05) => Instruction::LdIVx(x),
+        (0x0F, _, 0x06, 0x05) => Instruction::LdVxI(x),
+        (0x0F, _, 0x07, 0x05) if schip => Instruction::LdRVx(x),
+        (0x0F, _, 0x08, 0x05) if schip => Instruction::LdVxR(x),
+        _ => Instruction::Unknown(opcode),
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Instruction::Scd(n) => write!(f, "SCD {}", n),
+            Instruction::Scu(n) => write!(f, "SCU {}", n),
+            Instruction::Cls => write!(f, "CLS"),
+            Instruction::Ret => write!(f, "RET"),
+            Instruction::Scr => write!(f, "SCR"),
+            Instruction::Scl => write!(f, "SCL"),
+            Instruction::Exit => write!(f, "EXIT"),
+            Instruction::Low => write!(f, "LOW"),
+            Instruction::High => write!(f, "HIGH"),
+            Instruction::Jp(nnn) => write!(f, "JP {:#05X}", nnn),
+            Instruction::Call(nnn) => write!(f, "CALL {:#05X}", nnn),
+            Instruction::SeVxKk(x, kk) => write!(f, "SE V{:X}, {:#04X}", x, kk),
+            Instruction::SneVxKk(x, kk) => write!(f, "SNE V{:X}, {:#04X}", x, kk),
+            Instruction::SeVxVy(x, y) => write!(f, "SE V{:X}, V{:X}", x, y),
+            Instruction::LdIVxVy(x, y) => write!(f, "LD [I], V{:X}-V{:X}", x, y),
+            Instruction::LdVxVyI(x, y) => write!(f, "LD V{:X}-V{:X}, [I]", x, y),
+            Instruction::LdVxKk(x, kk) => write!(f, "LD V{:X}, {:#04X}", x, kk),
+            Instruction::AddVxKk(x, kk) => write!(f, "ADD V{:X}, {:#04X}", x, kk),
+            Instruction::LdVxVy(x, y) => write!(f, "LD V{:X}, V{:X}", x, y),
+            Instruction::OrVxVy(x, y) => write!(f, "OR V{:X}, V{:X}", x, y),
+            Instruction::AndVxVy(x, y) => write!(f, "AND V{:X}, V{:X}", x, y),
+            Instruction::XorVxVy(x, y) => write!(f, "XOR V{:X}, V{:X}", x, y),
+            Instruction::AddVxVy(x, y) => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Instruction::SubVxVy(x, y) => write!(f, "SUB V{:X}, V{:X}", x, y),
+            Instruction::ShrVxVy(x, y) => write!(f, "SHR V{:X}, V{:X}", x, y),
+            Instruction::SubnVxVy(x, y) => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            Instruction::ShlVxVy(x, y) => write!(f, "SHL V{:X}, V{:X}", x, y),
+            Instruction::SneVxVy(x, y) => write!(f, "SNE V{:X}, V{:X}", x, y),
+            Instruction::LdINnn(nnn) => write!(f, "LD I, {:#05X}", nnn),
+            Instruction::JpV0Nnn(nnn) => write!(f, "JP V0, {:#05X}", nnn),
+            Instruction::RndVxKk(x, kk) => write!(f, "RND V{:X}, {:#04X}", x, kk),
+            Instruction::DrwVxVyN(x, y, n) => write!(f, "DRW V{:X}, V{:X}, {}", x, y, n),
+            Instruction::SkpVx(x) => write!(f, "SKP V{:X}", x),
+            Instruction::SknpVx(x) => write!(f, "SKNP V{:X}", x),
+            Instruction::LdILong => write!(f, "LD I, LONG"),
+            Instruction::Plane(n) => write!(f, "PLANE {}", n),
+            Instruction::Audio => write!(f, "AUDIO"),
+            Instruction::LdVxDt(x) => write!(f, "LD V{:X}, DT", x),
+            Instruction::LdVxK(x) => write!(f, "LD V{:X}, K", x),
+            Instruction::LdDtVx(x) => write!(f, "LD DT, V{:X}", x),
+            Instruction::LdStVx(x) => write!(f, "LD ST, V{:X}", x),
+            Instruction::AddIVx(x) => write!(f, "ADD I, V{:X}", x),
+            Instruction::LdFVx(x) => write!(f, "LD F, V{:X}", x),
+            Instruction::LdHfVx(x) => write!(f, "LD HF, V{:X}", x),
+            Instruction::LdBVx(x) => write!(f, "LD B, V{:X}", x),
+            Instruction::PitchVx(x) => write!(f, "PITCH V{:X}", x),
+            Instruction::LdIVx(x) => write!(f, "LD [I], V{:X}", x),
+            Instruction::LdVxI(x) => write!(f, "LD V{:X}, [I]", x),
+            Instruction::LdRVx(x) => write!(f, "LD R, V{:X}", x),
+            Instruction::LdVxR(x) => write!(f, "LD V{:X}, R", x),
+            Instruction::Unknown(opcode) => write!(f, "DW {:#06X}", opcode),
+        }
+    }
+}
+
+/// One line of a ROM listing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Line {
+    pub address: u16,
+    /// The 2 bytes of the opcode, 4 for F000 NNNN, or a single one for a trailing odd byte.
+    pub bytes: Vec<u8>,
+    pub instruction: Instruction,
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let hex: String = self
+            .bytes
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect();
+        write!(f, "{:03X}: {:<8}  ", self.address, hex)?;
+
+        match (self.instruction, self.bytes.as_slice()) {
+            (Instruction::LdILong, [_, _, high, low]) => {
+                write!(f, "LD I, LONG {:#06X}", (*high as u16) << 8 | *low as u16)
+            }
+            (_, [byte]) => write!(f, "DB {:#04X}", byte),
+            (instruction, _) => write!(f, "{}", instruction),
+        }
+    }
+}
+
+/// Disassemble the instruction at `offset` in `bytes`, listed as if it were at `address`.
+fn line_at(bytes: &[u8], offset: usize, address: u16, variant: Variant) -> Line {
+    if offset + 1 >= bytes.len() {
+        let byte = bytes.get(offset).copied().unwrap_or(0);
+
+        return Line {
+            address,
+            bytes: vec![byte],
+            instruction: Instruction::Unknown(byte as u16),
+        };
+    }
+
+    let opcode = (bytes[offset] as u16) << 8 | bytes[offset + 1] as u16;
+    let mut instruction = disassemble(opcode, variant);
+
+    // A long load cut by the end of the bytes is just data
+    if offset + instruction.size() > bytes.len() {
+        instruction = Instruction::Unknown(opcode);
+    }
+
+    Line {
+        address,
+        bytes: bytes[offset..offset + instruction.size()].to_vec(),
+        instruction,
+    }
+}
+
+/// Disassemble the instruction at `address` in the interpreter memory.
+pub fn disassemble_at(memory: &[u8], address: u16, variant: Variant) -> Line {
+    line_at(memory, address as usize, address, variant)
+}
+
+/// Disassemble a whole ROM, addresses starting where `Interpreter::load_rom()` puts it.
+pub fn disassemble_rom(rom: &[u8], variant: Variant) -> Vec<Line> {
+    let mut lines = Vec::new();
+    let mut offset = 0;
+
+    while offset < rom.len() {
+        let address = Interpreter::PROGRAM_START.wrapping_add(offset as u16);
+        let line = line_at(rom, offset, address, variant);
+
+        offset += line.bytes.len();
+        lines.push(line);
+    }
+
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{disassemble, disassemble_rom, Instruction};
+    use crate::interpreter::variant::Variant;
+
+    #[test]
+    fn test_display() {
+        let cases = [
+            (0x63AA, "LD V3, 0xAA"),
+            (0xD015, "DRW V0, V1, 5"),
+            (0x2ABC, "CALL 0xABC"),
+            (0xF365, "LD V3, [I]"),
+            (0x8126, "SHR V1, V2"),
+            (0xB200, "JP V0, 0x200"),
+            (0xFFFF, "DW 0xFFFF"),
+        ];
+
+        for (opcode, text) in cases.iter() {
+            assert_eq!(disassemble(*opcode, Variant::Chip8).to_string(), *text);
+        }
+    }
+
+    #[test]
+    fn test_variants() {
+        assert_eq!(
+            disassemble(0x00FF, Variant::Chip8),
+            Instruction::Unknown(0x00FF)
+        );
+        assert_eq!(disassemble(0x00FF, Variant::SuperChip), Instruction::High);
+        assert_eq!(
+            disassemble(0xF201, Variant::SuperChip),
+            Instruction::Unknown(0xF201)
+        );
+        assert_eq!(disassemble(0xF201, Variant::XoChip), Instruction::Plane(2));
+    }
+
+    #[test]
+    fn test_encode_roundtrip() {
+        for opcode in 0..=u16::MAX {
+            assert_eq!(disassemble(opcode, Variant::XoChip).encode(), opcode);
+        }
+    }
+
+    #[test]
+    fn test_disassemble_rom() {
+        let rom = [0x00, 0xE0, 0xF0, 0x00, 0x12, 0x34, 0xAB];
+        let listing: Vec<String> = disassemble_rom(&rom, Variant::XoChip)
+            .iter()
+            .map(|line| line.to_string())
+            .collect();
+
+        assert_eq!(
+            listing,
+            [
+                "200: 00E0      CLS",
+                "202: F0001234  LD I, LONG 0x1234",
+                "206: AB        DB 0xAB",
+            ]
+        );
+    }
+}
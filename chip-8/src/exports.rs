@@ -1,48 +1,1312 @@
-use crate::interpreter::{screen::Screen, Interpreter};
+use crate::audio::{Audio, Waveform};
+use crate::disasm::disassemble_at;
+use crate::interpreter::debugger::{Condition, Debugger, Register, StopReason};
+use crate::interpreter::error::ExecError;
+use crate::interpreter::screen::Palette;
+use crate::interpreter::timing::Timing;
+use crate::interpreter::trace::Tracer;
+use crate::interpreter::watch::{Access, WatchTarget};
+use crate::interpreter::{quirks::Quirks, rewind::Rewind, variant::Variant};
+use crate::interpreter::{FrameResult, Interpreter};
+use std::cell::{Cell, RefCell};
 
-static mut CHIP8: Interpreter = Interpreter::new();
+/// Returned instead of an `ExecError` code when a handle doesn't point to a live instance.
+pub const INVALID_HANDLE: u8 = 255;
+
+/// Largest upscaling of the RGBA framebuffer.
+pub const MAX_SCALE: usize = 16;
+
+/// Everything the host owns through a handle.
+struct Instance {
+    interpreter: Interpreter,
+    // Save states, indexed by slot
+    states: Vec<Vec<u8>>,
+    // Fed on every tick once enabled
+    rewind: Option<Rewind>,
+    // Last text handed to the host, such as a disassembled line
+    text: String,
+    debugger: Debugger,
+    // PC, opcode, kind, target, old and new value of the last watchpoint hit
+    watch_hit: [u16; 6],
+    // The screen as RGBA, upscaled `scale` times
+    rgba: Vec<u8>,
+    palette: Palette,
+    scale: usize,
+    audio: Audio,
+    // Samples handed to the host by the last `instance_render_audio()`, or the sound of
+    // the frames of the last `instance_run_frame()` or `instance_run_for()`
+    samples: Vec<f32>,
+    // Frames whose sound was rendered by the runs, for `Audio::frame_len()`
+    audio_frames: u64,
+    // What the last `instance_run_frame()` or `instance_run_for()` did
+    frame: FrameResult,
+}
+
+impl Instance {
+    const SAMPLE_RATE: u32 = 44_100;
+
+    /// The code of a stop reason, keeping a watchpoint hit for `instance_get_watch_hit()`.
+    fn stop_code(&mut self, reason: StopReason) -> u8 {
+        if let StopReason::Watchpoint(hit) = reason {
+            let (kind, target) = match hit.target {
+                WatchTarget::Read(address) => (0, address),
+                WatchTarget::Write(address) => (1, address),
+                WatchTarget::Register(register) => (2, register.id() as u16),
+            };
+            self.watch_hit = [hit.pc, hit.opcode, kind, target, hit.old, hit.new];
+        }
+
+        reason.code()
+    }
+
+    /// Run a frame, adding its sound to `samples` before the tick stops it, and feed the
+    /// rewind buffer like `instance_tick()` does.
+    fn run_frame(&mut self) -> Result<FrameResult, ExecError> {
+        let (audio, samples) = (&mut self.audio, &mut self.samples);
+        let len = audio.frame_len(self.audio_frames);
+
+        let frame = self.interpreter.run_frame_with(|interpreter| {
+            let start = samples.len();
+            samples.resize(start + len, 0.0);
+            audio.render(interpreter, &mut samples[start..]);
+        })?;
+        self.audio_frames += 1;
+
+        if let Some(rewind) = self.rewind.as_mut() {
+            rewind.record(&self.interpreter);
+        }
+
+        Ok(frame)
+    }
+}
+
+thread_local! {
+    // Boxed so the pointers handed to the host (pixels, memory) don't move when the list grows.
+    // A handle is the index in this list plus one, 0 never being a valid handle.
+    static INSTANCES: RefCell<Vec<Option<Box<Instance>>>> = const { RefCell::new(Vec::new()) };
+    // The instance behind the single-instance exports, created on first use
+    static DEFAULT_HANDLE: Cell<u32> = const { Cell::new(0) };
+}
+
+fn with_instance<R>(handle: u32, f: impl FnOnce(&mut Instance) -> R) -> Option<R> {
+    INSTANCES.with(|instances| {
+        let mut instances = instances.borrow_mut();
+        let index = (handle as usize).checked_sub(1)?;
+        let instance = instances.get_mut(index)?.as_mut()?;
+
+        Some(f(instance))
+    })
+}
+
+fn default_handle() -> u32 {
+    DEFAULT_HANDLE.with(|handle| {
+        if handle.get() == 0 {
+            handle.set(create());
+        }
+
+        handle.get()
+    })
+}
+
+fn quirks_from_preset(preset: u8) -> Option<Quirks> {
+    match preset {
+        0 => Some(Quirks::new()),
+        1 => Some(Quirks::COSMAC_VIP),
+        2 => Some(Quirks::CHIP_48),
+        3 => Some(Quirks::SUPER_CHIP),
+        4 => Some(Quirks::XO_CHIP),
+        _ => None,
+    }
+}
+
+fn variant_from_id(id: u8) -> Option<Variant> {
+    match id {
+        0 => Some(Variant::Chip8),
+        1 => Some(Variant::SuperChip),
+        2 => Some(Variant::XoChip),
+        _ => None,
+    }
+}
+
+/// Create a new interpreter and return its handle.
+#[no_mangle]
+pub fn create() -> u32 {
+    INSTANCES.with(|instances| {
+        let mut instances = instances.borrow_mut();
+        let instance = Some(Box::new(Instance {
+            interpreter: Interpreter::new(),
+            states: Vec::new(),
+            rewind: None,
+            text: String::new(),
+            debugger: Debugger::new(),
+            watch_hit: [0; 6],
+            rgba: Vec::new(),
+            palette: Palette::default(),
+            scale: 1,
+            audio: Audio::new(Instance::SAMPLE_RATE),
+            samples: Vec::new(),
+            audio_frames: 0,
+            frame: FrameResult::default(),
+        }));
+
+        // Reuse the slot of a destroyed instance if there is one
+        match instances.iter().position(Option::is_none) {
+            Some(index) => {
+                instances[index] = instance;
+                index as u32 + 1
+            }
+            None => {
+                instances.push(instance);
+                instances.len() as u32
+            }
+        }
+    })
+}
+
+/// Free an interpreter, every pointer obtained from it becoming invalid.
+#[no_mangle]
+pub fn destroy(handle: u32) {
+    INSTANCES.with(|instances| {
+        let mut instances = instances.borrow_mut();
+
+        if let Some(slot) = (handle as usize)
+            .checked_sub(1)
+            .and_then(|index| instances.get_mut(index))
+        {
+            *slot = None;
+        }
+    });
+
+    DEFAULT_HANDLE.with(|default| {
+        if default.get() == handle {
+            default.set(0);
+        }
+    });
+}
+
+/// Allocate `len` bytes the host can fill with a ROM before calling `instance_load_rom()`.
+#[no_mangle]
+pub fn alloc_rom_buffer(len: usize) -> *mut u8 {
+    let mut buffer = Vec::<u8>::with_capacity(len);
+    let ptr = buffer.as_mut_ptr();
+    std::mem::forget(buffer);
+
+    ptr
+}
+
+/// # Safety
+///
+/// `ptr` and `len` must come from the same `alloc_rom_buffer()` call, freed only once.
+#[no_mangle]
+pub unsafe fn free_rom_buffer(ptr: *mut u8, len: usize) {
+    drop(Vec::from_raw_parts(ptr, 0, len));
+}
+
+#[no_mangle]
+pub fn instance_init(handle: u32) {
+    with_instance(handle, |instance| instance.interpreter.init());
+}
+
+/// Reset the interpreter and load the ROM, returning 0 or the `LoadError` code.
+///
+/// # Safety
+///
+/// `ptr` must point to `len` readable bytes, like a buffer from `alloc_rom_buffer()`.
+#[no_mangle]
+pub unsafe fn instance_load_rom(handle: u32, ptr: *const u8, len: usize) -> u8 {
+    let rom = std::slice::from_raw_parts(ptr, len);
+
+    with_instance(handle, |instance| {
+        match instance.interpreter.load_rom(rom) {
+            Ok(()) => {
+                if let Some(rewind) = instance.rewind.as_mut() {
+                    rewind.clear();
+                }
+
+                0
+            }
+            Err(error) => error.code(),
+        }
+    })
+    .unwrap_or(INVALID_HANDLE)
+}
+
+/// The memory moves when init() resizes it for another variant, so ask again after init().
+#[no_mangle]
+pub fn instance_get_memory(handle: u32) -> *const u8 {
+    with_instance(handle, |instance| instance.interpreter.memory.as_ptr())
+        .unwrap_or(std::ptr::null())
+}
 
 #[no_mangle]
-pub unsafe fn init() {
-    CHIP8.init();
+pub fn instance_get_memory_size(handle: u32) -> usize {
+    with_instance(handle, |instance| instance.interpreter.memory.len()).unwrap_or(0)
 }
 
+/// The first `instance_get_width() * instance_get_height()` bytes are the visible pixels.
 #[no_mangle]
-pub unsafe fn get_memory() -> &'static [u8; 4096] {
-    &CHIP8.memory
+pub fn instance_get_pixels(handle: u32) -> *const u8 {
+    with_instance(handle, |instance| {
+        instance.interpreter.screen.pixels.as_ptr()
+    })
+    .unwrap_or(std::ptr::null())
 }
 
+/// Count a frame: decrement the timers and feed the rewind buffer.
 #[no_mangle]
-pub unsafe fn get_pixels() -> &'static [u8; 2048] {
-    &CHIP8.screen.pixels
+pub fn instance_tick(handle: u32) {
+    with_instance(handle, |instance| {
+        instance.interpreter.tick();
+
+        if let Some(rewind) = instance.rewind.as_mut() {
+            rewind.record(&instance.interpreter);
+        }
+    });
+}
+
+/// Start recording a state every `interval` ticks, keeping the last `capacity` ones.
+#[no_mangle]
+pub fn instance_enable_rewind(handle: u32, capacity: usize, interval: usize) {
+    with_instance(handle, |instance| {
+        instance.rewind = Some(Rewind::new(capacity, interval))
+    });
+}
+
+#[no_mangle]
+pub fn instance_disable_rewind(handle: u32) {
+    with_instance(handle, |instance| instance.rewind = None);
+}
+
+/// Go back about `frames` ticks and return how many were actually rewound.
+#[no_mangle]
+pub fn instance_rewind(handle: u32, frames: usize) -> usize {
+    with_instance(handle, |instance| match instance.rewind.as_mut() {
+        Some(rewind) => rewind
+            .rewind(&mut instance.interpreter, frames)
+            .unwrap_or(0),
+        None => 0,
+    })
+    .unwrap_or(0)
+}
+
+/// Execute one instruction and return 0, or the `ExecError` code if it failed.
+#[no_mangle]
+pub fn instance_cycle(handle: u32) -> u8 {
+    with_instance(handle, |instance| match instance.interpreter.cycle() {
+        Ok(()) => 0,
+        Err(error) => error.code(),
+    })
+    .unwrap_or(INVALID_HANDLE)
+}
+
+#[no_mangle]
+pub fn instance_set_instructions_per_frame(handle: u32, count: usize) {
+    with_instance(handle, |instance| {
+        instance.interpreter.instructions_per_frame = count
+    });
+}
+
+/// Pace `instance_run_frame()`: 0 for `instructions_per_frame` instructions per frame,
+/// 1 for the cycles of the COSMAC VIP. Returns false for an unknown timing.
+#[no_mangle]
+pub fn instance_set_timing(handle: u32, timing: u8) -> bool {
+    let timing = match timing {
+        0 => Timing::Fixed,
+        1 => Timing::CosmacVip,
+        _ => return false,
+    };
+
+    with_instance(handle, |instance| instance.interpreter.timing = timing).is_some()
+}
+
+/// Run a frame of instructions then tick, returning 0 or the `ExecError` code.
+/// `instance_get_frame_flags()` then tells what the frame did, and
+/// `instance_get_frame_audio()` what it sounded like.
+#[no_mangle]
+pub fn instance_run_frame(handle: u32) -> u8 {
+    with_instance(handle, |instance| {
+        instance.samples.clear();
+
+        match instance.run_frame() {
+            Ok(frame) => {
+                instance.frame = frame;
+                0
+            }
+            Err(error) => error.code(),
+        }
+    })
+    .unwrap_or(INVALID_HANDLE)
+}
+
+/// Run as many frames as fit in `micros` plus what previous calls left over, so the
+/// program keeps its speed whatever the refresh rate. Returns 0 or the `ExecError` code.
+#[no_mangle]
+pub fn instance_run_for(handle: u32, micros: u32) -> u8 {
+    with_instance(handle, |instance| {
+        let mut result = FrameResult {
+            halted: instance.interpreter.has_exited(),
+            ..FrameResult::default()
+        };
+        instance.samples.clear();
+
+        for _ in 0..instance.interpreter.frames_due(micros.into()) {
+            match instance.run_frame() {
+                Ok(frame) => result = result.then(frame),
+                Err(error) => return error.code(),
+            }
+        }
+
+        instance.frame = result;
+        0
+    })
+    .unwrap_or(INVALID_HANDLE)
+}
+
+/// What the last run did: bit 0 set if the screen changed, bit 1 if the sound timer was
+/// running, bit 2 if the program waits for a key and bit 3 if it exited.
+#[no_mangle]
+pub fn instance_get_frame_flags(handle: u32) -> u8 {
+    with_instance(handle, |instance| instance.frame.flags()).unwrap_or(0)
+}
+
+/// The sound of the frames the last `instance_run_frame()` or `instance_run_for()` ran,
+/// `instance_get_frame_audio_len()` samples rendered like `instance_render_audio()` does.
+/// The pointer is valid until the next run.
+#[no_mangle]
+pub fn instance_get_frame_audio(handle: u32) -> *const f32 {
+    with_instance(handle, |instance| instance.samples.as_ptr()).unwrap_or(std::ptr::null())
+}
+
+#[no_mangle]
+pub fn instance_get_frame_audio_len(handle: u32) -> usize {
+    with_instance(handle, |instance| instance.samples.len()).unwrap_or(0)
 }
 
+/// Press a keypad key, from 0x0 to 0xF. Other keys are ignored instead of trapping.
 #[no_mangle]
-pub unsafe fn tick() {
-    CHIP8.tick();
+pub fn instance_set_key_down(handle: u32, key: u8) {
+    if key <= 0xF {
+        with_instance(handle, |instance| {
+            instance.interpreter.keypad.set_down(key.into())
+        });
+    }
 }
 
 #[no_mangle]
-pub unsafe fn cycle() {
-    &CHIP8.cycle();
+pub fn instance_set_key_up(handle: u32, key: u8) {
+    if key <= 0xF {
+        with_instance(handle, |instance| {
+            instance.interpreter.keypad.set_up(key.into())
+        });
+    }
 }
 
+/// Seed the CXKK random generator, the same seed always giving the same game.
 #[no_mangle]
-pub unsafe fn set_key_down(key: u8) {
-    CHIP8.keypad.set_down(key.into());
+pub fn instance_set_seed(handle: u32, seed: u64) {
+    with_instance(handle, |instance| instance.interpreter.rng.set_seed(seed));
 }
 
+/// Select the quirks preset: 0 for the default behavior, 1 for COSMAC VIP,
+/// 2 for CHIP-48, 3 for SUPER-CHIP and 4 for XO-CHIP. Returns false for an unknown preset.
 #[no_mangle]
-pub unsafe fn set_key_up(key: u8) {
-    CHIP8.keypad.set_up(key.into());
+pub fn instance_set_quirks(handle: u32, preset: u8) -> bool {
+    match quirks_from_preset(preset) {
+        Some(quirks) => {
+            with_instance(handle, |instance| instance.interpreter.quirks = quirks).is_some()
+        }
+        None => false,
+    }
 }
 
+/// Make DXYN wait for the next frame, like on the VIP, without changing the other quirks.
 #[no_mangle]
-pub unsafe fn get_width() -> usize {
-    Screen::WIDTH
+pub fn instance_set_display_wait(handle: u32, enabled: bool) {
+    with_instance(handle, |instance| {
+        instance.interpreter.quirks.display_wait = enabled
+    });
 }
 
+/// Select the instruction set: 0 for CHIP-8, 1 for SUPER-CHIP and 2 for XO-CHIP,
+/// applied on the next init(). Returns false for an unknown variant.
+#[no_mangle]
+pub fn instance_set_variant(handle: u32, variant: u8) -> bool {
+    match variant_from_id(variant) {
+        Some(variant) => {
+            with_instance(handle, |instance| instance.interpreter.variant = variant).is_some()
+        }
+        None => false,
+    }
+}
+
+/// Snapshot the interpreter into `slot` and return the size of the state.
+#[no_mangle]
+pub fn instance_save_state(handle: u32, slot: usize) -> usize {
+    with_instance(handle, |instance| {
+        if instance.states.len() <= slot {
+            instance.states.resize(slot + 1, Vec::new());
+        }
+
+        instance.states[slot] = instance.interpreter.save_state();
+        instance.states[slot].len()
+    })
+    .unwrap_or(0)
+}
+
+/// The state saved in `slot`, for the host to keep it somewhere (null if the slot is empty).
+#[no_mangle]
+pub fn instance_get_state(handle: u32, slot: usize) -> *const u8 {
+    with_instance(handle, |instance| match instance.states.get(slot) {
+        Some(state) if !state.is_empty() => state.as_ptr(),
+        _ => std::ptr::null(),
+    })
+    .unwrap_or(std::ptr::null())
+}
+
+/// Restore the state saved in `slot`, returning 0 or the `StateError` code.
+#[no_mangle]
+pub fn instance_load_state(handle: u32, slot: usize) -> u8 {
+    with_instance(handle, |instance| {
+        let state = instance.states.get(slot).map(Vec::as_slice).unwrap_or(&[]);
+
+        match instance.interpreter.load_state(state) {
+            Ok(()) => 0,
+            Err(error) => error.code(),
+        }
+    })
+    .unwrap_or(INVALID_HANDLE)
+}
+
+/// Restore a state kept by the host, returning 0 or the `StateError` code.
+///
+/// # Safety
+///
+/// `ptr` must point to `len` readable bytes, like a buffer from `alloc_rom_buffer()`.
+#[no_mangle]
+pub unsafe fn instance_load_state_from(handle: u32, ptr: *const u8, len: usize) -> u8 {
+    let state = std::slice::from_raw_parts(ptr, len);
+
+    with_instance(handle, |instance| {
+        match instance.interpreter.load_state(state) {
+            Ok(()) => 0,
+            Err(error) => error.code(),
+        }
+    })
+    .unwrap_or(INVALID_HANDLE)
+}
+
+/// Width of the active resolution, which changes with the SUPER-CHIP hi-res mode.
+#[no_mangle]
+pub fn instance_get_width(handle: u32) -> usize {
+    with_instance(handle, |instance| instance.interpreter.screen.width()).unwrap_or(0)
+}
+
+#[no_mangle]
+pub fn instance_get_height(handle: u32) -> usize {
+    with_instance(handle, |instance| instance.interpreter.screen.height()).unwrap_or(0)
+}
+
+/// Set palette entry `index`, 0 for unlit pixels, 1 and 2 for pixels lit on the first and
+/// second plane and 3 for both, to `rgba` as `0xRRGGBBAA`. Returns false for a bad index.
+#[no_mangle]
+pub fn instance_set_palette_color(handle: u32, index: u8, rgba: u32) -> bool {
+    with_instance(handle, |instance| {
+        match instance.palette.colors.get_mut(index as usize) {
+            Some(color) => {
+                *color = rgba.to_be_bytes();
+                true
+            }
+            None => false,
+        }
+    })
+    .unwrap_or(false)
+}
+
+/// Draw each pixel as a `scale` by `scale` square, between 1 and `MAX_SCALE`.
+#[no_mangle]
+pub fn instance_set_scale(handle: u32, scale: usize) {
+    with_instance(handle, |instance| {
+        instance.scale = scale.clamp(1, MAX_SCALE);
+    });
+}
+
+/// Render the screen as RGBA, ready for an `ImageData` of `instance_get_width() * scale`
+/// by `instance_get_height() * scale` pixels. The pointer is valid until the next call.
+#[no_mangle]
+pub fn instance_render_rgba(handle: u32) -> *const u8 {
+    with_instance(handle, |instance| {
+        let screen = &instance.interpreter.screen;
+        instance.rgba.resize(screen.rgba_len(instance.scale), 0);
+        screen.render_rgba_scaled(&mut instance.rgba, &instance.palette, instance.scale);
+
+        instance.rgba.as_ptr()
+    })
+    .unwrap_or(std::ptr::null())
+}
+
+/// Configure the beeper: `waveform` is 0 for a square wave and 1 for a sine, `volume`
+/// goes from 0 to 1. Returns false for an unknown waveform.
+#[no_mangle]
+pub fn instance_set_audio(handle: u32, sample_rate: u32, waveform: u8, volume: f32) -> bool {
+    let waveform = match waveform {
+        0 => Waveform::Square,
+        1 => Waveform::Sine,
+        _ => return false,
+    };
+
+    with_instance(handle, |instance| {
+        let audio = &mut instance.audio;
+        audio.sample_rate = sample_rate.max(1);
+        audio.waveform = waveform;
+        audio.volume = volume.clamp(0.0, 1.0);
+        true
+    })
+    .unwrap_or(false)
+}
+
+/// Generate the next `len` mono samples, between -1 and 1, from the current sound timer.
+/// Called once per frame, before `instance_tick()`, by hosts running the frames with
+/// `instance_cycle()`. The pointer is valid until the next call.
+#[no_mangle]
+pub fn instance_render_audio(handle: u32, len: usize) -> *const f32 {
+    with_instance(handle, |instance| {
+        instance.samples.resize(len, 0.0);
+        instance
+            .audio
+            .render(&instance.interpreter, &mut instance.samples);
+
+        instance.samples.as_ptr()
+    })
+    .unwrap_or(std::ptr::null())
+}
+
+/// Disassemble the instruction at `address` into the text buffer and return its length in bytes.
+#[no_mangle]
+pub fn instance_disassemble(handle: u32, address: u16) -> usize {
+    with_instance(handle, |instance| {
+        let interpreter = &instance.interpreter;
+        let line = disassemble_at(&interpreter.memory, address, interpreter.variant);
+
+        instance.text = line.to_string();
+        instance.text.len()
+    })
+    .unwrap_or(0)
+}
+
+/// UTF-8 text written by the last call filling the text buffer, such as `instance_disassemble()`.
+#[no_mangle]
+pub fn instance_get_text(handle: u32) -> *const u8 {
+    with_instance(handle, |instance| instance.text.as_ptr()).unwrap_or(std::ptr::null())
+}
+
+/// Read a register: 0 to 15 for V0 to VF, 16 for I, 17 for PC, 18 for SP, 19 for DT, 20 for ST.
+#[no_mangle]
+pub fn instance_get_register(handle: u32, register: u8) -> u16 {
+    with_instance(handle, |instance| {
+        Register::from_id(register).map_or(0, |register| register.read(&instance.interpreter))
+    })
+    .unwrap_or(0)
+}
+
+/// The 16 return addresses, the first `instance_get_register(handle, 18)` being in use.
+#[no_mangle]
+pub fn instance_get_stack(handle: u32) -> *const u16 {
+    with_instance(handle, |instance| instance.interpreter.stack.as_ptr())
+        .unwrap_or(std::ptr::null())
+}
+
+#[no_mangle]
+pub fn instance_add_breakpoint(handle: u32, address: u16) {
+    with_instance(handle, |instance| {
+        instance.debugger.add_breakpoint(address, None)
+    });
+}
+
+/// Break at `address` only when the condition, such as `V3 == 0x10`, holds.
+/// Returns false if the condition can't be parsed.
+///
+/// # Safety
+///
+/// `ptr` must point to `len` bytes of UTF-8, like a buffer from `alloc_rom_buffer()`.
+#[no_mangle]
+pub unsafe fn instance_add_conditional_breakpoint(
+    handle: u32,
+    address: u16,
+    ptr: *const u8,
+    len: usize,
+) -> bool {
+    let text = std::slice::from_raw_parts(ptr, len);
+    let condition = match std::str::from_utf8(text).ok().and_then(Condition::parse) {
+        Some(condition) => condition,
+        None => return false,
+    };
+
+    with_instance(handle, |instance| {
+        instance.debugger.add_breakpoint(address, Some(condition))
+    })
+    .is_some()
+}
+
+#[no_mangle]
+pub fn instance_remove_breakpoint(handle: u32, address: u16) -> bool {
+    with_instance(handle, |instance| {
+        instance.debugger.remove_breakpoint(address)
+    })
+    .unwrap_or(false)
+}
+
+#[no_mangle]
+pub fn instance_clear_breakpoints(handle: u32) {
+    with_instance(handle, |instance| instance.debugger.clear_breakpoints());
+}
+
+/// Execute one instruction and return the `StopReason` code.
+#[no_mangle]
+pub fn instance_step(handle: u32) -> u8 {
+    with_instance(handle, |instance| {
+        let reason = instance.debugger.step(&mut instance.interpreter);
+        instance.stop_code(reason)
+    })
+    .unwrap_or(INVALID_HANDLE)
+}
+
+/// Step, running a whole subroutine call in up to `max_cycles` instructions.
+#[no_mangle]
+pub fn instance_step_over(handle: u32, max_cycles: usize) -> u8 {
+    with_instance(handle, |instance| {
+        let reason = instance
+            .debugger
+            .step_over(&mut instance.interpreter, max_cycles);
+        instance.stop_code(reason)
+    })
+    .unwrap_or(INVALID_HANDLE)
+}
+
+/// Run until the current subroutine returns, in up to `max_cycles` instructions.
+#[no_mangle]
+pub fn instance_step_out(handle: u32, max_cycles: usize) -> u8 {
+    with_instance(handle, |instance| {
+        let reason = instance
+            .debugger
+            .step_out(&mut instance.interpreter, max_cycles);
+        instance.stop_code(reason)
+    })
+    .unwrap_or(INVALID_HANDLE)
+}
+
+/// Execute up to `max_cycles` instructions, stopping early on a breakpoint,
+/// and return the `StopReason` code.
+#[no_mangle]
+pub fn instance_run_until_break(handle: u32, max_cycles: usize) -> u8 {
+    with_instance(handle, |instance| {
+        let reason = instance
+            .debugger
+            .run_until_break(&mut instance.interpreter, max_cycles);
+        instance.stop_code(reason)
+    })
+    .unwrap_or(INVALID_HANDLE)
+}
+
+/// Stop when `len` bytes from `start` are accessed: 1 for reads, 2 for writes, 3 for both.
+/// Returns false for another access.
+#[no_mangle]
+pub fn instance_watch_memory(handle: u32, start: u16, len: usize, access: u8) -> bool {
+    let access = match access {
+        1 => Access::Read,
+        2 => Access::Write,
+        3 => Access::ReadWrite,
+        _ => return false,
+    };
+
+    with_instance(handle, |instance| {
+        instance
+            .interpreter
+            .watchpoints
+            .watch_memory(start, len, access)
+    })
+    .is_some()
+}
+
+#[no_mangle]
+pub fn instance_unwatch_memory(handle: u32, start: u16) -> bool {
+    with_instance(handle, |instance| {
+        instance.interpreter.watchpoints.unwatch_memory(start)
+    })
+    .unwrap_or(false)
+}
+
+/// Stop when an instruction changes a register, numbered like in `instance_get_register()`.
+#[no_mangle]
+pub fn instance_watch_register(handle: u32, register: u8) -> bool {
+    let register = match Register::from_id(register) {
+        Some(register) => register,
+        None => return false,
+    };
+
+    with_instance(handle, |instance| {
+        instance.interpreter.watchpoints.watch_register(register)
+    })
+    .is_some()
+}
+
+#[no_mangle]
+pub fn instance_unwatch_register(handle: u32, register: u8) -> bool {
+    with_instance(handle, |instance| match Register::from_id(register) {
+        Some(register) => instance.interpreter.watchpoints.unwatch_register(register),
+        None => false,
+    })
+    .unwrap_or(false)
+}
+
+#[no_mangle]
+pub fn instance_clear_watchpoints(handle: u32) {
+    with_instance(handle, |instance| instance.interpreter.watchpoints.clear());
+}
+
+/// The last watchpoint hit as 6 values: PC, opcode, kind (0 read, 1 write, 2 register),
+/// address or register number, old value and new value.
+#[no_mangle]
+pub fn instance_get_watch_hit(handle: u32) -> *const u16 {
+    with_instance(handle, |instance| instance.watch_hit.as_ptr()).unwrap_or(std::ptr::null())
+}
+
+/// Start recording the last `capacity` executed instructions.
+#[no_mangle]
+pub fn instance_enable_trace(handle: u32, capacity: usize) {
+    with_instance(handle, |instance| {
+        instance.interpreter.tracer = Some(Tracer::with_capacity(capacity))
+    });
+}
+
+#[no_mangle]
+pub fn instance_disable_trace(handle: u32) {
+    with_instance(handle, |instance| instance.interpreter.tracer = None);
+}
+
+/// Move the recorded instructions into the text buffer, one per line, and return its length.
+#[no_mangle]
+pub fn instance_take_trace(handle: u32) -> usize {
+    with_instance(handle, |instance| {
+        let entries = match instance.interpreter.tracer.as_mut() {
+            Some(tracer) => tracer.drain(),
+            None => Vec::new(),
+        };
+
+        instance.text.clear();
+        for entry in entries {
+            instance.text += &format!("{}\n", entry);
+        }
+        instance.text.len()
+    })
+    .unwrap_or(0)
+}
+
+// Single-instance exports, thin wrappers over a default instance for hosts running one machine
+
 #[no_mangle]
-pub unsafe fn get_height() -> usize {
-    Screen::HEIGHT
+pub fn init() {
+    instance_init(default_handle());
+}
+
+/// # Safety
+///
+/// See `instance_load_rom()`.
+#[no_mangle]
+pub unsafe fn load_rom(ptr: *const u8, len: usize) -> u8 {
+    instance_load_rom(default_handle(), ptr, len)
+}
+
+#[no_mangle]
+pub fn get_memory() -> *const u8 {
+    instance_get_memory(default_handle())
+}
+
+#[no_mangle]
+pub fn get_memory_size() -> usize {
+    instance_get_memory_size(default_handle())
+}
+
+#[no_mangle]
+pub fn get_pixels() -> *const u8 {
+    instance_get_pixels(default_handle())
+}
+
+#[no_mangle]
+pub fn tick() {
+    instance_tick(default_handle());
+}
+
+#[no_mangle]
+pub fn enable_rewind(capacity: usize, interval: usize) {
+    instance_enable_rewind(default_handle(), capacity, interval);
+}
+
+#[no_mangle]
+pub fn disable_rewind() {
+    instance_disable_rewind(default_handle());
+}
+
+#[no_mangle]
+pub fn rewind(frames: usize) -> usize {
+    instance_rewind(default_handle(), frames)
+}
+
+#[no_mangle]
+pub fn cycle() -> u8 {
+    instance_cycle(default_handle())
+}
+
+#[no_mangle]
+pub fn set_instructions_per_frame(count: usize) {
+    instance_set_instructions_per_frame(default_handle(), count);
+}
+
+#[no_mangle]
+pub fn set_timing(timing: u8) -> bool {
+    instance_set_timing(default_handle(), timing)
+}
+
+#[no_mangle]
+pub fn run_frame() -> u8 {
+    instance_run_frame(default_handle())
+}
+
+#[no_mangle]
+pub fn run_for(micros: u32) -> u8 {
+    instance_run_for(default_handle(), micros)
+}
+
+#[no_mangle]
+pub fn get_frame_flags() -> u8 {
+    instance_get_frame_flags(default_handle())
+}
+
+#[no_mangle]
+pub fn get_frame_audio() -> *const f32 {
+    instance_get_frame_audio(default_handle())
+}
+
+#[no_mangle]
+pub fn get_frame_audio_len() -> usize {
+    instance_get_frame_audio_len(default_handle())
+}
+
+#[no_mangle]
+pub fn set_key_down(key: u8) {
+    instance_set_key_down(default_handle(), key);
+}
+
+#[no_mangle]
+pub fn set_key_up(key: u8) {
+    instance_set_key_up(default_handle(), key);
+}
+
+#[no_mangle]
+pub fn set_seed(seed: u64) {
+    instance_set_seed(default_handle(), seed);
+}
+
+#[no_mangle]
+pub fn set_quirks(preset: u8) -> bool {
+    instance_set_quirks(default_handle(), preset)
+}
+
+#[no_mangle]
+pub fn set_display_wait(enabled: bool) {
+    instance_set_display_wait(default_handle(), enabled);
+}
+
+#[no_mangle]
+pub fn set_variant(variant: u8) -> bool {
+    instance_set_variant(default_handle(), variant)
+}
+
+#[no_mangle]
+pub fn save_state(slot: usize) -> usize {
+    instance_save_state(default_handle(), slot)
+}
+
+#[no_mangle]
+pub fn get_state(slot: usize) -> *const u8 {
+    instance_get_state(default_handle(), slot)
+}
+
+#[no_mangle]
+pub fn load_state(slot: usize) -> u8 {
+    instance_load_state(default_handle(), slot)
+}
+
+/// # Safety
+///
+/// See `instance_load_state_from()`.
+#[no_mangle]
+pub unsafe fn load_state_from(ptr: *const u8, len: usize) -> u8 {
+    instance_load_state_from(default_handle(), ptr, len)
+}
+
+#[no_mangle]
+pub fn get_width() -> usize {
+    instance_get_width(default_handle())
+}
+
+#[no_mangle]
+pub fn get_height() -> usize {
+    instance_get_height(default_handle())
+}
+
+#[no_mangle]
+pub fn set_palette_color(index: u8, rgba: u32) -> bool {
+    instance_set_palette_color(default_handle(), index, rgba)
+}
+
+#[no_mangle]
+pub fn set_scale(scale: usize) {
+    instance_set_scale(default_handle(), scale);
+}
+
+#[no_mangle]
+pub fn render_rgba() -> *const u8 {
+    instance_render_rgba(default_handle())
+}
+
+#[no_mangle]
+pub fn set_audio(sample_rate: u32, waveform: u8, volume: f32) -> bool {
+    instance_set_audio(default_handle(), sample_rate, waveform, volume)
+}
+
+#[no_mangle]
+pub fn render_audio(len: usize) -> *const f32 {
+    instance_render_audio(default_handle(), len)
+}
+
+#[no_mangle]
+pub fn disassemble(address: u16) -> usize {
+    instance_disassemble(default_handle(), address)
+}
+
+#[no_mangle]
+pub fn get_text() -> *const u8 {
+    instance_get_text(default_handle())
+}
+
+#[no_mangle]
+pub fn get_register(register: u8) -> u16 {
+    instance_get_register(default_handle(), register)
+}
+
+#[no_mangle]
+pub fn get_stack() -> *const u16 {
+    instance_get_stack(default_handle())
+}
+
+#[no_mangle]
+pub fn add_breakpoint(address: u16) {
+    instance_add_breakpoint(default_handle(), address)
+}
+
+/// # Safety
+///
+/// See `instance_add_conditional_breakpoint()`.
+#[no_mangle]
+pub unsafe fn add_conditional_breakpoint(address: u16, ptr: *const u8, len: usize) -> bool {
+    instance_add_conditional_breakpoint(default_handle(), address, ptr, len)
+}
+
+#[no_mangle]
+pub fn remove_breakpoint(address: u16) -> bool {
+    instance_remove_breakpoint(default_handle(), address)
+}
+
+#[no_mangle]
+pub fn clear_breakpoints() {
+    instance_clear_breakpoints(default_handle())
+}
+
+#[no_mangle]
+pub fn step() -> u8 {
+    instance_step(default_handle())
+}
+
+#[no_mangle]
+pub fn step_over(max_cycles: usize) -> u8 {
+    instance_step_over(default_handle(), max_cycles)
+}
+
+#[no_mangle]
+pub fn step_out(max_cycles: usize) -> u8 {
+    instance_step_out(default_handle(), max_cycles)
+}
+
+#[no_mangle]
+pub fn run_until_break(max_cycles: usize) -> u8 {
+    instance_run_until_break(default_handle(), max_cycles)
+}
+
+#[no_mangle]
+pub fn watch_memory(start: u16, len: usize, access: u8) -> bool {
+    instance_watch_memory(default_handle(), start, len, access)
+}
+
+#[no_mangle]
+pub fn unwatch_memory(start: u16) -> bool {
+    instance_unwatch_memory(default_handle(), start)
+}
+
+#[no_mangle]
+pub fn watch_register(register: u8) -> bool {
+    instance_watch_register(default_handle(), register)
+}
+
+#[no_mangle]
+pub fn unwatch_register(register: u8) -> bool {
+    instance_unwatch_register(default_handle(), register)
+}
+
+#[no_mangle]
+pub fn clear_watchpoints() {
+    instance_clear_watchpoints(default_handle())
+}
+
+#[no_mangle]
+pub fn get_watch_hit() -> *const u16 {
+    instance_get_watch_hit(default_handle())
+}
+
+#[no_mangle]
+pub fn enable_trace(capacity: usize) {
+    instance_enable_trace(default_handle(), capacity)
+}
+
+#[no_mangle]
+pub fn disable_trace() {
+    instance_disable_trace(default_handle())
+}
+
+#[no_mangle]
+pub fn take_trace() -> usize {
+    instance_take_trace(default_handle())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_instances_are_independent() {
+        let a = create();
+        let b = create();
+        instance_init(a);
+        instance_init(b);
+
+        instance_set_key_down(a, 3);
+        instance_set_key_down(a, 16);
+        instance_set_key_up(a, 255);
+        with_instance(a, |instance| {
+            assert!(instance.interpreter.keypad.is_pressed(3))
+        });
+        with_instance(b, |instance| {
+            assert!(!instance.interpreter.keypad.is_pressed(3))
+        });
+
+        destroy(a);
+        destroy(b);
+    }
+
+    #[test]
+    fn test_destroyed_handle() {
+        let handle = create();
+        destroy(handle);
+
+        assert_eq!(instance_cycle(handle), INVALID_HANDLE);
+        assert_eq!(instance_cycle(0), INVALID_HANDLE);
+        assert!(instance_get_pixels(handle).is_null());
+        // The slot is reused by the next instance
+        assert_eq!(create(), handle);
+    }
+
+    #[test]
+    fn test_load_rom_from_buffer() {
+        let handle = create();
+        let ptr = alloc_rom_buffer(2);
+
+        unsafe {
+            ptr.copy_from_nonoverlapping([0x12, 0x34].as_ptr(), 2);
+            assert_eq!(instance_load_rom(handle, ptr, 2), 0);
+            free_rom_buffer(ptr, 2);
+        }
+
+        with_instance(handle, |instance| {
+            assert_eq!(instance.interpreter.memory[0x200..0x202], [0x12, 0x34])
+        });
+        destroy(handle);
+    }
+
+    #[test]
+    fn test_state_slots() {
+        let handle = create();
+        instance_init(handle);
+        instance_set_key_down(handle, 5);
+
+        assert!(instance_get_state(handle, 1).is_null());
+        assert!(instance_save_state(handle, 1) > 0);
+        assert!(!instance_get_state(handle, 1).is_null());
+
+        instance_set_key_up(handle, 5);
+        assert_eq!(instance_load_state(handle, 1), 0);
+        with_instance(handle, |instance| {
+            assert!(instance.interpreter.keypad.is_pressed(5))
+        });
+
+        // An empty slot isn't a valid state
+        assert_eq!(instance_load_state(handle, 0), 1);
+        destroy(handle);
+    }
+
+    #[test]
+    fn test_disassemble() {
+        let handle = create();
+        let ptr = alloc_rom_buffer(2);
+
+        unsafe {
+            ptr.copy_from_nonoverlapping([0x63, 0xAA].as_ptr(), 2);
+            instance_load_rom(handle, ptr, 2);
+            free_rom_buffer(ptr, 2);
+
+            let len = instance_disassemble(handle, 0x200);
+            let text = std::slice::from_raw_parts(instance_get_text(handle), len);
+            assert_eq!(text, b"200: 63AA      LD V3, 0xAA");
+        }
+        destroy(handle);
+    }
+
+    #[test]
+    fn test_render_rgba() {
+        let handle = create();
+        let ptr = alloc_rom_buffer(4);
+
+        unsafe {
+            // LD I, 0x000 (the 0 font sprite); DRW V0, V0, 1
+            ptr.copy_from_nonoverlapping([0xA0, 0x00, 0xD0, 0x01].as_ptr(), 4);
+            instance_load_rom(handle, ptr, 4);
+            free_rom_buffer(ptr, 4);
+        }
+        instance_cycle(handle);
+        instance_cycle(handle);
+
+        assert!(instance_set_palette_color(handle, 1, 0x11223344));
+        assert!(!instance_set_palette_color(handle, 4, 0));
+        instance_set_scale(handle, 2);
+
+        let rgba =
+            unsafe { std::slice::from_raw_parts(instance_render_rgba(handle), 128 * 64 * 4) };
+        // 0xF0: four lit pixels, doubled
+        assert_eq!(rgba[..4], [0x11, 0x22, 0x33, 0x44]);
+        assert_eq!(rgba[28..32], [0x11, 0x22, 0x33, 0x44]);
+        assert_eq!(rgba[32..36], [0, 0, 0, 255]);
+        assert_eq!(rgba[128 * 4..128 * 4 + 4], [0x11, 0x22, 0x33, 0x44]);
+        destroy(handle);
+    }
+
+    #[test]
+    fn test_render_audio() {
+        let handle = create();
+        assert!(instance_set_audio(handle, 8000, 0, 1.0));
+        assert!(!instance_set_audio(handle, 8000, 2, 1.0));
+
+        let samples = unsafe { std::slice::from_raw_parts(instance_render_audio(handle, 80), 80) };
+        assert!(samples.iter().all(|sample| *sample == 0.0));
+
+        with_instance(handle, |instance| instance.interpreter.stimer = 1);
+        let samples = unsafe { std::slice::from_raw_parts(instance_render_audio(handle, 80), 80) };
+        assert_eq!(samples[45], 1.0);
+        destroy(handle);
+    }
+
+    #[test]
+    fn test_run_for() {
+        let handle = create();
+        // LD V0, 5; LD ST, V0; CLS; JP 0x206
+        let rom = [0x60, 0x05, 0xF0, 0x18, 0x00, 0xE0, 0x12, 0x06];
+        unsafe { instance_load_rom(handle, rom.as_ptr(), rom.len()) };
+        instance_enable_rewind(handle, 10, 1);
+
+        assert_eq!(instance_run_for(handle, 10_000), 0);
+        assert_eq!(instance_get_frame_flags(handle), 0);
+
+        // Two frames, drawing in the first one and still beeping after the second
+        instance_set_instructions_per_frame(handle, 3);
+        assert_eq!(instance_run_for(handle, 25_000), 0);
+        assert_eq!(instance_get_frame_flags(handle), 0b11);
+        with_instance(handle, |instance| {
+            assert_eq!(instance.frame.frames, 2);
+            assert_eq!(instance.rewind.as_ref().unwrap().available_frames(), 1);
+        });
+
+        assert_eq!(instance_get_frame_audio_len(handle), 2 * 735);
+
+        // The last frame of the beep is heard too
+        with_instance(handle, |instance| instance.interpreter.stimer = 1);
+        assert_eq!(instance_run_frame(handle), 0);
+        assert_eq!(instance_get_frame_flags(handle), 0b10);
+        let len = instance_get_frame_audio_len(handle);
+        let samples = unsafe { std::slice::from_raw_parts(instance_get_frame_audio(handle), len) };
+        assert_eq!(len, 735);
+        assert!(samples.iter().any(|sample| *sample != 0.0));
+
+        assert!(!instance_set_timing(handle, 2));
+        assert!(instance_set_timing(handle, 1));
+        instance_run_frame(handle);
+        with_instance(handle, |instance| {
+            assert!(instance.frame.instructions > 3);
+        });
+        destroy(handle);
+        assert_eq!(instance_run_frame(handle), INVALID_HANDLE);
+    }
+
+    #[test]
+    fn test_debugger() {
+        let handle = create();
+        let rom = [0x73, 0x01, 0x12, 0x00];
+        let condition = b"V3 == 4";
+
+        unsafe {
+            instance_load_rom(handle, rom.as_ptr(), rom.len());
+            assert!(instance_add_conditional_breakpoint(
+                handle,
+                0x202,
+                condition.as_ptr(),
+                condition.len()
+            ));
+            assert!(!instance_add_conditional_breakpoint(
+                handle,
+                0x202,
+                condition.as_ptr(),
+                3
+            ));
+        }
+
+        assert_eq!(instance_run_until_break(handle, 100), 1);
+        assert_eq!(instance_get_register(handle, 3), 4);
+        assert_eq!(instance_get_register(handle, 17), 0x202);
+        assert_eq!(instance_step(handle), 0);
+        assert_eq!(instance_get_register(handle, 17), 0x200);
+        destroy(handle);
+    }
+
+    #[test]
+    fn test_watch_hit() {
+        let handle = create();
+        let rom = [0x63, 0x05, 0x12, 0x00];
+
+        unsafe {
+            instance_load_rom(handle, rom.as_ptr(), rom.len());
+        }
+        assert!(instance_watch_register(handle, 3));
+        assert!(!instance_watch_memory(handle, 0x300, 1, 0));
+
+        assert_eq!(instance_run_until_break(handle, 10), 4);
+        let hit = unsafe { std::slice::from_raw_parts(instance_get_watch_hit(handle), 6) };
+        assert_eq!(hit, [0x200, 0x6305, 2, 3, 0, 5]);
+        destroy(handle);
+    }
 }
@@ -0,0 +1,620 @@
+//! A GDB Remote Serial Protocol server, so `gdb` or any RSP client can debug a ROM:
+//!
+//! ```text
+//! (gdb) target remote localhost:1234
+//! ```
+//!
+//! Registers are described to the client by `TARGET_XML`, numbered like
+//! `Register::from_id()`. Breakpoints (`Z0`) go to a `Debugger`, watchpoints (`Z2` to `Z4`)
+//! to the interpreter's `Watchpoints`.
+
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+
+use crate::interpreter::debugger::{Debugger, Register, StopReason};
+use crate::interpreter::error::ExecError;
+use crate::interpreter::watch::{Access, WatchTarget};
+use crate::interpreter::Interpreter;
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.chip8.core">
+    <reg name="v0" bitsize="8" type="uint8" regnum="0"/>
+    <reg name="v1" bitsize="8" type="uint8"/>
+    <reg name="v2" bitsize="8" type="uint8"/>
+    <reg name="v3" bitsize="8" type="uint8"/>
+    <reg name="v4" bitsize="8" type="uint8"/>
+    <reg name="v5" bitsize="8" type="uint8"/>
+    <reg name="v6" bitsize="8" type="uint8"/>
+    <reg name="v7" bitsize="8" type="uint8"/>
+    <reg name="v8" bitsize="8" type="uint8"/>
+    <reg name="v9" bitsize="8" type="uint8"/>
+    <reg name="va" bitsize="8" type="uint8"/>
+    <reg name="vb" bitsize="8" type="uint8"/>
+    <reg name="vc" bitsize="8" type="uint8"/>
+    <reg name="vd" bitsize="8" type="uint8"/>
+    <reg name="ve" bitsize="8" type="uint8"/>
+    <reg name="vf" bitsize="8" type="uint8"/>
+    <reg name="i" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+    <reg name="sp" bitsize="8" type="uint8"/>
+    <reg name="dt" bitsize="8" type="uint8"/>
+    <reg name="st" bitsize="8" type="uint8"/>
+  </feature>
+</target>
+"#;
+
+const REGISTER_COUNT: u8 = 21;
+
+/// Largest packet we accept, advertised in `qSupported`.
+const PACKET_SIZE: usize = 0x1000;
+
+/// Instructions between two timer ticks while continuing, like the web view does.
+const CYCLES_PER_TICK: usize = 10;
+
+/// Timer ticks between two checks for an interrupt from the client.
+const TICKS_PER_POLL: usize = 64;
+
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGSEGV: u8 = 11;
+
+/// A byte stream to an RSP client.
+pub trait Connection: Read + Write {
+    /// Whether the client sent an interrupt (`0x03`) while the program runs. Must not block.
+    fn interrupted(&mut self) -> io::Result<bool> {
+        Ok(false)
+    }
+}
+
+impl Connection for TcpStream {
+    fn interrupted(&mut self) -> io::Result<bool> {
+        self.set_nonblocking(true)?;
+        let mut byte = [0];
+        let peeked = self.peek(&mut byte);
+        self.set_nonblocking(false)?;
+
+        match peeked {
+            Ok(0) => Err(ErrorKind::UnexpectedEof.into()),
+            Ok(_) if byte[0] == 0x03 => self.read_exact(&mut byte).map(|_| true),
+            Ok(_) => Ok(false),
+            Err(error) if error.kind() == ErrorKind::WouldBlock => Ok(false),
+            Err(error) => Err(error),
+        }
+    }
+}
+
+impl<C: Connection + ?Sized> Connection for &mut C {
+    fn interrupted(&mut self) -> io::Result<bool> {
+        (**self).interrupted()
+    }
+}
+
+/// Wait for a single client on `address` and debug `interpreter` until it detaches.
+pub fn serve(interpreter: &mut Interpreter, address: impl ToSocketAddrs) -> io::Result<()> {
+    let listener = TcpListener::bind(address)?;
+    let (stream, _) = listener.accept()?;
+    stream.set_nodelay(true)?;
+
+    run_session(stream, interpreter)
+}
+
+/// Answer the client's packets until it kills the session, detaches or disconnects.
+pub fn run_session<C: Connection>(connection: C, interpreter: &mut Interpreter) -> io::Result<()> {
+    let mut session = Session {
+        connection,
+        interpreter,
+        debugger: Debugger::new(),
+        acks: true,
+    };
+
+    while let Some(packet) = session.receive()? {
+        match session.handle(&packet) {
+            Some(reply) => session.send(&reply)?,
+            None => break,
+        }
+
+        // The OK still goes through the usual ack
+        if packet == "QStartNoAckMode" {
+            session.acks = false;
+        }
+    }
+
+    Ok(())
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(text: &str) -> Option<Vec<u8>> {
+    if text.len() % 2 != 0 {
+        return None;
+    }
+
+    (0..text.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(text.get(index..index + 2)?, 16).ok())
+        .collect()
+}
+
+fn parse_number(text: &str) -> Option<usize> {
+    usize::from_str_radix(text, 16).ok()
+}
+
+/// `addr,len` as sent by `m`, `M`, `Z` and `z`.
+fn parse_range(text: &str) -> Option<(usize, usize)> {
+    let (address, len) = text.split_once(',')?;
+    Some((parse_number(address)?, parse_number(len)?))
+}
+
+/// Register values are sent little-endian, in as many bytes as the register is wide.
+fn register_width(register: Register) -> usize {
+    match register {
+        Register::I | Register::Pc => 2,
+        _ => 1,
+    }
+}
+
+struct Session<'a, C: Connection> {
+    connection: C,
+    interpreter: &'a mut Interpreter,
+    debugger: Debugger,
+    // Cleared once the client asks for `QStartNoAckMode`
+    acks: bool,
+}
+
+impl<'a, C: Connection> Session<'a, C> {
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        let mut byte = [0];
+
+        loop {
+            match self.connection.read(&mut byte) {
+                Ok(0) => return Ok(None),
+                Ok(_) => return Ok(Some(byte[0])),
+                Err(error) if error.kind() == ErrorKind::Interrupted => continue,
+                Err(error) => return Err(error),
+            }
+        }
+    }
+
+    /// The next well-formed packet, `None` once the client disconnected. Acks, stray
+    /// interrupts and packets with a bad checksum (after asking for them again) are skipped.
+    fn receive(&mut self) -> io::Result<Option<String>> {
+        loop {
+            match self.read_byte()? {
+                None => return Ok(None),
+                Some(b'$') => {}
+                Some(_) => continue,
+            }
+
+            let mut data = Vec::new();
+            loop {
+                match self.read_byte()? {
+                    None => return Ok(None),
+                    Some(b'#') => break,
+                    Some(byte) if data.len() < PACKET_SIZE => data.push(byte),
+                    Some(_) => {}
+                }
+            }
+
+            let mut sum = [0; 2];
+            for digit in sum.iter_mut() {
+                match self.read_byte()? {
+                    Some(byte) => *digit = byte,
+                    None => return Ok(None),
+                }
+            }
+            let valid = std::str::from_utf8(&sum)
+                .ok()
+                .and_then(|sum| u8::from_str_radix(sum, 16).ok())
+                == Some(checksum(&data));
+
+            if self.acks {
+                self.connection.write_all(if valid { b"+" } else { b"-" })?;
+            }
+
+            if valid {
+                return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+            }
+        }
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let packet = format!("${}#{:02x}", data, checksum(data.as_bytes()));
+        self.connection.write_all(packet.as_bytes())?;
+        self.connection.flush()?;
+
+        if !self.acks {
+            return Ok(());
+        }
+
+        // Resend until acknowledged, the client may well have dropped the packet
+        loop {
+            match self.read_byte()? {
+                Some(b'+') | None => return Ok(()),
+                Some(b'-') => self.connection.write_all(packet.as_bytes())?,
+                Some(_) => {}
+            }
+        }
+    }
+
+    /// The reply to `packet`, `None` to end the session. An empty reply tells the client
+    /// the packet isn't supported.
+    fn handle(&mut self, packet: &str) -> Option<String> {
+        let (command, arguments) = packet.split_at(packet.chars().next().map_or(0, char::len_utf8));
+
+        let reply = match command {
+            "?" => format!("S{:02x}", SIGTRAP),
+            "q" | "Q" => self.query(packet),
+            "H" => "OK".to_string(),
+            "g" => self.read_registers(),
+            "G" => self.write_registers(arguments),
+            "p" => self.read_register(arguments),
+            "P" => self.write_register(arguments),
+            "m" => self.read_memory(arguments),
+            "M" => self.write_memory(arguments),
+            "Z" => self.set_point(arguments, true),
+            "z" => self.set_point(arguments, false),
+            "s" => {
+                let reason = self.debugger.step(self.interpreter);
+                self.stop_reply(reason)
+            }
+            "c" => self.resume(),
+            "D" => {
+                let _ = self.send("OK");
+                return None;
+            }
+            "k" => return None,
+            _ => String::new(),
+        };
+
+        Some(reply)
+    }
+
+    fn query(&mut self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            format!("PacketSize={:x};qXfer:features:read+;swbreak+", PACKET_SIZE)
+        } else if let Some(request) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            match parse_range(request) {
+                Some((offset, len)) => {
+                    let rest = TARGET_XML.get(offset.min(TARGET_XML.len())..).unwrap_or("");
+                    if rest.len() <= len {
+                        format!("l{}", rest)
+                    } else {
+                        format!("m{}", &rest[..len])
+                    }
+                }
+                None => "E01".to_string(),
+            }
+        } else if packet == "QStartNoAckMode" {
+            "OK".to_string()
+        } else {
+            match packet {
+                "qAttached" => "1".to_string(),
+                "qC" => "QC1".to_string(),
+                "qfThreadInfo" => "m1".to_string(),
+                "qsThreadInfo" => "l".to_string(),
+                _ => String::new(),
+            }
+        }
+    }
+
+    fn encode_register(&self, register: Register) -> String {
+        let value = register.read(self.interpreter);
+        to_hex(&value.to_le_bytes()[..register_width(register)])
+    }
+
+    fn read_registers(&self) -> String {
+        (0..REGISTER_COUNT)
+            .filter_map(Register::from_id)
+            .map(|register| self.encode_register(register))
+            .collect()
+    }
+
+    fn write_registers(&mut self, arguments: &str) -> String {
+        let bytes = match from_hex(arguments) {
+            Some(bytes) => bytes,
+            None => return "E01".to_string(),
+        };
+
+        let mut bytes = bytes.iter();
+        for register in (0..REGISTER_COUNT).filter_map(Register::from_id) {
+            let mut value = [0; 2];
+            for byte in value.iter_mut().take(register_width(register)) {
+                match bytes.next() {
+                    Some(next) => *byte = *next,
+                    None => return "OK".to_string(),
+                }
+            }
+            register.write(self.interpreter, u16::from_le_bytes(value));
+        }
+
+        "OK".to_string()
+    }
+
+    fn read_register(&self, arguments: &str) -> String {
+        match parse_number(arguments).and_then(|id| Register::from_id(id.min(0xFF) as u8)) {
+            Some(register) => self.encode_register(register),
+            None => "E01".to_string(),
+        }
+    }
+
+    fn write_register(&mut self, arguments: &str) -> String {
+        let parsed = arguments.split_once('=').and_then(|(id, value)| {
+            let register = Register::from_id(parse_number(id)?.min(0xFF) as u8)?;
+            let bytes = from_hex(value)?;
+            if bytes.len() != register_width(register) {
+                return None;
+            }
+
+            let mut value = [0; 2];
+            value[..bytes.len()].copy_from_slice(&bytes);
+            Some((register, u16::from_le_bytes(value)))
+        });
+
+        match parsed {
+            Some((register, value)) => {
+                register.write(self.interpreter, value);
+                "OK".to_string()
+            }
+            None => "E01".to_string(),
+        }
+    }
+
+    fn read_memory(&self, arguments: &str) -> String {
+        let bytes = parse_range(arguments).and_then(|(address, len)| {
+            let end = address.checked_add(len)?;
+            self.interpreter.memory.get(address..end)
+        });
+
+        match bytes {
+            Some(bytes) => to_hex(bytes),
+            None => "E01".to_string(),
+        }
+    }
+
+    fn write_memory(&mut self, arguments: &str) -> String {
+        let parsed = arguments.split_once(':').and_then(|(range, data)| {
+            let (address, len) = parse_range(range)?;
+            let bytes = from_hex(data)?;
+            let end = address.checked_add(len)?;
+            (bytes.len() == len).then_some((address..end, bytes))
+        });
+
+        match parsed {
+            Some((range, bytes)) => match self.interpreter.memory.get_mut(range) {
+                Some(memory) => {
+                    memory.copy_from_slice(&bytes);
+                    "OK".to_string()
+                }
+                None => "E01".to_string(),
+            },
+            None => "E01".to_string(),
+        }
+    }
+
+    /// `Z`/`z` packets: `type,addr,kind`, kind being the length for watchpoints.
+    fn set_point(&mut self, arguments: &str, insert: bool) -> String {
+        let mut fields = arguments.splitn(3, ',');
+        let kind = fields.next();
+        let target = fields.next().and_then(parse_number).zip(
+            fields
+                .next()
+                .and_then(|len| parse_number(len.split(';').next()?)),
+        );
+
+        // A watch can't cover more than the whole memory
+        let (address, len) = match target {
+            Some((address, len)) if address <= 0xFFFF => {
+                (address as u16, len.min(self.interpreter.memory.len()))
+            }
+            _ => return "E01".to_string(),
+        };
+        let access = match kind {
+            Some("0") | Some("1") => None,
+            Some("2") => Some(Access::Write),
+            Some("3") => Some(Access::Read),
+            Some("4") => Some(Access::ReadWrite),
+            _ => return String::new(),
+        };
+
+        match (access, insert) {
+            (None, true) => self.debugger.add_breakpoint(address, None),
+            (None, false) => {
+                self.debugger.remove_breakpoint(address);
+            }
+            (Some(access), true) => self
+                .interpreter
+                .watchpoints
+                .watch_memory(address, len, access),
+            (Some(_), false) => {
+                self.interpreter.watchpoints.unwatch_memory(address);
+            }
+        }
+
+        "OK".to_string()
+    }
+
+    /// Continue until something stops the program or the client interrupts it.
+    fn resume(&mut self) -> String {
+        loop {
+            for _ in 0..TICKS_PER_POLL {
+                match self
+                    .debugger
+                    .run_until_break(self.interpreter, CYCLES_PER_TICK)
+                {
+                    StopReason::CycleLimit => self.interpreter.tick(),
+                    reason => return self.stop_reply(reason),
+                }
+            }
+
+            match self.connection.interrupted() {
+                Ok(false) => {}
+                Ok(true) => return format!("S{:02x}", SIGINT),
+                // The reply won't get through either, the next read ends the session
+                Err(_) => return format!("S{:02x}", SIGINT),
+            }
+        }
+    }
+
+    fn stop_reply(&self, reason: StopReason) -> String {
+        match reason {
+            StopReason::Stepped | StopReason::CycleLimit => format!("S{:02x}", SIGTRAP),
+            StopReason::Breakpoint(_) => format!("T{:02x}swbreak:;", SIGTRAP),
+            StopReason::Watchpoint(hit) => match hit.target {
+                WatchTarget::Write(address) => format!("T{:02x}watch:{:x};", SIGTRAP, address),
+                WatchTarget::Read(address) => format!("T{:02x}rwatch:{:x};", SIGTRAP, address),
+                WatchTarget::Register(_) => format!("S{:02x}", SIGTRAP),
+            },
+            StopReason::Exited => "W00".to_string(),
+            StopReason::Error(ExecError::UnknownOpcode { .. }) => format!("S{:02x}", SIGILL),
+            StopReason::Error(_) => format!("S{:02x}", SIGSEGV),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{checksum, from_hex, run_session, Connection};
+    use crate::asm::assemble;
+    use crate::interpreter::variant::Variant;
+    use crate::interpreter::Interpreter;
+    use std::io::{self, Cursor, Read, Write};
+
+    struct Client {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Read for Client {
+        fn read(&mut self, bytes: &mut [u8]) -> io::Result<usize> {
+            self.input.read(bytes)
+        }
+    }
+
+    impl Write for Client {
+        fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+            self.output.write(bytes)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Connection for Client {}
+
+    fn packet(data: &str) -> String {
+        format!("${}#{:02x}+", data, checksum(data.as_bytes()))
+    }
+
+    /// Run the packets against the interpreter, returning the replies.
+    fn exchange(interpreter: &mut Interpreter, packets: &[&str]) -> Vec<String> {
+        let input: String = packets.iter().map(|data| packet(data)).collect();
+        let mut client = Client {
+            input: Cursor::new(input.into_bytes()),
+            output: Vec::new(),
+        };
+        run_session(&mut client, interpreter).unwrap();
+
+        let output = String::from_utf8(client.output).unwrap();
+        output
+            .split('$')
+            .skip(1)
+            .map(|reply| {
+                let (data, sum) = reply.split_once('#').unwrap();
+                // Our acks for the next packet follow the checksum
+                assert_eq!(from_hex(&sum[..2]), Some(vec![checksum(data.as_bytes())]));
+                data.to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_registers_and_memory() {
+        let mut interpreter = Interpreter::new();
+        interpreter
+            .load_rom(&assemble("LD V1, 0xAB\nLD I, 0x345").unwrap())
+            .unwrap();
+
+        let replies = exchange(
+            &mut interpreter,
+            &[
+                "s",
+                "s",
+                "g",
+                "p11",
+                "P3=7f",
+                "m200,4",
+                "M300,2:beef",
+                "m300,2",
+                "m1000,1",
+                "mffffffffffffffff,1",
+                "Mffffffffffffffff,1:00",
+                "Z2,300,ffffffffffffffff",
+                "qXfer:features:read:target.xml:0,10",
+            ],
+        );
+
+        assert_eq!(replies[0], "S05");
+        let registers = "00ab".to_string() + &"00".repeat(14) + "4503" + "0402" + "000000";
+        assert_eq!(replies[2], registers);
+        assert_eq!(replies[3], "0402");
+        assert_eq!(replies[4], "OK");
+        assert_eq!(interpreter.v[3], 0x7F);
+        assert_eq!(replies[5], "61aba345");
+        assert_eq!(replies[7], "beef");
+        assert_eq!(replies[8], "E01");
+        assert_eq!(replies[9], "E01");
+        assert_eq!(replies[10], "E01");
+        assert_eq!(replies[11], "OK");
+        assert_eq!(replies[12], "m<?xml version=\"1");
+    }
+
+    #[test]
+    fn test_breakpoints_and_continue() {
+        let mut interpreter = Interpreter::new();
+        let rom = assemble(
+            "
+            LD I, 0x300
+            loop: ADD V0, 1
+            SE V0, 5
+            JP loop
+            LD [I], V0
+            EXIT
+            ",
+        )
+        .unwrap();
+        interpreter.variant = Variant::SuperChip;
+        interpreter.load_rom(&rom).unwrap();
+
+        let replies = exchange(
+            &mut interpreter,
+            &[
+                "Z0,206,2", "c", "p0", "z0,206,2", "Z2,300,1", "c", "z2,300,1", "c", "k", "?",
+            ],
+        );
+
+        assert_eq!(
+            replies,
+            [
+                "OK",
+                "T05swbreak:;",
+                "01",
+                "OK",
+                "OK",
+                "T05watch:300;",
+                "OK",
+                "W00"
+            ]
+        );
+        assert_eq!(interpreter.memory[0x300], 5);
+    }
+}
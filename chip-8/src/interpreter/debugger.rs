@@ -0,0 +1,468 @@
+use std::fmt;
+
+use super::error::ExecError;
+use super::watch::WatchHit;
+use super::Interpreter;
+use crate::disasm::{disassemble, Instruction};
+
+/// A register a condition can look at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Register {
+    V(u8),
+    I,
+    Pc,
+    Sp,
+    Dt,
+    St,
+}
+
+impl Register {
+    /// 0 to 15 for V0 to VF, then 16 for I, 17 for PC, 18 for SP, 19 for DT and 20 for ST.
+    pub fn from_id(id: u8) -> Option<Register> {
+        match id {
+            0..=15 => Some(Register::V(id)),
+            16 => Some(Register::I),
+            17 => Some(Register::Pc),
+            18 => Some(Register::Sp),
+            19 => Some(Register::Dt),
+            20 => Some(Register::St),
+            _ => None,
+        }
+    }
+
+    /// The inverse of `from_id()`.
+    pub fn id(&self) -> u8 {
+        match *self {
+            Register::V(x) => x & 0xF,
+            Register::I => 16,
+            Register::Pc => 17,
+            Register::Sp => 18,
+            Register::Dt => 19,
+            Register::St => 20,
+        }
+    }
+
+    fn parse(text: &str) -> Option<Register> {
+        let upper = text.to_ascii_uppercase();
+
+        match upper.as_str() {
+            "I" => Some(Register::I),
+            "PC" => Some(Register::Pc),
+            "SP" => Some(Register::Sp),
+            "DT" => Some(Register::Dt),
+            "ST" => Some(Register::St),
+            _ => {
+                let digit = upper.strip_prefix('V')?;
+                match digit.len() {
+                    1 => u8::from_str_radix(digit, 16).ok().map(Register::V),
+                    _ => None,
+                }
+            }
+        }
+    }
+
+    pub fn read(&self, interpreter: &Interpreter) -> u16 {
+        match *self {
+            Register::V(x) => interpreter.v[x as usize & 0xF] as u16,
+            Register::I => interpreter.i,
+            Register::Pc => interpreter.pc,
+            Register::Sp => interpreter.sp as u16,
+            Register::Dt => interpreter.dtimer as u16,
+            Register::St => interpreter.stimer as u16,
+        }
+    }
+
+    /// Set the register, truncating `value` to its width. SP can't go past the stack.
+    pub fn write(&self, interpreter: &mut Interpreter, value: u16) {
+        match *self {
+            Register::V(x) => interpreter.v[x as usize & 0xF] = value as u8,
+            Register::I => interpreter.i = value,
+            Register::Pc => interpreter.pc = value,
+            Register::Sp => interpreter.sp = value.min(interpreter.stack.len() as u16) as u8,
+            Register::Dt => interpreter.dtimer = value as u8,
+            Register::St => interpreter.stimer = value as u8,
+        }
+    }
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Register::V(x) => write!(f, "V{:X}", x),
+            Register::I => write!(f, "I"),
+            Register::Pc => write!(f, "PC"),
+            Register::Sp => write!(f, "SP"),
+            Register::Dt => write!(f, "DT"),
+            Register::St => write!(f, "ST"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// A test on a register such as `V3 == 0x10`, for conditional breakpoints.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Condition {
+    pub register: Register,
+    pub comparison: Comparison,
+    pub value: u16,
+}
+
+impl Condition {
+    /// Parse `<register> <comparison> <value>`, the value in decimal or `0x` hexadecimal.
+    pub fn parse(text: &str) -> Option<Condition> {
+        let mut words = text.split_whitespace();
+        let register = Register::parse(words.next()?)?;
+        let comparison = match words.next()? {
+            "==" => Comparison::Eq,
+            "!=" => Comparison::Ne,
+            "<" => Comparison::Lt,
+            "<=" => Comparison::Le,
+            ">" => Comparison::Gt,
+            ">=" => Comparison::Ge,
+            _ => return None,
+        };
+        let value = words.next()?;
+        let value = match value
+            .strip_prefix("0x")
+            .or_else(|| value.strip_prefix("0X"))
+        {
+            Some(hex) => u16::from_str_radix(hex, 16).ok()?,
+            None => value.parse().ok()?,
+        };
+
+        match words.next() {
+            Some(_) => None,
+            None => Some(Condition {
+                register,
+                comparison,
+                value,
+            }),
+        }
+    }
+
+    pub fn holds(&self, interpreter: &Interpreter) -> bool {
+        let register = self.register.read(interpreter);
+
+        match self.comparison {
+            Comparison::Eq => register == self.value,
+            Comparison::Ne => register != self.value,
+            Comparison::Lt => register < self.value,
+            Comparison::Le => register <= self.value,
+            Comparison::Gt => register > self.value,
+            Comparison::Ge => register >= self.value,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Breakpoint {
+    pub address: u16,
+    pub condition: Option<Condition>,
+}
+
+/// Why the debugger handed control back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    /// The step asked for is done.
+    Stepped,
+    /// About to execute the instruction at this address.
+    Breakpoint(u16),
+    /// Ran `max_cycles` without stopping.
+    CycleLimit,
+    /// A watchpoint caught the instruction just executed.
+    Watchpoint(WatchHit),
+    Exited,
+    Error(ExecError),
+}
+
+impl StopReason {
+    /// Numeric code handed to the host through the WASM exports: 0 stepped,
+    /// 1 breakpoint, 2 cycle limit, 3 exited, 4 watchpoint, and 0x80 with the `ExecError`
+    /// code for errors.
+    pub fn code(&self) -> u8 {
+        match self {
+            StopReason::Stepped => 0,
+            StopReason::Breakpoint(_) => 1,
+            StopReason::CycleLimit => 2,
+            StopReason::Exited => 3,
+            StopReason::Watchpoint(_) => 4,
+            StopReason::Error(error) => 0x80 | error.code(),
+        }
+    }
+}
+
+/// Breakpoints and stepping over an interpreter it is handed on every call.
+#[derive(Debug, Default)]
+pub struct Debugger {
+    breakpoints: Vec<Breakpoint>,
+    // The breakpoint we last stopped on, not hit again when resuming from it
+    resume_from: Option<u16>,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    /// Break before executing `address`, only when `condition` holds if there is one.
+    /// Replaces the breakpoint already at `address`.
+    pub fn add_breakpoint(&mut self, address: u16, condition: Option<Condition>) {
+        self.remove_breakpoint(address);
+        self.breakpoints.push(Breakpoint { address, condition });
+    }
+
+    /// Returns false if there was no breakpoint at `address`.
+    pub fn remove_breakpoint(&mut self, address: u16) -> bool {
+        let len = self.breakpoints.len();
+        self.breakpoints
+            .retain(|breakpoint| breakpoint.address != address);
+
+        self.breakpoints.len() != len
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    fn should_break(&self, interpreter: &Interpreter) -> bool {
+        self.breakpoints.iter().any(|breakpoint| {
+            breakpoint.address == interpreter.pc
+                && breakpoint
+                    .condition
+                    .map_or(true, |condition| condition.holds(interpreter))
+        })
+    }
+
+    /// Execute up to `max_cycles` instructions, stopping on a breakpoint or once `done`.
+    fn run(
+        &mut self,
+        interpreter: &mut Interpreter,
+        max_cycles: usize,
+        done: impl Fn(&Interpreter) -> bool,
+    ) -> StopReason {
+        let mut resume_from = self.resume_from.take();
+        // Whatever was caught outside of the debugger is stale by now
+        interpreter.watchpoints.take_hit();
+
+        for _ in 0..max_cycles {
+            if resume_from.take() != Some(interpreter.pc) && self.should_break(interpreter) {
+                self.resume_from = Some(interpreter.pc);
+                return StopReason::Breakpoint(interpreter.pc);
+            }
+
+            if let Err(error) = interpreter.cycle() {
+                return StopReason::Error(error);
+            }
+
+            if let Some(hit) = interpreter.watchpoints.take_hit() {
+                return StopReason::Watchpoint(hit);
+            }
+
+            if interpreter.has_exited() {
+                return StopReason::Exited;
+            }
+
+            if done(interpreter) {
+                return StopReason::Stepped;
+            }
+        }
+
+        StopReason::CycleLimit
+    }
+
+    /// Execute a single instruction, breakpoints aside.
+    pub fn step(&mut self, interpreter: &mut Interpreter) -> StopReason {
+        self.resume_from = Some(interpreter.pc);
+        self.run(interpreter, 1, |_| true)
+    }
+
+    /// Like `step()`, except a subroutine call runs until it returns.
+    pub fn step_over(&mut self, interpreter: &mut Interpreter, max_cycles: usize) -> StopReason {
+        let pc = interpreter.pc as usize;
+        let opcode = match interpreter.memory.get(pc..pc + 2) {
+            Some(bytes) => (bytes[0] as u16) << 8 | bytes[1] as u16,
+            None => return self.step(interpreter),
+        };
+
+        if let Instruction::Call(_) = disassemble(opcode, interpreter.variant) {
+            let (return_address, sp) = (interpreter.pc.wrapping_add(2), interpreter.sp);
+
+            self.resume_from = Some(interpreter.pc);
+            self.run(interpreter, max_cycles, |interpreter| {
+                interpreter.pc == return_address && interpreter.sp == sp
+            })
+        } else {
+            self.step(interpreter)
+        }
+    }
+
+    /// Run until the current subroutine returns, or step outside of any.
+    pub fn step_out(&mut self, interpreter: &mut Interpreter, max_cycles: usize) -> StopReason {
+        let sp = interpreter.sp;
+        if sp == 0 {
+            return self.step(interpreter);
+        }
+
+        self.resume_from = Some(interpreter.pc);
+        self.run(interpreter, max_cycles, |interpreter| interpreter.sp < sp)
+    }
+
+    /// Run until a breakpoint, an error or `max_cycles` instructions.
+    pub fn run_until_break(
+        &mut self,
+        interpreter: &mut Interpreter,
+        max_cycles: usize,
+    ) -> StopReason {
+        self.run(interpreter, max_cycles, |_| false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Comparison, Condition, Debugger, Register, StopReason};
+    use crate::asm::assemble;
+    use crate::interpreter::watch::Access;
+    use crate::interpreter::Interpreter;
+
+    fn load(source: &str) -> Interpreter {
+        let mut interpreter = Interpreter::new();
+        interpreter.load_rom(&assemble(source).unwrap()).unwrap();
+        interpreter
+    }
+
+    #[test]
+    fn test_parse_condition() {
+        assert_eq!(
+            Condition::parse("V3 == 0x10"),
+            Some(Condition {
+                register: Register::V(3),
+                comparison: Comparison::Eq,
+                value: 0x10,
+            })
+        );
+        assert_eq!(
+            Condition::parse("dt >= 5").map(|condition| condition.register),
+            Some(Register::Dt)
+        );
+        assert_eq!(Condition::parse("V3 = 1"), None);
+        assert_eq!(Condition::parse("VG == 1"), None);
+    }
+
+    #[test]
+    fn test_breakpoints() {
+        let mut interpreter = load(
+            "
+            loop:   ADD V3, 1
+                    JP loop
+            ",
+        );
+        let mut debugger = Debugger::new();
+        debugger.add_breakpoint(0x202, Condition::parse("V3 == 0x10"));
+
+        assert_eq!(
+            debugger.run_until_break(&mut interpreter, 1000),
+            StopReason::Breakpoint(0x202)
+        );
+        assert_eq!(interpreter.v[3], 0x10);
+
+        // Resuming doesn't stop on the same breakpoint right away
+        assert_eq!(
+            debugger.run_until_break(&mut interpreter, 10),
+            StopReason::CycleLimit
+        );
+
+        debugger.add_breakpoint(0x200, None);
+        assert_eq!(
+            debugger.run_until_break(&mut interpreter, 10),
+            StopReason::Breakpoint(0x200)
+        );
+        assert!(debugger.remove_breakpoint(0x200));
+        assert!(!debugger.remove_breakpoint(0x200));
+    }
+
+    #[test]
+    fn test_stepping() {
+        let source = "
+                    CALL sub
+                    LD V0, 1
+            halt:   JP halt
+            sub:    LD V1, 2
+                    LD V2, 3
+                    RET
+            ";
+        let mut debugger = Debugger::new();
+
+        let mut interpreter = load(source);
+        assert_eq!(
+            debugger.step_over(&mut interpreter, 100),
+            StopReason::Stepped
+        );
+        assert_eq!((interpreter.pc, interpreter.sp), (0x202, 0));
+        assert_eq!(interpreter.v[2], 3);
+
+        let mut interpreter = load(source);
+        assert_eq!(debugger.step(&mut interpreter), StopReason::Stepped);
+        assert_eq!((interpreter.pc, interpreter.sp), (0x206, 1));
+        assert_eq!(
+            debugger.step_out(&mut interpreter, 100),
+            StopReason::Stepped
+        );
+        assert_eq!((interpreter.pc, interpreter.sp), (0x202, 0));
+        assert_eq!(interpreter.v[1], 2);
+    }
+
+    #[test]
+    fn test_breakpoint_inside_step_over() {
+        let mut interpreter = load(
+            "
+                    CALL sub
+            halt:   JP halt
+            sub:    RET
+            ",
+        );
+        let mut debugger = Debugger::new();
+        debugger.add_breakpoint(0x204, None);
+
+        assert_eq!(
+            debugger.step_over(&mut interpreter, 100),
+            StopReason::Breakpoint(0x204)
+        );
+    }
+
+    #[test]
+    fn test_watchpoint_stops() {
+        let mut interpreter = load(
+            "
+                    LD I, 0x300
+            loop:   ADD V0, 1
+                    LD B, V0
+                    JP loop
+            ",
+        );
+        interpreter
+            .watchpoints
+            .watch_memory(0x302, 1, Access::Write);
+        let mut debugger = Debugger::new();
+
+        match debugger.run_until_break(&mut interpreter, 100) {
+            StopReason::Watchpoint(hit) => {
+                assert_eq!((hit.pc, hit.opcode), (0x204, 0xF033));
+                assert_eq!((hit.old, hit.new), (0, 1));
+            }
+            reason => panic!("stopped on {:?}", reason),
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExecError {
+    UnknownOpcode { pc: u16, opcode: u16 },
+    StackOverflow,
+    StackUnderflow,
+    MemoryOutOfBounds { addr: usize },
+}
+
+impl ExecError {
+    /// Numeric code handed to the host through the WASM exports, 0 being "no error".
+    pub fn code(&self) -> u8 {
+        match self {
+            ExecError::UnknownOpcode { .. } => 1,
+            ExecError::StackOverflow => 2,
+            ExecError::StackUnderflow => 3,
+            ExecError::MemoryOutOfBounds { .. } => 4,
+        }
+    }
+}
+
+impl fmt::Display for ExecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExecError::UnknownOpcode { pc, opcode } => {
+                write!(f, "unknown opcode {:#06X} at {:#05X}", opcode, pc)
+            }
+            ExecError::StackOverflow => write!(f, "stack overflow"),
+            ExecError::StackUnderflow => write!(f, "stack underflow"),
+            ExecError::MemoryOutOfBounds { addr } => {
+                write!(f, "memory access out of bounds at {:#06X}", addr)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ExecError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadError {
+    RomTooLarge { size: usize, max: usize },
+}
+
+impl LoadError {
+    /// Numeric code handed to the host through the WASM exports, 0 being "no error".
+    pub fn code(&self) -> u8 {
+        match self {
+            LoadError::RomTooLarge { .. } => 1,
+        }
+    }
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::RomTooLarge { size, max } => {
+                write!(f, "ROM is {} bytes but only {} fit in memory", size, max)
+            }
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StateError {
+    BadMagic,
+    UnsupportedVersion(u8),
+    Truncated,
+    Invalid,
+}
+
+impl StateError {
+    /// Numeric code handed to the host through the WASM exports, 0 being "no error".
+    pub fn code(&self) -> u8 {
+        match self {
+            StateError::BadMagic => 1,
+            StateError::UnsupportedVersion(_) => 2,
+            StateError::Truncated => 3,
+            StateError::Invalid => 4,
+        }
+    }
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateError::BadMagic => write!(f, "not a save state"),
+            StateError::UnsupportedVersion(version) => {
+                write!(f, "unsupported save state version {}", version)
+            }
+            StateError::Truncated => write!(f, "save state is truncated"),
+            StateError::Invalid => write!(f, "save state is corrupted"),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
@@ -0,0 +1,328 @@
+use super::error::ExecError;
+use super::timing::{Timing, VIP_CYCLES_PER_FRAME};
+use super::Interpreter;
+
+// A frame is 1/60 of a second, so time is counted in 1/60 µs to stay exact
+const MICROS_PER_SECOND: u64 = 1_000_000;
+const FRAMES_PER_SECOND: u64 = 60;
+
+/// What happened during `run_frame()`, or all the frames of `run_for()`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FrameResult {
+    /// Frames run, always 1 for `run_frame()`.
+    pub frames: u32,
+    pub instructions: usize,
+    /// The screen was drawn to, cleared, scrolled or switched resolution.
+    pub drew: bool,
+    /// The sound timer was running during the frame, before the tick.
+    pub beeping: bool,
+    /// The program is stuck on FX0A until a key is pressed.
+    pub waiting_for_key: bool,
+    /// The program stopped itself with 00FD.
+    pub halted: bool,
+}
+
+impl FrameResult {
+    /// This result followed by `next`: the counts add up, `drew` is set if either drew
+    /// and the other flags are those of `next`.
+    pub fn then(self, next: FrameResult) -> FrameResult {
+        FrameResult {
+            frames: self.frames + next.frames,
+            instructions: self.instructions + next.instructions,
+            drew: self.drew || next.drew,
+            ..next
+        }
+    }
+
+    /// `drew`, `beeping`, `waiting_for_key` and `halted` as bits 0 to 3, for the WASM exports.
+    pub fn flags(&self) -> u8 {
+        self.drew as u8
+            | (self.beeping as u8) << 1
+            | (self.waiting_for_key as u8) << 2
+            | (self.halted as u8) << 3
+    }
+}
+
+impl Interpreter {
+    /// Execute a frame of instructions then tick the timers once, like 1/60 of a second
+    /// on the real machine, whatever rate the host calls it at.
+    ///
+    /// A frame is `instructions_per_frame` instructions with the fixed timing. With the
+    /// COSMAC VIP timing it lasts until the instructions used up the cycles of a frame,
+    /// counting only the instructions run here and not those stepped by the debugger.
+    /// Either way, DXYN ends it early with the display wait quirk.
+    ///
+    /// On error the frame stops at the failing instruction, without ticking.
+    pub fn run_frame(&mut self) -> Result<FrameResult, ExecError> {
+        self.run_frame_with(|_| {})
+    }
+
+    /// `run_frame()`, calling `before_tick` once the instructions ran but before the
+    /// timers tick. That is when the frame's sound is rendered, or the last frame of every
+    /// beep would be lost.
+    pub fn run_frame_with(
+        &mut self,
+        before_tick: impl FnOnce(&Interpreter),
+    ) -> Result<FrameResult, ExecError> {
+        self.drew = false;
+        self.waiting_for_key = false;
+
+        self.in_frame = true;
+        let instructions = self.run_instructions();
+        self.in_frame = false;
+        let instructions = instructions?;
+        // An instruction running past the end of the frame eats into the next one
+        self.cycles = self.cycles.saturating_sub(VIP_CYCLES_PER_FRAME);
+
+        before_tick(self);
+        let beeping = self.stimer > 0;
+        self.tick();
+
+        Ok(FrameResult {
+            frames: 1,
+            instructions,
+            drew: self.drew,
+            beeping,
+            waiting_for_key: self.waiting_for_key,
+            halted: self.exited,
+        })
+    }
+
+    fn run_instructions(&mut self) -> Result<usize, ExecError> {
+        let mut instructions = 0;
+
+        while !self.exited && !self.waiting_for_vblank {
+            let time_left = match self.timing {
+                Timing::Fixed => instructions < self.instructions_per_frame,
+                Timing::CosmacVip => self.cycles < VIP_CYCLES_PER_FRAME,
+            };
+            if !time_left {
+                break;
+            }
+
+            self.cycle()?;
+            instructions += 1;
+        }
+
+        Ok(instructions)
+    }
+
+    /// Add `micros` to the time not run yet and return how many whole frames it makes,
+    /// keeping the remainder for the next call.
+    pub fn frames_due(&mut self, micros: u64) -> u32 {
+        self.pending_time =
+            (self.pending_time).saturating_add(micros.saturating_mul(FRAMES_PER_SECOND));
+        let frames = self.pending_time / MICROS_PER_SECOND;
+        self.pending_time %= MICROS_PER_SECOND;
+
+        frames.min(u32::MAX as u64) as u32
+    }
+
+    /// Run the frames that fit in `micros` plus what was left over by the previous call,
+    /// so calling it at any rate keeps the program at 60 frames per second.
+    ///
+    /// `drew` is set if any frame drew, the other flags are as of the last frame.
+    pub fn run_for(&mut self, micros: u64) -> Result<FrameResult, ExecError> {
+        self.run_for_with(micros, |_| {})
+    }
+
+    /// `run_for()`, calling `before_tick` in every frame like `run_frame_with()`.
+    pub fn run_for_with(
+        &mut self,
+        micros: u64,
+        mut before_tick: impl FnMut(&Interpreter),
+    ) -> Result<FrameResult, ExecError> {
+        let mut result = FrameResult {
+            halted: self.exited,
+            ..FrameResult::default()
+        };
+
+        for _ in 0..self.frames_due(micros) {
+            result = result.then(self.run_frame_with(&mut before_tick)?);
+        }
+
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FrameResult;
+    use crate::interpreter::debugger::Debugger;
+    use crate::interpreter::quirks::Quirks;
+    use crate::interpreter::timing::Timing;
+    use crate::interpreter::Interpreter;
+
+    #[test]
+    fn test_run_frame() {
+        // LD V0, 5; LD ST, V0; CLS; JP 0x206
+        let mut interpreter = Interpreter::new();
+        interpreter
+            .load_rom(&[0x60, 0x05, 0xF0, 0x18, 0x00, 0xE0, 0x12, 0x06])
+            .unwrap();
+        interpreter.instructions_per_frame = 3;
+
+        let frame = interpreter.run_frame().unwrap();
+        assert_eq!(frame.frames, 1);
+        assert_eq!(frame.instructions, 3);
+        assert!(frame.drew && frame.beeping);
+        assert!(!frame.waiting_for_key && !frame.halted);
+        // Ticked once
+        assert_eq!(interpreter.stimer, 4);
+
+        let frame = interpreter.run_frame().unwrap();
+        assert!(!frame.drew);
+        assert_eq!(interpreter.pc, 0x206);
+        assert_eq!(interpreter.stimer, 3);
+
+        // The sound of a frame is there to hear before the tick, even for ST=1
+        interpreter.stimer = 1;
+        let mut heard = false;
+        let frame = interpreter
+            .run_frame_with(|interpreter| heard = interpreter.stimer > 0)
+            .unwrap();
+        assert!(heard && frame.beeping);
+        assert_eq!(interpreter.stimer, 0);
+        assert!(!interpreter.run_frame().unwrap().beeping);
+    }
+
+    #[test]
+    fn test_run_frame_stops() {
+        // LD V1, K; JP 0x202
+        let mut interpreter = Interpreter::new();
+        interpreter.load_rom(&[0xF1, 0x0A, 0x12, 0x02]).unwrap();
+        let frame = interpreter.run_frame().unwrap();
+        assert!(frame.waiting_for_key);
+        assert_eq!(interpreter.pc, 0x200);
+
+        interpreter.keypad.set_down(7);
+        let frame = interpreter.run_frame().unwrap();
+        assert!(!frame.waiting_for_key);
+        assert_eq!(interpreter.v[1], 7);
+
+        // EXIT, on SUPER-CHIP
+        let mut interpreter = Interpreter::new();
+        interpreter.variant = crate::interpreter::variant::Variant::SuperChip;
+        interpreter.load_rom(&[0x00, 0xFD]).unwrap();
+        let frame = interpreter.run_frame().unwrap();
+        assert_eq!(frame.instructions, 1);
+        assert!(frame.halted);
+
+        // An unknown opcode fails the frame before the timers tick
+        let mut interpreter = Interpreter::new();
+        interpreter.load_rom(&[0xFF, 0xFF]).unwrap();
+        interpreter.dtimer = 2;
+        assert!(interpreter.run_frame().is_err());
+        assert_eq!(interpreter.dtimer, 2);
+    }
+
+    #[test]
+    fn test_display_wait() {
+        // LD V0, 0; DRW V0, V0, 1; ADD V1, 1; JP 0x202
+        let rom = [0x60, 0x00, 0xD0, 0x01, 0x71, 0x01, 0x12, 0x02];
+        let mut interpreter = Interpreter::new();
+        interpreter.load_rom(&rom).unwrap();
+        interpreter.instructions_per_frame = 20;
+
+        // Drawing doesn't stop anything without the quirk
+        assert_eq!(interpreter.run_frame().unwrap().instructions, 20);
+
+        let mut interpreter = Interpreter::new();
+        interpreter.load_rom(&rom).unwrap();
+        interpreter.instructions_per_frame = 20;
+        interpreter.quirks.display_wait = true;
+
+        let frame = interpreter.run_frame().unwrap();
+        assert_eq!(frame.instructions, 2);
+        assert!(frame.drew);
+
+        // Once per frame from then on
+        let frame = interpreter.run_frame().unwrap();
+        assert_eq!(frame.instructions, 3);
+        assert_eq!(interpreter.v[1], 1);
+        assert_eq!(interpreter.pc, 0x204);
+    }
+
+    #[test]
+    fn test_vip_timing() {
+        // ADD V0, 1; JP 0x200
+        let mut interpreter = Interpreter::new();
+        interpreter.load_rom(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+        interpreter.timing = Timing::CosmacVip;
+
+        // 78 and 80 cycles, so the 47th one ends 44 cycles past the 3668 of a frame
+        let frame = interpreter.run_frame().unwrap();
+        assert_eq!(frame.instructions, 47);
+        assert_eq!(interpreter.v[0], 24);
+        assert_eq!(interpreter.cycles, 44);
+
+        // CLS takes most of a frame
+        let mut interpreter = Interpreter::new();
+        interpreter.load_rom(&[0x00, 0xE0, 0x12, 0x00]).unwrap();
+        interpreter.timing = Timing::CosmacVip;
+        assert_eq!(interpreter.run_frame().unwrap().instructions, 3);
+        assert_eq!(interpreter.run_frame().unwrap().instructions, 2);
+
+        // DRW V0, V0, 1 with the display wait ends the frame
+        let mut interpreter = Interpreter::new();
+        interpreter
+            .load_rom(&[0x60, 0x00, 0xD0, 0x01, 0x12, 0x02])
+            .unwrap();
+        interpreter.timing = Timing::CosmacVip;
+        interpreter.quirks = Quirks::COSMAC_VIP;
+        assert_eq!(interpreter.run_frame().unwrap().instructions, 2);
+        assert_eq!(interpreter.run_frame().unwrap().instructions, 2);
+        assert_eq!(interpreter.cycles, 0);
+
+        // Instructions run from the debugger don't count against the frames
+        let mut interpreter = Interpreter::new();
+        interpreter.load_rom(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+        interpreter.timing = Timing::CosmacVip;
+        let mut debugger = Debugger::new();
+        debugger.run_until_break(&mut interpreter, 100_000);
+        assert_eq!(interpreter.cycles, 0);
+        assert_eq!(interpreter.run_frame().unwrap().instructions, 47);
+    }
+
+    #[test]
+    fn test_run_for() {
+        // JP 0x200
+        let mut interpreter = Interpreter::new();
+        interpreter.load_rom(&[0x12, 0x00]).unwrap();
+        interpreter.dtimer = 100;
+
+        // Less than a frame runs nothing yet
+        assert_eq!(interpreter.run_for(10_000).unwrap().frames, 0);
+        assert_eq!(interpreter.dtimer, 100);
+
+        let frame = interpreter.run_for(10_000).unwrap();
+        assert_eq!(frame.frames, 1);
+        assert_eq!(frame.instructions, interpreter.instructions_per_frame);
+        assert_eq!(interpreter.dtimer, 99);
+
+        // 144 Hz for one second is still 60 frames, the fractions adding up
+        let frames: u32 = (0..144)
+            .map(|_| interpreter.run_for(6944).unwrap().frames)
+            .sum();
+        assert_eq!(frames, 60);
+
+        let mut interpreter = Interpreter::new();
+        interpreter.load_rom(&[0x12, 0x00]).unwrap();
+        let frames: u32 = (0..3).map(|_| interpreter.frames_due(1_000_000)).sum();
+        assert_eq!(frames, 180);
+
+        let drew = FrameResult {
+            frames: 1,
+            drew: true,
+            ..FrameResult::default()
+        };
+        let waiting = FrameResult {
+            frames: 1,
+            waiting_for_key: true,
+            ..FrameResult::default()
+        };
+        assert_eq!(drew.then(waiting).flags(), 0b0101);
+        assert_eq!(waiting.then(drew).flags(), 0b0001);
+        assert_eq!(drew.then(waiting).frames, 2);
+    }
+}
@@ -1,8 +1,28 @@
+pub mod debugger;
+pub mod error;
+mod frame;
 mod keypad;
+pub mod quirks;
+pub mod rewind;
+pub mod rng;
 pub mod screen;
-
+mod state;
+pub mod timing;
+pub mod trace;
+pub mod variant;
+pub mod watch;
+
+use self::error::{ExecError, LoadError};
+pub use self::frame::FrameResult;
 use self::keypad::Keypad;
+use self::quirks::{LoadStore, Quirks};
+use self::rng::Rng;
 use self::screen::{PixelState, Screen};
+use self::timing::Timing;
+use self::trace::Tracer;
+use self::variant::Variant;
+use self::watch::Watchpoints;
+use crate::disasm::{disassemble, Instruction};
 
 const FONTS_SPRITES: [u8; 80] = [
     0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
@@ -23,8 +43,31 @@ const FONTS_SPRITES: [u8; 80] = [
     0xF0, 0x80, 0xF0, 0x80, 0x80, // F
 ];
 
+// SUPER-CHIP 8x10 digits, loaded right after the small ones
+const BIG_FONTS_SPRITES: [u8; 160] = [
+    0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+    0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+    0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+    0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+];
+
+const BIG_FONTS_ADDRESS: usize = FONTS_SPRITES.len();
+
 pub struct Interpreter {
-    pub memory: [u8; 4096],
+    /// 4 KiB, or 64 KiB for XO-CHIP, resized by init() to match the variant.
+    pub memory: Vec<u8>,
     pub v: [u8; 16],
     pub i: u16,
     pub pc: u16,
@@ -34,12 +77,42 @@ pub struct Interpreter {
     pub stimer: u8,
     pub screen: Screen,
     pub keypad: Keypad,
+    pub rng: Rng,
+    pub quirks: Quirks,
+    pub variant: Variant,
+    /// SUPER-CHIP RPL user flags, kept across init() like the HP-48 kept them.
+    pub rpl: [u8; 16],
+    /// XO-CHIP 1-bit audio pattern loaded by F002, played back at a rate set by `pitch`.
+    pub audio_pattern: [u8; 16],
+    pub pitch: u8,
+    /// Not part of the machine, kept across init() and load_state().
+    pub watchpoints: Watchpoints,
+    /// Not part of the machine either, records the executed instructions when set.
+    pub tracer: Option<Tracer>,
+    /// Instructions run by run_frame() with the fixed timing, kept across init() and
+    /// load_state() like `timing`.
+    pub instructions_per_frame: usize,
+    pub timing: Timing,
+    // Machine cycles charged to the current frame with the COSMAC VIP timing
+    cycles: u32,
+    // Set while run_frame() runs instructions, the only ones charged to the frame
+    in_frame: bool,
+    // Time given to run_for() not run yet, in 1/60 µs
+    pending_time: u64,
+    // Set when the screen changes, cleared at the start of each frame
+    drew: bool,
+    // Set when FX0A finds no key pressed, cleared at the start of each frame
+    waiting_for_key: bool,
+    // Set by DXYN with the display wait quirk, cleared on the next tick
+    waiting_for_vblank: bool,
+    // Set by the SUPER-CHIP 00FD instruction
+    exited: bool,
 }
 
 impl Interpreter {
-    pub const fn new() -> Self {
+    pub fn new() -> Self {
         Self {
-            memory: [0; 4096],
+            memory: vec![0; Variant::Chip8.memory_size()],
             v: [0; 16],
             i: 0,
             pc: 0,
@@ -49,28 +122,86 @@ impl Interpreter {
             stimer: 0,
             screen: Screen::new(),
             keypad: Keypad::new(),
+            rng: Rng::new(Rng::DEFAULT_SEED),
+            quirks: Quirks::new(),
+            variant: Variant::Chip8,
+            rpl: [0; 16],
+            audio_pattern: [0; 16],
+            pitch: Interpreter::DEFAULT_PITCH,
+            watchpoints: Watchpoints::new(),
+            tracer: None,
+            instructions_per_frame: Interpreter::DEFAULT_INSTRUCTIONS_PER_FRAME,
+            timing: Timing::Fixed,
+            cycles: 0,
+            in_frame: false,
+            pending_time: 0,
+            drew: false,
+            waiting_for_key: false,
+            waiting_for_vblank: false,
+            exited: false,
         }
     }
 
+    /// Pitch for which an XO-CHIP audio pattern plays at 4000 bits per second.
+    pub const DEFAULT_PITCH: u8 = 64;
+    /// Where programs are loaded and start executing.
+    pub const PROGRAM_START: u16 = 0x200;
+    /// What the web view always ran, about 600 instructions per second.
+    pub const DEFAULT_INSTRUCTIONS_PER_FRAME: usize = 10;
+
     pub fn init(&mut self) {
+        self.memory.resize(self.variant.memory_size(), 0);
+
         // Load fonts in memory
-        for i in 0..80 {
-            self.memory[i] = FONTS_SPRITES[i];
-        }
+        self.memory[..FONTS_SPRITES.len()].copy_from_slice(&FONTS_SPRITES);
+        self.memory[BIG_FONTS_ADDRESS..BIG_FONTS_ADDRESS + BIG_FONTS_SPRITES.len()]
+            .copy_from_slice(&BIG_FONTS_SPRITES);
 
         self.v = [0; 16];
         self.i = 0;
         // The CHIP-8 program space goes from 0x200 to 0xFFF
-        self.pc = 0x200;
+        self.pc = Interpreter::PROGRAM_START;
         self.stack = [0; 16];
         self.sp = 0;
         self.dtimer = 0;
         self.stimer = 0;
         // TODO probably better to avoid a new() here
         self.screen = Screen::new();
+        self.audio_pattern = [0; 16];
+        self.pitch = Interpreter::DEFAULT_PITCH;
+        self.cycles = 0;
+        self.waiting_for_vblank = false;
+        self.exited = false;
+    }
+
+    /// Reset the interpreter for the current variant and copy `rom` at 0x200.
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), LoadError> {
+        let start = Interpreter::PROGRAM_START as usize;
+        let max = self.variant.memory_size() - start;
+
+        if rom.len() > max {
+            return Err(LoadError::RomTooLarge {
+                size: rom.len(),
+                max,
+            });
+        }
+
+        self.init();
+        // Don't leave pieces of a previous program behind the new one
+        self.memory[start..].fill(0);
+        self.memory[start..start + rom.len()].copy_from_slice(rom);
+
+        Ok(())
+    }
+
+    /// Whether the program stopped itself with the SUPER-CHIP exit instruction.
+    pub fn has_exited(&self) -> bool {
+        self.exited
     }
 
     pub fn tick(&mut self) {
+        self.waiting_for_vblank = false;
+
         if self.dtimer > 0 {
             self.dtimer -= 1;
         }
@@ -80,103 +211,240 @@ impl Interpreter {
         }
     }
 
-    pub fn cycle(&mut self) {
+    pub fn cycle(&mut self) -> Result<(), ExecError> {
+        if self.waiting_for_vblank || self.exited {
+            return Ok(());
+        }
+
+        self.check_memory(self.pc as usize, 2)?;
+        let pc = self.pc;
         let instruction = self.fetch();
-        self.decode(instruction);
+
+        if self.watchpoints.is_empty() && self.tracer.is_none() {
+            return self.decode(instruction);
+        }
+
+        self.watchpoints.begin(pc, instruction);
+        let before = self.watchpoints.register_values(self);
+        let traced = self.tracer.as_ref().map(|_| trace::registers(self));
+        let result = self.decode(instruction);
+        let after = self.watchpoints.register_values(self);
+        self.watchpoints.on_registers(&before, &after);
+
+        if let Some(traced) = traced {
+            let decoded = disassemble(instruction, self.variant);
+            let registers = trace::registers(self);
+            if let Some(tracer) = self.tracer.as_mut() {
+                tracer.record(pc, instruction, decoded, &traced, &registers);
+            }
+        }
+
+        result
     }
 
     pub fn fetch(&mut self) -> u16 {
         let pc = self.pc as usize;
-        let instruction: u16 = (self.memory[pc] as u16) << 8 | (self.memory[(pc + 1)] as u16);
-        self.pc += 2;
+        let instruction: u16 = (self.memory[pc] as u16) << 8 | (self.memory[pc + 1] as u16);
+        self.pc = self.pc.wrapping_add(2);
 
         instruction
     }
 
-    pub fn decode(&mut self, instruction: u16) {
-        let n1 = ((instruction & 0xF000) >> 12) as u8;
-        let n2 = ((instruction & 0x0F00) >> 8) as u8;
-        let n3 = ((instruction & 0x00F0) >> 4) as u8;
-        let n4 = (instruction & 0x000F) as u8;
-
-        let nnn = instruction & 0x0FFF;
-        let kk = (instruction & 0x00FF) as u8;
-
-        let (x, y, n) = (n2 as usize, n3 as usize, n4);
-
-        match (n1, n2, n3, n4) {
-            (0x00, 0x00, 0x0e, 0x00) => self.execute_cls(),
-            (0x00, 0x00, 0x0e, 0x0e) => self.execute_ret(),
-            (0x01, _, _, _) => self.execute_jp_nnn(nnn),
-            (0x02, _, _, _) => self.execute_call_nnn(nnn),
-            (0x03, _, _, _) => self.execute_se_vx_kk(x, kk),
-            (0x04, _, _, _) => self.execute_sne_vx_kk(x, kk),
-            (0x05, _, _, 0x00) => self.execute_se_vx_vy(x, y),
-            (0x06, _, _, _) => self.execute_ld_vx_kk(x, kk),
-            (0x07, _, _, _) => self.execute_add_vx_kk(x, kk),
-            (0x08, _, _, 0x00) => self.execute_ld_vx_vy(x, y),
-            (0x08, _, _, 0x01) => self.execute_or_vx_vy(x, y),
-            (0x08, _, _, 0x02) => self.execute_and_vx_vy(x, y),
-            (0x08, _, _, 0x03) => self.execute_xor_vx_vy(x, y),
-            (0x08, _, _, 0x04) => self.execute_add_vx_vy(x, y),
-            (0x08, _, _, 0x05) => self.execute_sub_vx_vy(x, y),
-            (0x08, _, _, 0x06) => self.execute_shr_vx_vy(x),
-            (0x08, _, _, 0x07) => self.execute_subn_vx_vy(x, y),
-            (0x08, _, _, 0x0E) => self.execute_shl_vx_vy(x),
-            (0x09, _, _, 0x00) => self.execute_sne_vx_vy(x, y),
-            (0x0A, _, _, _) => self.execute_ld_i_nnn(nnn),
-            (0x0B, _, _, _) => self.execute_jp_v0_nnn(nnn),
-            (0x0C, _, _, _) => self.execute_rnd_vx_kk(x, kk),
-            (0x0D, _, _, _) => self.execute_drw_vx_vy_n(x, y, n),
-            (0x0E, _, 0x09, 0x0E) => self.execute_skp_vx(x),
-            (0x0E, _, 0x0A, 0x01) => self.execute_skpn_vx(x),
-            (0x0F, _, 0x00, 0x07) => self.execute_ld_vx_dt(x),
-            (0x0F, _, 0x00, 0x0A) => self.execute_ld_vx_k(x),
-            (0x0F, _, 0x01, 0x05) => self.execute_ld_dt_vx(x),
-            (0x0F, _, 0x01, 0x08) => self.execute_ld_st_vx(x),
-            (0x0F, _, 0x01, 0x0E) => self.execute_add_i_vx(x),
-            (0x0F, _, 0x02, 0x09) => self.execute_ld_f_vx(x),
-            (0x0F, _, 0x03, 0x03) => self.execute_ld_b_vx(x),
-            (0x0F, _, 0x05, 0x05) => self.execute_ld_i_vx(x),
-            (0x0F, _, 0x06, 0x05) => self.execute_ld_vx_i(x),
-            _ => (),
+    pub fn decode(&mut self, instruction: u16) -> Result<(), ExecError> {
+        let decoded = disassemble(instruction, self.variant);
+
+        if self.in_frame && self.timing == Timing::CosmacVip {
+            self.cycles = self
+                .cycles
+                .saturating_add(timing::vip_cycles(decoded, self));
+        }
+
+        match decoded {
+            Instruction::Scd(n) => self.execute_scd_n(n),
+            Instruction::Scu(n) => self.execute_scu_n(n),
+            Instruction::Cls => self.execute_cls(),
+            Instruction::Ret => self.execute_ret()?,
+            Instruction::Scr => self.execute_scr(),
+            Instruction::Scl => self.execute_scl(),
+            Instruction::Exit => self.execute_exit(),
+            Instruction::Low => self.execute_low(),
+            Instruction::High => self.execute_high(),
+            Instruction::Jp(nnn) => self.execute_jp_nnn(nnn),
+            Instruction::Call(nnn) => self.execute_call_nnn(nnn)?,
+            Instruction::SeVxKk(x, kk) => self.execute_se_vx_kk(x as usize, kk),
+            Instruction::SneVxKk(x, kk) => self.execute_sne_vx_kk(x as usize, kk),
+            Instruction::SeVxVy(x, y) => self.execute_se_vx_vy(x as usize, y as usize),
+            Instruction::LdIVxVy(x, y) => self.execute_ld_i_vx_vy(x as usize, y as usize)?,
+            Instruction::LdVxVyI(x, y) => self.execute_ld_vx_vy_i(x as usize, y as usize)?,
+            Instruction::LdVxKk(x, kk) => self.execute_ld_vx_kk(x as usize, kk),
+            Instruction::AddVxKk(x, kk) => self.execute_add_vx_kk(x as usize, kk),
+            Instruction::LdVxVy(x, y) => self.execute_ld_vx_vy(x as usize, y as usize),
+            Instruction::OrVxVy(x, y) => self.execute_or_vx_vy(x as usize, y as usize),
+            Instruction::AndVxVy(x, y) => self.execute_and_vx_vy(x as usize, y as usize),
+            Instruction::XorVxVy(x, y) => self.execute_xor_vx_vy(x as usize, y as usize),
+            Instruction::AddVxVy(x, y) => self.execute_add_vx_vy(x as usize, y as usize),
+            Instruction::SubVxVy(x, y) => self.execute_sub_vx_vy(x as usize, y as usize),
+            Instruction::ShrVxVy(x, y) => self.execute_shr_vx_vy(x as usize, y as usize),
+            Instruction::SubnVxVy(x, y) => self.execute_subn_vx_vy(x as usize, y as usize),
+            Instruction::ShlVxVy(x, y) => self.execute_shl_vx_vy(x as usize, y as usize),
+            Instruction::SneVxVy(x, y) => self.execute_sne_vx_vy(x as usize, y as usize),
+            Instruction::LdINnn(nnn) => self.execute_ld_i_nnn(nnn),
+            Instruction::JpV0Nnn(nnn) => self.execute_jp_v0_nnn((nnn >> 8) as usize, nnn),
+            Instruction::RndVxKk(x, kk) => self.execute_rnd_vx_kk(x as usize, kk),
+            Instruction::DrwVxVyN(x, y, n) => {
+                self.execute_drw_vx_vy_n(x as usize, y as usize, n)?
+            }
+            Instruction::SkpVx(x) => self.execute_skp_vx(x as usize),
+            Instruction::SknpVx(x) => self.execute_skpn_vx(x as usize),
+            Instruction::LdILong => self.execute_ld_i_long()?,
+            Instruction::Plane(n) => self.execute_plane_n(n),
+            Instruction::Audio => self.execute_audio()?,
+            Instruction::LdVxDt(x) => self.execute_ld_vx_dt(x as usize),
+            Instruction::LdVxK(x) => self.execute_ld_vx_k(x as usize),
+            Instruction::LdDtVx(x) => self.execute_ld_dt_vx(x as usize),
+            Instruction::LdStVx(x) => self.execute_ld_st_vx(x as usize),
+            Instruction::AddIVx(x) => self.execute_add_i_vx(x as usize),
+            Instruction::LdFVx(x) => self.execute_ld_f_vx(x as usize),
+            Instruction::LdHfVx(x) => self.execute_ld_hf_vx(x as usize),
+            Instruction::LdBVx(x) => self.execute_ld_b_vx(x as usize)?,
+            Instruction::PitchVx(x) => self.execute_pitch_vx(x as usize),
+            Instruction::LdIVx(x) => self.execute_ld_i_vx(x as usize)?,
+            Instruction::LdVxI(x) => self.execute_ld_vx_i(x as usize)?,
+            Instruction::LdRVx(x) => self.execute_ld_r_vx(x as usize),
+            Instruction::LdVxR(x) => self.execute_ld_vx_r(x as usize),
+            Instruction::Unknown(opcode) => {
+                return Err(ExecError::UnknownOpcode {
+                    pc: self.pc.wrapping_sub(2),
+                    opcode,
+                })
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Read memory on behalf of an instruction, where the watchpoints can see it.
+    fn load(&mut self, address: usize) -> u8 {
+        let value = self.memory[address];
+
+        if !self.watchpoints.is_empty() {
+            self.watchpoints.on_read(address, value);
+        }
+
+        value
+    }
+
+    /// Write memory on behalf of an instruction, where the watchpoints can see it.
+    fn store(&mut self, address: usize, value: u8) {
+        if !self.watchpoints.is_empty() {
+            self.watchpoints
+                .on_write(address, self.memory[address], value);
+        }
+
+        self.memory[address] = value;
+    }
+
+    /// Make sure `len` bytes starting at `addr` are all inside the memory.
+    fn check_memory(&self, addr: usize, len: usize) -> Result<(), ExecError> {
+        if addr + len > self.memory.len() {
+            return Err(ExecError::MemoryOutOfBounds {
+                addr: addr.max(self.memory.len()),
+            });
         }
+
+        Ok(())
+    }
+
+    fn execute_scd_n(&mut self, n: u8) {
+        self.screen.scroll_down(n as usize);
+        self.drew = true;
+    }
+
+    fn execute_scu_n(&mut self, n: u8) {
+        self.screen.scroll_up(n as usize);
+        self.drew = true;
     }
 
     fn execute_cls(&mut self) {
         self.screen.clear();
+        self.drew = true;
+    }
+
+    fn execute_scr(&mut self) {
+        self.screen.scroll_right(4);
+        self.drew = true;
+    }
+
+    fn execute_scl(&mut self) {
+        self.screen.scroll_left(4);
+        self.drew = true;
     }
 
-    fn execute_ret(&mut self) {
+    fn execute_exit(&mut self) {
+        self.exited = true;
+    }
+
+    fn execute_low(&mut self) {
+        self.screen.set_hires(false);
+        self.drew = true;
+    }
+
+    fn execute_high(&mut self) {
+        self.screen.set_hires(true);
+        self.drew = true;
+    }
+
+    fn execute_ret(&mut self) -> Result<(), ExecError> {
+        if self.sp == 0 {
+            return Err(ExecError::StackUnderflow);
+        }
+
         self.sp -= 1;
         self.pc = self.stack[self.sp as usize];
+
+        Ok(())
     }
 
     fn execute_jp_nnn(&mut self, nnn: u16) {
         self.pc = nnn;
     }
 
-    fn execute_call_nnn(&mut self, nnn: u16) {
+    fn execute_call_nnn(&mut self, nnn: u16) -> Result<(), ExecError> {
+        if self.sp as usize >= self.stack.len() {
+            return Err(ExecError::StackOverflow);
+        }
+
         self.stack[self.sp as usize] = self.pc;
         self.sp += 1;
         self.pc = nnn;
+
+        Ok(())
+    }
+
+    /// Skip the next instruction, which is four bytes long for the XO-CHIP F000 NNNN.
+    fn skip(&mut self) {
+        let pc = self.pc as usize;
+        let long = self.variant == Variant::XoChip
+            && self.memory.get(pc..pc + 2) == Some(&[0xF0, 0x00][..]);
+
+        self.pc = self.pc.wrapping_add(if long { 4 } else { 2 });
     }
 
     fn execute_se_vx_kk(&mut self, x: usize, kk: u8) {
         if self.v[x] == kk {
-            self.pc += 2;
+            self.skip();
         }
     }
 
     fn execute_sne_vx_kk(&mut self, x: usize, kk: u8) {
         if self.v[x] != kk {
-            self.pc += 2;
+            self.skip();
         }
     }
 
     fn execute_sne_vx_vy(&mut self, x: usize, y: usize) {
         if self.v[x] != self.v[y] {
-            self.pc += 2;
+            self.skip();
         }
     }
 
@@ -185,8 +453,39 @@ impl Interpreter {
         let vy = self.v[y];
 
         if vx == vy {
-            self.pc += 2;
+            self.skip();
+        }
+    }
+
+    /// The range of registers used by 5XY2/5XY3, walked backwards when X > Y.
+    fn register_range(x: usize, y: usize) -> Vec<usize> {
+        if x <= y {
+            (x..=y).collect()
+        } else {
+            (y..=x).rev().collect()
+        }
+    }
+
+    fn execute_ld_i_vx_vy(&mut self, x: usize, y: usize) -> Result<(), ExecError> {
+        let registers = Interpreter::register_range(x, y);
+        self.check_memory(self.i as usize, registers.len())?;
+
+        for (offset, register) in registers.into_iter().enumerate() {
+            self.store(self.i as usize + offset, self.v[register]);
+        }
+
+        Ok(())
+    }
+
+    fn execute_ld_vx_vy_i(&mut self, x: usize, y: usize) -> Result<(), ExecError> {
+        let registers = Interpreter::register_range(x, y);
+        self.check_memory(self.i as usize, registers.len())?;
+
+        for (offset, register) in registers.into_iter().enumerate() {
+            self.v[register] = self.load(self.i as usize + offset);
         }
+
+        Ok(())
     }
 
     fn execute_ld_vx_kk(&mut self, x: usize, kk: u8) {
@@ -194,13 +493,13 @@ impl Interpreter {
     }
 
     fn execute_add_vx_kk(&mut self, x: usize, kk: u8) {
-        self.v[x] += kk;
+        self.v[x] = self.v[x].wrapping_add(kk);
     }
 
     fn execute_add_vx_vy(&mut self, x: usize, y: usize) {
         let (result, overflow) = self.v[y].overflowing_add(self.v[x]);
-        self.v[15] = if overflow { 1 } else { 0 };
         self.v[x] = result;
+        self.v[15] = if overflow { 1 } else { 0 };
     }
 
     fn execute_ld_vx_vy(&mut self, x: usize, y: usize) {
@@ -209,67 +508,144 @@ impl Interpreter {
 
     fn execute_or_vx_vy(&mut self, x: usize, y: usize) {
         self.v[x] |= self.v[y];
+        self.reset_vf();
     }
 
     fn execute_and_vx_vy(&mut self, x: usize, y: usize) {
         self.v[x] &= self.v[y];
+        self.reset_vf();
     }
 
     fn execute_xor_vx_vy(&mut self, x: usize, y: usize) {
         self.v[x] ^= self.v[y];
+        self.reset_vf();
+    }
+
+    fn reset_vf(&mut self) {
+        if self.quirks.vf_reset {
+            self.v[15] = 0;
+        }
     }
 
     fn execute_sub_vx_vy(&mut self, x: usize, y: usize) {
-        self.v[15] = if self.v[x] > self.v[y] { 1 } else { 0 };
+        let no_borrow = self.v[x] >= self.v[y];
         self.v[x] = self.v[x].wrapping_sub(self.v[y]);
+        self.v[15] = no_borrow as u8;
     }
 
     fn execute_subn_vx_vy(&mut self, x: usize, y: usize) {
-        self.v[15] = if self.v[y] > self.v[x] { 1 } else { 0 };
+        let no_borrow = self.v[y] >= self.v[x];
         self.v[x] = self.v[y].wrapping_sub(self.v[x]);
+        self.v[15] = no_borrow as u8;
     }
 
-    fn execute_shr_vx_vy(&mut self, x: usize) {
-        self.v[15] = self.v[x] & 0x1;
+    fn execute_shr_vx_vy(&mut self, x: usize, y: usize) {
+        if !self.quirks.shift {
+            self.v[x] = self.v[y];
+        }
+
+        let flag = self.v[x] & 0x1;
         self.v[x] >>= 1;
+        self.v[15] = flag;
     }
 
-    fn execute_shl_vx_vy(&mut self, x: usize) {
-        self.v[15] = (self.v[x] & 0x80) >> 7;
+    fn execute_shl_vx_vy(&mut self, x: usize, y: usize) {
+        if !self.quirks.shift {
+            self.v[x] = self.v[y];
+        }
+
+        let flag = (self.v[x] & 0x80) >> 7;
         self.v[x] <<= 1;
+        self.v[15] = flag;
     }
 
     fn execute_ld_i_nnn(&mut self, nnn: u16) {
         self.i = nnn;
     }
 
-    fn execute_jp_v0_nnn(&mut self, nnn: u16) {
-        self.pc = nnn + self.v[0] as u16;
+    fn execute_jp_v0_nnn(&mut self, x: usize, nnn: u16) {
+        let offset = if self.quirks.jump {
+            self.v[x]
+        } else {
+            self.v[0]
+        };
+        self.pc = nnn + offset as u16;
     }
 
     fn execute_rnd_vx_kk(&mut self, x: usize, kk: u8) {
-        // I don't want to use #[wasm_bindgen] everywhere in my code, so
-        let rand = 69;
-        self.v[x] = rand & kk;
+        self.v[x] = self.rng.next_u8() & kk;
     }
 
-    fn execute_drw_vx_vy_n(&mut self, x: usize, y: usize, n: u8) {
+    fn execute_drw_vx_vy_n(&mut self, x: usize, y: usize, n: u8) -> Result<(), ExecError> {
+        // SUPER-CHIP draws a 16x16 sprite (two bytes per row) for DXY0
+        let (height, row_bytes) = match n {
+            0 if self.variant != Variant::Chip8 => (16, 2),
+            _ => (n as usize, 1),
+        };
+        let sprite_size = height * row_bytes;
+        // With both XO-CHIP planes selected, the second plane sprite follows the first one
+        let planes = self.screen.planes();
+
+        self.check_memory(self.i as usize, sprite_size * planes.count_ones() as usize)?;
         self.v[15] = 0;
-        let rows: &[u8] = &self.memory[self.i as usize..(self.i + n as u16) as usize];
 
-        for row_index in 0..n {
-            let row = rows[row_index as usize];
+        let mut address = self.i as usize;
+
+        for plane in [1, 2] {
+            if planes & plane == 0 {
+                continue;
+            }
+
+            self.draw_sprite(plane, address, (x, y), (height, row_bytes));
+            address += sprite_size;
+        }
+        self.drew = true;
+
+        if self.quirks.display_wait {
+            self.waiting_for_vblank = true;
+        }
+
+        Ok(())
+    }
+
+    fn draw_sprite(
+        &mut self,
+        plane: u8,
+        address: usize,
+        (x, y): (usize, usize),
+        (height, row_bytes): (usize, usize),
+    ) {
+        let (width, screen_height) = (self.screen.width(), self.screen.height());
+        // The starting position always wraps, the sprite itself is clipped with the quirk
+        let start_x = self.v[x] as usize % width;
+        let start_y = self.v[y] as usize % screen_height;
+
+        for row_index in 0..height {
+            let new_y = start_y + row_index;
+
+            if self.quirks.clipping && new_y >= screen_height {
+                break;
+            }
+
+            for row_position in 0..row_bytes * 8 {
+                let row = self.load(address + row_index * row_bytes + row_position / 8);
 
-            for row_position in 0..8 {
-                if row >> (7 - row_position) & 0x01 == 1 {
-                    let new_x = ((self.v[x] + row_position) % Screen::WIDTH as u8) as usize;
-                    let new_y = ((self.v[y] + row_index) % Screen::HEIGHT as u8) as usize;
-                    let current_pixel_state = self.screen.get_pixel_state((new_x, new_y));
+                if row >> (7 - row_position % 8) & 0x01 == 1 {
+                    let new_x = start_x + row_position;
+
+                    if self.quirks.clipping && new_x >= width {
+                        continue;
+                    }
+
+                    let (new_x, new_y) = (new_x % width, new_y % screen_height);
+                    let current_pixel_state = self.screen.get_pixel_state((new_x, new_y), plane);
                     if let PixelState::On = current_pixel_state {
                         self.v[15] = 1;
-                        self.screen.update_pixel((new_x, new_y), PixelState::Off);
+                        self.screen
+                            .update_pixel((new_x, new_y), plane, PixelState::Off);
                     } else {
-                        self.screen.update_pixel((new_x, new_y), PixelState::On);
+                        self.screen
+                            .update_pixel((new_x, new_y), plane, PixelState::On);
                     }
                 }
             }
@@ -277,15 +653,40 @@ impl Interpreter {
     }
 
     fn execute_skp_vx(&mut self, x: usize) {
-        if self.keypad.is_pressed(self.v[x] as usize) {
-            self.pc += 2;
+        if self.keypad.is_pressed((self.v[x] & 0xF) as usize) {
+            self.skip();
         }
     }
 
     fn execute_skpn_vx(&mut self, x: usize) {
-        if !self.keypad.is_pressed(self.v[x] as usize) {
-            self.pc += 2;
+        if !self.keypad.is_pressed((self.v[x] & 0xF) as usize) {
+            self.skip();
+        }
+    }
+
+    fn execute_ld_i_long(&mut self) -> Result<(), ExecError> {
+        let pc = self.pc as usize;
+        self.check_memory(pc, 2)?;
+
+        self.i = (self.memory[pc] as u16) << 8 | self.memory[pc + 1] as u16;
+        self.pc = self.pc.wrapping_add(2);
+
+        Ok(())
+    }
+
+    fn execute_plane_n(&mut self, n: u8) {
+        self.screen.select_planes(n);
+    }
+
+    fn execute_audio(&mut self) -> Result<(), ExecError> {
+        let (i, len) = (self.i as usize, self.audio_pattern.len());
+        self.check_memory(i, len)?;
+
+        for offset in 0..len {
+            self.audio_pattern[offset] = self.load(i + offset);
         }
+
+        Ok(())
     }
 
     fn execute_ld_vx_dt(&mut self, x: usize) {
@@ -295,7 +696,10 @@ impl Interpreter {
     fn execute_ld_vx_k(&mut self, x: usize) {
         match self.keypad.get_key_pressed() {
             Some(i) => self.v[x] = i,
-            None => self.pc -= 2,
+            None => {
+                self.pc = self.pc.wrapping_sub(2);
+                self.waiting_for_key = true;
+            }
         }
     }
 
@@ -308,34 +712,84 @@ impl Interpreter {
     }
 
     fn execute_add_i_vx(&mut self, x: usize) {
-        self.i += self.v[x] as u16;
+        self.i = self.i.wrapping_add(self.v[x] as u16);
     }
 
     fn execute_ld_f_vx(&mut self, x: usize) {
         self.i = (self.v[x] as u16) * 5;
     }
 
-    fn execute_ld_b_vx(&mut self, x: usize) {
-        self.memory[self.i as usize] = self.v[x] / 100;
-        self.memory[(self.i + 1) as usize] = self.v[x] % 100 / 10;
-        self.memory[(self.i + 2) as usize] = self.v[x] % 10;
+    fn execute_ld_hf_vx(&mut self, x: usize) {
+        self.i = (BIG_FONTS_ADDRESS + (self.v[x] & 0xF) as usize * 10) as u16;
+    }
+
+    fn execute_ld_b_vx(&mut self, x: usize) -> Result<(), ExecError> {
+        let i = self.i as usize;
+        self.check_memory(i, 3)?;
+
+        self.store(i, self.v[x] / 100);
+        self.store(i + 1, self.v[x] % 100 / 10);
+        self.store(i + 2, self.v[x] % 10);
+
+        Ok(())
     }
 
-    fn execute_ld_i_vx(&mut self, x: usize) {
-        for index in 0..=x {
-            self.memory[self.i as usize + index] = self.v[index];
+    fn execute_ld_i_vx(&mut self, x: usize) -> Result<(), ExecError> {
+        let i = self.i as usize;
+        self.check_memory(i, x + 1)?;
+
+        for register in 0..=x {
+            self.store(i + register, self.v[register]);
         }
+        self.increment_i_after_load_store(x);
+
+        Ok(())
+    }
+
+    fn execute_ld_vx_i(&mut self, x: usize) -> Result<(), ExecError> {
+        let i = self.i as usize;
+        self.check_memory(i, x + 1)?;
+
+        for register in 0..=x {
+            self.v[register] = self.load(i + register);
+        }
+        self.increment_i_after_load_store(x);
+
+        Ok(())
+    }
+
+    fn execute_pitch_vx(&mut self, x: usize) {
+        self.pitch = self.v[x];
+    }
+
+    fn execute_ld_r_vx(&mut self, x: usize) {
+        self.rpl[..=x].copy_from_slice(&self.v[..=x]);
+    }
+
+    fn execute_ld_vx_r(&mut self, x: usize) {
+        self.v[..=x].copy_from_slice(&self.rpl[..=x]);
     }
 
-    fn execute_ld_vx_i(&mut self, x: usize) {
-        for index in 0..=x {
-            self.v[index] = self.memory[self.i as usize + index];
+    fn increment_i_after_load_store(&mut self, x: usize) {
+        match self.quirks.load_store {
+            LoadStore::Unchanged => (),
+            LoadStore::IncrementByX => self.i = self.i.wrapping_add(x as u16),
+            LoadStore::IncrementByXPlusOne => self.i = self.i.wrapping_add(x as u16 + 1),
         }
     }
 }
 
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use super::error::{ExecError, LoadError};
+    use super::quirks::Quirks;
+    use super::variant::Variant;
     use super::Interpreter;
 
     #[test]
@@ -343,7 +797,7 @@ mod tests {
         let mut interpreter = Interpreter::new();
         interpreter.sp = 12;
         interpreter.stack[11] = 0x4444;
-        interpreter.decode(0x00EE);
+        interpreter.decode(0x00EE).unwrap();
 
         assert_eq!(interpreter.pc, 0x4444);
         assert_eq!(interpreter.sp, 11);
@@ -352,7 +806,7 @@ mod tests {
     #[test]
     fn test_jp_nnn() {
         let mut interpreter = Interpreter::new();
-        interpreter.decode(0x16FF);
+        interpreter.decode(0x16FF).unwrap();
 
         assert_eq!(interpreter.pc, 0x6FF);
     }
@@ -363,7 +817,7 @@ mod tests {
         interpreter.sp = 11;
         interpreter.stack[12] = 0x4444;
         interpreter.pc = 0x66;
-        interpreter.decode(0x2AAA);
+        interpreter.decode(0x2AAA).unwrap();
 
         assert_eq!(interpreter.pc, 0x0AAA);
         assert_eq!(interpreter.sp, 12);
@@ -377,11 +831,11 @@ mod tests {
         interpreter.v[0] = 0xAA;
 
         interpreter.fetch();
-        interpreter.decode(0x30AA);
+        interpreter.decode(0x30AA).unwrap();
         assert_eq!(interpreter.pc, 6);
 
         interpreter.fetch();
-        interpreter.decode(0x30AB);
+        interpreter.decode(0x30AB).unwrap();
         assert_eq!(interpreter.pc, 8);
     }
 
@@ -392,11 +846,11 @@ mod tests {
         interpreter.v[0] = 0xAA;
 
         interpreter.fetch();
-        interpreter.decode(0x40AA);
+        interpreter.decode(0x40AA).unwrap();
         assert_eq!(interpreter.pc, 4);
 
         interpreter.fetch();
-        interpreter.decode(0x40AB);
+        interpreter.decode(0x40AB).unwrap();
         assert_eq!(interpreter.pc, 8);
     }
 
@@ -407,10 +861,10 @@ mod tests {
         interpreter.v[0] = 0xAA;
         interpreter.v[1] = 0xAA;
 
-        interpreter.decode(0x9010);
+        interpreter.decode(0x9010).unwrap();
         assert_eq!(interpreter.pc, 2);
 
-        interpreter.decode(0x9020);
+        interpreter.decode(0x9020).unwrap();
         assert_eq!(interpreter.pc, 4);
     }
 
@@ -423,18 +877,18 @@ mod tests {
         interpreter.v[2] = 0x77;
 
         interpreter.fetch();
-        interpreter.decode(0x5010);
+        interpreter.decode(0x5010).unwrap();
         assert_eq!(interpreter.pc, 6);
 
         interpreter.fetch();
-        interpreter.decode(0x5020);
+        interpreter.decode(0x5020).unwrap();
         assert_eq!(interpreter.pc, 8);
     }
 
     #[test]
     fn test_ld_vx_kk() {
         let mut interpreter = Interpreter::new();
-        interpreter.decode(0x61AA);
+        interpreter.decode(0x61AA).unwrap();
 
         assert_eq!(interpreter.v[1], 0xAA);
     }
@@ -443,7 +897,7 @@ mod tests {
     fn test_add_vx_kk() {
         let mut interpreter = Interpreter::new();
         interpreter.v[3] = 2;
-        interpreter.decode(0x73AA);
+        interpreter.decode(0x73AA).unwrap();
 
         assert_eq!(interpreter.v[3], 0xAA + 2);
     }
@@ -453,7 +907,7 @@ mod tests {
         let mut interpreter = Interpreter::new();
         interpreter.v[3] = 2;
         interpreter.v[5] = 5;
-        interpreter.decode(0x8350);
+        interpreter.decode(0x8350).unwrap();
 
         assert_eq!(interpreter.v[3], 5);
     }
@@ -464,7 +918,7 @@ mod tests {
         interpreter.v[1] = 0x0B;
         interpreter.v[2] = 0x03;
 
-        interpreter.decode(0x8121);
+        interpreter.decode(0x8121).unwrap();
         assert_eq!(interpreter.v[1], 11);
     }
 
@@ -474,7 +928,7 @@ mod tests {
         interpreter.v[1] = 0x0B;
         interpreter.v[2] = 0x03;
 
-        interpreter.decode(0x8122);
+        interpreter.decode(0x8122).unwrap();
         assert_eq!(interpreter.v[1], 3);
     }
 
@@ -484,7 +938,7 @@ mod tests {
         interpreter.v[1] = 0x0B;
         interpreter.v[2] = 0x03;
 
-        interpreter.decode(0x8123);
+        interpreter.decode(0x8123).unwrap();
         assert_eq!(interpreter.v[1], 8);
     }
 
@@ -494,16 +948,16 @@ mod tests {
 
         interpreter.v[1] = 0xF;
         interpreter.v[2] = 0x3;
-        interpreter.decode(0x8124);
+        interpreter.decode(0x8124).unwrap();
 
-        let result_without_overflow = (0xF as u16 + 0x3 as u16) as u8;
+        let result_without_overflow = (0xF_u16 + 0x3_u16) as u8;
 
         assert_eq!(interpreter.v[1], result_without_overflow);
         assert_eq!(interpreter.v[15], 0);
 
         interpreter.v[1] = 0xFF;
         interpreter.v[2] = 0x03;
-        interpreter.decode(0x8124);
+        interpreter.decode(0x8124).unwrap();
 
         let result_with_overflow = (0xFF + 0x03) as u8;
 
@@ -517,14 +971,14 @@ mod tests {
 
         interpreter.v[1] = 0xF;
         interpreter.v[2] = 0x3;
-        interpreter.decode(0x8125);
+        interpreter.decode(0x8125).unwrap();
 
         assert_eq!(interpreter.v[1], 0x0C);
         assert_eq!(interpreter.v[15], 1);
 
         interpreter.v[1] = 0x14;
         interpreter.v[2] = 0xFF;
-        interpreter.decode(0x8125);
+        interpreter.decode(0x8125).unwrap();
 
         assert_eq!(interpreter.v[1], 0x15);
         assert_eq!(interpreter.v[15], 0);
@@ -536,31 +990,52 @@ mod tests {
 
         interpreter.v[1] = 0xF;
         interpreter.v[2] = 0x3;
-        interpreter.decode(0x8127);
+        interpreter.decode(0x8127).unwrap();
 
         assert_eq!(interpreter.v[1], 0xF4);
         assert_eq!(interpreter.v[15], 0);
 
         interpreter.v[1] = 0x0E;
         interpreter.v[2] = 0xFF;
-        interpreter.decode(0x8127);
+        interpreter.decode(0x8127).unwrap();
 
         assert_eq!(interpreter.v[1], 0xF1);
         assert_eq!(interpreter.v[15], 1);
     }
 
+    #[test]
+    fn test_flag_written_after_result() {
+        let mut interpreter = Interpreter::new();
+
+        // Equal values don't borrow
+        interpreter.v[1] = 0x20;
+        interpreter.v[2] = 0x20;
+        interpreter.decode(0x8125).unwrap();
+        assert_eq!(interpreter.v[15], 1);
+
+        // With VF as the destination, the flag wins over the result
+        interpreter.v[15] = 0x10;
+        interpreter.v[1] = 0x20;
+        interpreter.decode(0x8F15).unwrap();
+        assert_eq!(interpreter.v[15], 0);
+
+        interpreter.v[15] = 0xFF;
+        interpreter.decode(0x8F14).unwrap();
+        assert_eq!(interpreter.v[15], 1);
+    }
+
     #[test]
     fn test_shr_vx_vy() {
         let mut interpreter = Interpreter::new();
 
         interpreter.v[1] = 0x0E;
-        interpreter.decode(0x8126);
+        interpreter.decode(0x8126).unwrap();
 
         assert_eq!(interpreter.v[15], 0);
         assert_eq!(interpreter.v[1], 0x07);
 
         interpreter.v[1] = 0x0F;
-        interpreter.decode(0x8126);
+        interpreter.decode(0x8126).unwrap();
 
         assert_eq!(interpreter.v[15], 1);
         assert_eq!(interpreter.v[1], 0x07);
@@ -571,13 +1046,13 @@ mod tests {
         let mut interpreter = Interpreter::new();
 
         interpreter.v[1] = 0b01110000;
-        interpreter.decode(0x812E);
+        interpreter.decode(0x812E).unwrap();
 
         assert_eq!(interpreter.v[15], 0);
         assert_eq!(interpreter.v[1], 0b11100000);
 
         interpreter.v[1] = 0b11000000;
-        interpreter.decode(0x812E);
+        interpreter.decode(0x812E).unwrap();
 
         assert_eq!(interpreter.v[15], 1);
         assert_eq!(interpreter.v[1], 0b10000000);
@@ -587,7 +1062,7 @@ mod tests {
     fn test_ld_i_nnn() {
         let mut interpreter = Interpreter::new();
 
-        interpreter.decode(0xA123);
+        interpreter.decode(0xA123).unwrap();
 
         assert_eq!(interpreter.i, 0x123);
     }
@@ -597,11 +1072,28 @@ mod tests {
         let mut interpreter = Interpreter::new();
         interpreter.v[0] = 0x04;
 
-        interpreter.decode(0xB130);
+        interpreter.decode(0xB130).unwrap();
 
         assert_eq!(interpreter.pc, 0x134);
     }
 
+    #[test]
+    fn test_rnd_vx_kk() {
+        let mut a = Interpreter::new();
+        let mut b = Interpreter::new();
+        a.rng.set_seed(1234);
+        b.rng.set_seed(1234);
+
+        for _ in 0..16 {
+            a.decode(0xC1FF).unwrap();
+            b.decode(0xC1FF).unwrap();
+            assert_eq!(a.v[1], b.v[1]);
+        }
+
+        a.decode(0xC20F).unwrap();
+        assert_eq!(a.v[2] & 0xF0, 0);
+    }
+
     #[test]
     fn test_skp_vx() {
         let mut interpreter = Interpreter::new();
@@ -610,10 +1102,10 @@ mod tests {
         interpreter.v[2] = 2;
         interpreter.keypad.set_down(1);
 
-        interpreter.decode(0xE19E);
+        interpreter.decode(0xE19E).unwrap();
         assert_eq!(interpreter.pc, 4);
 
-        interpreter.decode(0xE29E);
+        interpreter.decode(0xE29E).unwrap();
         assert_eq!(interpreter.pc, 4);
     }
 
@@ -625,10 +1117,10 @@ mod tests {
         interpreter.v[2] = 2;
         interpreter.keypad.set_down(1);
 
-        interpreter.decode(0xE1A1);
+        interpreter.decode(0xE1A1).unwrap();
         assert_eq!(interpreter.pc, 2);
 
-        interpreter.decode(0xE2A1);
+        interpreter.decode(0xE2A1).unwrap();
         assert_eq!(interpreter.pc, 4);
     }
 
@@ -636,7 +1128,7 @@ mod tests {
     fn test_ld_vx_dt() {
         let mut interpreter = Interpreter::new();
         interpreter.dtimer = 0x01;
-        interpreter.decode(0xF107);
+        interpreter.decode(0xF107).unwrap();
 
         assert_eq!(interpreter.v[1], 0x01);
     }
@@ -645,13 +1137,13 @@ mod tests {
     fn test_ld_vx_k() {
         let mut interpreter = Interpreter::new();
         interpreter.fetch();
-        interpreter.decode(0xF10A);
+        interpreter.decode(0xF10A).unwrap();
 
         assert_eq!(interpreter.pc, 0);
 
         interpreter.fetch();
         interpreter.keypad.set_down(1);
-        interpreter.decode(0xF10A);
+        interpreter.decode(0xF10A).unwrap();
 
         assert_eq!(interpreter.pc, 2);
         assert_eq!(interpreter.v[1], 1);
@@ -661,7 +1153,7 @@ mod tests {
     fn test_ld_dt_vx() {
         let mut interpreter = Interpreter::new();
         interpreter.v[1] = 5;
-        interpreter.decode(0xF115);
+        interpreter.decode(0xF115).unwrap();
 
         assert_eq!(interpreter.dtimer, 5);
     }
@@ -670,7 +1162,7 @@ mod tests {
     fn test_ld_st_vx() {
         let mut interpreter = Interpreter::new();
         interpreter.v[1] = 10;
-        interpreter.decode(0xF118);
+        interpreter.decode(0xF118).unwrap();
 
         assert_eq!(interpreter.stimer, 10);
     }
@@ -681,7 +1173,7 @@ mod tests {
         interpreter.v[1] = 9;
         interpreter.i = 4;
 
-        interpreter.decode(0xF11E);
+        interpreter.decode(0xF11E).unwrap();
         assert_eq!(interpreter.i, 13);
     }
 
@@ -689,7 +1181,7 @@ mod tests {
     fn test_ld_f_vx() {
         let mut interpreter = Interpreter::new();
         interpreter.v[1] = 5;
-        interpreter.decode(0xF129);
+        interpreter.decode(0xF129).unwrap();
 
         assert_eq!(interpreter.i, 5 * 5);
     }
@@ -699,7 +1191,7 @@ mod tests {
         let mut interpreter = Interpreter::new();
         interpreter.v[1] = 156;
         interpreter.i = 0;
-        interpreter.decode(0xF133);
+        interpreter.decode(0xF133).unwrap();
 
         assert_eq!(interpreter.memory[0], 1);
         assert_eq!(interpreter.memory[1], 5);
@@ -707,7 +1199,7 @@ mod tests {
 
         interpreter.v[1] = 6;
         interpreter.i = 0;
-        interpreter.decode(0xF133);
+        interpreter.decode(0xF133).unwrap();
 
         assert_eq!(interpreter.memory[0], 0);
         assert_eq!(interpreter.memory[1], 0);
@@ -715,7 +1207,7 @@ mod tests {
 
         interpreter.v[1] = 56;
         interpreter.i = 0;
-        interpreter.decode(0xF133);
+        interpreter.decode(0xF133).unwrap();
 
         assert_eq!(interpreter.memory[0], 0);
         assert_eq!(interpreter.memory[1], 5);
@@ -727,7 +1219,7 @@ mod tests {
         let mut interpreter = Interpreter::new();
         interpreter.i = 0;
 
-        interpreter.decode(0xF355);
+        interpreter.decode(0xF355).unwrap();
 
         for i in 0..4 {
             assert_eq!(interpreter.memory[i], interpreter.v[i]);
@@ -742,10 +1234,373 @@ mod tests {
             interpreter.memory[i] = i as u8;
         }
 
-        interpreter.decode(0xF365);
+        interpreter.decode(0xF365).unwrap();
 
         for i in 0..4 {
             assert_eq!(interpreter.v[i], interpreter.memory[i]);
         }
     }
+
+    #[test]
+    fn test_unknown_opcode() {
+        let mut interpreter = Interpreter::new();
+        interpreter.init();
+        interpreter.memory[0x200] = 0xFF;
+        interpreter.memory[0x201] = 0xFF;
+
+        assert_eq!(
+            interpreter.cycle(),
+            Err(ExecError::UnknownOpcode {
+                pc: 0x200,
+                opcode: 0xFFFF
+            })
+        );
+    }
+
+    #[test]
+    fn test_stack_underflow() {
+        let mut interpreter = Interpreter::new();
+
+        assert_eq!(interpreter.decode(0x00EE), Err(ExecError::StackUnderflow));
+    }
+
+    #[test]
+    fn test_stack_overflow() {
+        let mut interpreter = Interpreter::new();
+        interpreter.sp = 16;
+
+        assert_eq!(interpreter.decode(0x2AAA), Err(ExecError::StackOverflow));
+        assert_eq!(interpreter.sp, 16);
+    }
+
+    #[test]
+    fn test_memory_out_of_bounds() {
+        let mut interpreter = Interpreter::new();
+        interpreter.i = 0xFFE;
+
+        assert_eq!(
+            interpreter.decode(0xF133),
+            Err(ExecError::MemoryOutOfBounds { addr: 0x1000 })
+        );
+
+        interpreter.pc = 0xFFF;
+        assert_eq!(
+            interpreter.cycle(),
+            Err(ExecError::MemoryOutOfBounds { addr: 0x1000 })
+        );
+    }
+
+    #[test]
+    fn test_quirk_vf_reset() {
+        let mut interpreter = Interpreter::new();
+        interpreter.quirks = Quirks::COSMAC_VIP;
+        interpreter.v[15] = 1;
+        interpreter.v[1] = 0x0B;
+        interpreter.v[2] = 0x03;
+
+        interpreter.decode(0x8121).unwrap();
+        assert_eq!(interpreter.v[1], 11);
+        assert_eq!(interpreter.v[15], 0);
+    }
+
+    #[test]
+    fn test_quirk_shift() {
+        let mut interpreter = Interpreter::new();
+        interpreter.quirks = Quirks::COSMAC_VIP;
+        interpreter.v[1] = 0xFF;
+        interpreter.v[2] = 0x0E;
+
+        interpreter.decode(0x8126).unwrap();
+        assert_eq!(interpreter.v[1], 0x07);
+        assert_eq!(interpreter.v[15], 0);
+
+        interpreter.decode(0x812E).unwrap();
+        assert_eq!(interpreter.v[1], 0x1C);
+        assert_eq!(interpreter.v[15], 0);
+    }
+
+    #[test]
+    fn test_quirk_load_store() {
+        let mut interpreter = Interpreter::new();
+        interpreter.quirks = Quirks::COSMAC_VIP;
+        interpreter.i = 0x300;
+
+        interpreter.decode(0xF355).unwrap();
+        assert_eq!(interpreter.i, 0x304);
+
+        interpreter.quirks = Quirks::CHIP_48;
+        interpreter.decode(0xF365).unwrap();
+        assert_eq!(interpreter.i, 0x307);
+    }
+
+    #[test]
+    fn test_quirk_jump() {
+        let mut interpreter = Interpreter::new();
+        interpreter.quirks = Quirks::SUPER_CHIP;
+        interpreter.v[0] = 0x04;
+        interpreter.v[1] = 0x08;
+
+        interpreter.decode(0xB130).unwrap();
+        assert_eq!(interpreter.pc, 0x138);
+    }
+
+    #[test]
+    fn test_quirk_clipping() {
+        let mut interpreter = Interpreter::new();
+        interpreter.memory[0] = 0xFF;
+        interpreter.v[0] = 60;
+        interpreter.v[1] = 31;
+
+        interpreter.decode(0xD011).unwrap();
+        assert_eq!(interpreter.screen.pixels[31 * 64], 1);
+        assert_eq!(interpreter.screen.pixels[31 * 64 + 63], 1);
+
+        let mut interpreter = Interpreter::new();
+        interpreter.quirks = Quirks::SUPER_CHIP;
+        interpreter.memory[0] = 0xFF;
+        interpreter.v[0] = 60;
+        interpreter.v[1] = 31;
+
+        interpreter.decode(0xD011).unwrap();
+        assert_eq!(interpreter.screen.pixels[31 * 64], 0);
+        assert_eq!(interpreter.screen.pixels[31 * 64 + 63], 1);
+    }
+
+    #[test]
+    fn test_quirk_display_wait() {
+        let mut interpreter = Interpreter::new();
+        interpreter.init();
+        interpreter.quirks = Quirks::COSMAC_VIP;
+        // DRW V0, V0, 1 twice
+        interpreter.memory[0x200..0x204].copy_from_slice(&[0xD0, 0x01, 0xD0, 0x01]);
+
+        interpreter.cycle().unwrap();
+        interpreter.cycle().unwrap();
+        assert_eq!(interpreter.pc, 0x202);
+
+        interpreter.tick();
+        interpreter.cycle().unwrap();
+        assert_eq!(interpreter.pc, 0x204);
+    }
+
+    #[test]
+    fn test_schip_opcodes_need_variant() {
+        let mut interpreter = Interpreter::new();
+
+        assert_eq!(
+            interpreter.decode(0x00FF),
+            Err(ExecError::UnknownOpcode {
+                pc: 0xFFFE,
+                opcode: 0x00FF
+            })
+        );
+        assert!(!interpreter.screen.is_hires());
+    }
+
+    #[test]
+    fn test_high_low() {
+        let mut interpreter = Interpreter::new();
+        interpreter.variant = Variant::SuperChip;
+
+        interpreter.decode(0x00FF).unwrap();
+        assert_eq!(interpreter.screen.width(), 128);
+        assert_eq!(interpreter.screen.height(), 64);
+
+        interpreter.decode(0x00FE).unwrap();
+        assert_eq!(interpreter.screen.width(), 64);
+        assert_eq!(interpreter.screen.height(), 32);
+    }
+
+    #[test]
+    fn test_scroll() {
+        let mut interpreter = Interpreter::new();
+        interpreter.variant = Variant::SuperChip;
+        interpreter.decode(0x00FF).unwrap();
+        interpreter.screen.pixels[0] = 1;
+
+        interpreter.decode(0x00C3).unwrap();
+        assert_eq!(interpreter.screen.pixels[0], 0);
+        assert_eq!(interpreter.screen.pixels[3 * 128], 1);
+
+        interpreter.decode(0x00FB).unwrap();
+        assert_eq!(interpreter.screen.pixels[3 * 128 + 4], 1);
+
+        interpreter.decode(0x00FC).unwrap();
+        interpreter.decode(0x00FC).unwrap();
+        assert!(interpreter.screen.pixels.iter().all(|&pixel| pixel == 0));
+    }
+
+    #[test]
+    fn test_drw_16x16() {
+        let mut interpreter = Interpreter::new();
+        interpreter.variant = Variant::SuperChip;
+        interpreter.decode(0x00FF).unwrap();
+        interpreter.i = 0x300;
+
+        for byte in 0..32 {
+            interpreter.memory[0x300 + byte] = 0xFF;
+        }
+
+        interpreter.decode(0xD010).unwrap();
+        assert_eq!(
+            interpreter
+                .screen
+                .pixels
+                .iter()
+                .filter(|&&p| p == 1)
+                .count(),
+            256
+        );
+        assert_eq!(interpreter.screen.pixels[15 + 15 * 128], 1);
+        assert_eq!(interpreter.v[15], 0);
+
+        interpreter.decode(0xD010).unwrap();
+        assert_eq!(interpreter.v[15], 1);
+    }
+
+    #[test]
+    fn test_ld_hf_vx() {
+        let mut interpreter = Interpreter::new();
+        interpreter.variant = Variant::SuperChip;
+        interpreter.v[1] = 2;
+
+        interpreter.decode(0xF130).unwrap();
+        assert_eq!(interpreter.i, 80 + 2 * 10);
+    }
+
+    #[test]
+    fn test_rpl_flags() {
+        let mut interpreter = Interpreter::new();
+        interpreter.variant = Variant::SuperChip;
+        interpreter.v[..4].copy_from_slice(&[1, 2, 3, 4]);
+
+        interpreter.decode(0xF375).unwrap();
+        interpreter.init();
+        interpreter.decode(0xF285).unwrap();
+
+        assert_eq!(interpreter.v[..4], [1, 2, 3, 0]);
+    }
+
+    #[test]
+    fn test_exit() {
+        let mut interpreter = Interpreter::new();
+        interpreter.init();
+        interpreter.variant = Variant::SuperChip;
+        interpreter.memory[0x200..0x202].copy_from_slice(&[0x00, 0xFD]);
+
+        interpreter.cycle().unwrap();
+        interpreter.cycle().unwrap();
+
+        assert!(interpreter.has_exited());
+        assert_eq!(interpreter.pc, 0x202);
+    }
+
+    #[test]
+    fn test_xo_memory() {
+        let mut interpreter = Interpreter::new();
+        interpreter.variant = Variant::XoChip;
+        interpreter.init();
+        assert_eq!(interpreter.memory.len(), 0x10000);
+
+        interpreter.memory[0x200..0x204].copy_from_slice(&[0xF0, 0x00, 0xC0, 0x00]);
+        interpreter.cycle().unwrap();
+
+        assert_eq!(interpreter.i, 0xC000);
+        assert_eq!(interpreter.pc, 0x204);
+
+        // FX0A at the very end of memory waits where it is
+        interpreter.memory[0xFFFE..].copy_from_slice(&[0xF0, 0x0A]);
+        interpreter.pc = 0xFFFE;
+        interpreter.cycle().unwrap();
+        assert_eq!(interpreter.pc, 0xFFFE);
+    }
+
+    #[test]
+    fn test_xo_skip_long_instruction() {
+        let mut interpreter = Interpreter::new();
+        interpreter.variant = Variant::XoChip;
+        interpreter.init();
+        interpreter.memory[0x200..0x206].copy_from_slice(&[0x30, 0x00, 0xF0, 0x00, 0x12, 0x34]);
+
+        interpreter.cycle().unwrap();
+
+        assert_eq!(interpreter.pc, 0x206);
+    }
+
+    #[test]
+    fn test_xo_register_range() {
+        let mut interpreter = Interpreter::new();
+        interpreter.variant = Variant::XoChip;
+        interpreter.i = 0x300;
+        interpreter.v[..4].copy_from_slice(&[1, 2, 3, 4]);
+
+        interpreter.decode(0x5132).unwrap();
+        assert_eq!(interpreter.memory[0x300..0x302], [2, 3]);
+
+        interpreter.decode(0x5312).unwrap();
+        assert_eq!(interpreter.memory[0x300..0x303], [4, 3, 2]);
+
+        interpreter.decode(0x5013).unwrap();
+        assert_eq!(interpreter.v[..2], [4, 3]);
+        assert_eq!(interpreter.i, 0x300);
+    }
+
+    #[test]
+    fn test_xo_planes() {
+        let mut interpreter = Interpreter::new();
+        interpreter.variant = Variant::XoChip;
+        interpreter.i = 0x300;
+        interpreter.memory[0x300..0x302].copy_from_slice(&[0x80, 0xC0]);
+
+        interpreter.decode(0xF301).unwrap();
+        interpreter.decode(0xD001).unwrap();
+        assert_eq!(interpreter.screen.pixels[..2], [3, 2]);
+
+        interpreter.decode(0xF101).unwrap();
+        interpreter.decode(0x00E0).unwrap();
+        assert_eq!(interpreter.screen.pixels[..2], [2, 2]);
+    }
+
+    #[test]
+    fn test_xo_audio() {
+        let mut interpreter = Interpreter::new();
+        interpreter.variant = Variant::XoChip;
+        interpreter.i = 0x300;
+        interpreter.memory[0x300..0x310].copy_from_slice(&[0xAA; 16]);
+        interpreter.v[2] = 112;
+
+        interpreter.decode(0xF002).unwrap();
+        interpreter.decode(0xF23A).unwrap();
+
+        assert_eq!(interpreter.audio_pattern, [0xAA; 16]);
+        assert_eq!(interpreter.pitch, 112);
+    }
+
+    #[test]
+    fn test_load_rom() {
+        let mut interpreter = Interpreter::new();
+        interpreter.load_rom(&[0xAA; 8]).unwrap();
+        interpreter.load_rom(&[0x12, 0x34]).unwrap();
+
+        assert_eq!(interpreter.pc, 0x200);
+        assert_eq!(interpreter.memory[0x200..0x204], [0x12, 0x34, 0, 0]);
+        assert_eq!(interpreter.memory[0x50], 0xFF);
+    }
+
+    #[test]
+    fn test_load_rom_too_large() {
+        let mut interpreter = Interpreter::new();
+
+        assert_eq!(interpreter.load_rom(&[0; 0xE00]), Ok(()));
+        assert_eq!(
+            interpreter.load_rom(&[0; 0xE01]),
+            Err(LoadError::RomTooLarge {
+                size: 0xE01,
+                max: 0xE00
+            })
+        );
+
+        interpreter.variant = Variant::XoChip;
+        assert_eq!(interpreter.load_rom(&[0; 0xE01]), Ok(()));
+    }
 }
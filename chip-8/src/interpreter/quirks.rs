@@ -0,0 +1,103 @@
+use super::variant::Variant;
+
+/// What FX55/FX65 do to I once the registers are stored or loaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadStore {
+    Unchanged,
+    IncrementByX,
+    IncrementByXPlusOne,
+}
+
+/// The ambiguous instructions of CHIP-8, which were implemented differently
+/// depending on the machine a ROM was written for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    /// 8XY6/8XYE shift VX in place instead of shifting VY into VX.
+    pub shift: bool,
+    pub load_store: LoadStore,
+    /// BNNN jumps to XNN + VX instead of NNN + V0.
+    pub jump: bool,
+    /// 8XY1/8XY2/8XY3 reset VF to 0.
+    pub vf_reset: bool,
+    /// Sprites are clipped at the screen edges instead of wrapping around.
+    pub clipping: bool,
+    /// DXYN waits for the next timer tick, so a program draws at most once per frame.
+    pub display_wait: bool,
+}
+
+impl Quirks {
+    pub const COSMAC_VIP: Quirks = Quirks {
+        shift: false,
+        load_store: LoadStore::IncrementByXPlusOne,
+        jump: false,
+        vf_reset: true,
+        clipping: true,
+        display_wait: true,
+    };
+
+    pub const CHIP_48: Quirks = Quirks {
+        shift: true,
+        load_store: LoadStore::IncrementByX,
+        jump: true,
+        vf_reset: false,
+        clipping: true,
+        display_wait: false,
+    };
+
+    pub const SUPER_CHIP: Quirks = Quirks {
+        shift: true,
+        load_store: LoadStore::Unchanged,
+        jump: true,
+        vf_reset: false,
+        clipping: true,
+        display_wait: false,
+    };
+
+    pub const XO_CHIP: Quirks = Quirks {
+        shift: false,
+        load_store: LoadStore::IncrementByXPlusOne,
+        jump: false,
+        vf_reset: false,
+        clipping: false,
+        display_wait: false,
+    };
+
+    /// `default` for `new()`, or `vip`, `chip48`, `schip` and `xochip` for the presets.
+    pub fn from_name(name: &str) -> Option<Quirks> {
+        match name {
+            "default" => Some(Quirks::new()),
+            "vip" => Some(Quirks::COSMAC_VIP),
+            "chip48" => Some(Quirks::CHIP_48),
+            "schip" => Some(Quirks::SUPER_CHIP),
+            "xochip" => Some(Quirks::XO_CHIP),
+            _ => None,
+        }
+    }
+
+    /// What programs written for `variant` usually expect.
+    pub fn for_variant(variant: Variant) -> Quirks {
+        match variant {
+            Variant::Chip8 => Quirks::new(),
+            Variant::SuperChip => Quirks::SUPER_CHIP,
+            Variant::XoChip => Quirks::XO_CHIP,
+        }
+    }
+
+    /// The behavior this interpreter had before quirks were configurable.
+    pub const fn new() -> Self {
+        Self {
+            shift: true,
+            load_store: LoadStore::Unchanged,
+            jump: false,
+            vf_reset: false,
+            clipping: false,
+            display_wait: false,
+        }
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,219 @@
+use std::collections::VecDeque;
+
+use super::error::StateError;
+use super::Interpreter;
+
+/// A ring buffer of save states recorded every `interval` frames.
+///
+/// Only the newest state is kept whole, every older one is stored as the XOR
+/// against its successor with the runs of zeros compressed, which is tiny since
+/// most of the memory and screen don't change from one frame to the next.
+pub struct Rewind {
+    capacity: usize,
+    interval: usize,
+    frames_since_record: usize,
+    latest: Option<Vec<u8>>,
+    // Oldest first, the last one turning `latest` into the state recorded before it
+    deltas: VecDeque<Vec<u8>>,
+}
+
+impl Rewind {
+    /// Keep up to `capacity` states, one every `interval` frames.
+    pub fn new(capacity: usize, interval: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            interval: interval.max(1),
+            frames_since_record: 0,
+            latest: None,
+            deltas: VecDeque::new(),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.frames_since_record = 0;
+        self.latest = None;
+        self.deltas.clear();
+    }
+
+    /// How many frames back the oldest recorded state is.
+    pub fn available_frames(&self) -> usize {
+        match self.latest {
+            Some(_) => self.frames_since_record + self.deltas.len() * self.interval,
+            None => 0,
+        }
+    }
+
+    /// Call once per frame, a state being recorded every `interval` calls.
+    pub fn record(&mut self, interpreter: &Interpreter) {
+        if self.latest.is_some() {
+            self.frames_since_record += 1;
+
+            if self.frames_since_record < self.interval {
+                return;
+            }
+        }
+
+        let state = interpreter.save_state();
+
+        match self.latest.take() {
+            // A state of another size (the variant changed) can't be diffed, so start over
+            Some(latest) if latest.len() == state.len() => {
+                self.deltas.push_back(encode_delta(&latest, &state));
+            }
+            _ => self.deltas.clear(),
+        }
+
+        while self.deltas.len() >= self.capacity {
+            self.deltas.pop_front();
+        }
+
+        self.latest = Some(state);
+        self.frames_since_record = 0;
+    }
+
+    /// Restore the newest state that is at least `frames` frames old, or the oldest one
+    /// if there isn't any. Returns how many frames were actually rewound.
+    pub fn rewind(
+        &mut self,
+        interpreter: &mut Interpreter,
+        frames: usize,
+    ) -> Result<usize, StateError> {
+        let mut state = match self.latest.take() {
+            Some(state) => state,
+            None => return Ok(0),
+        };
+
+        let steps = frames
+            .saturating_sub(self.frames_since_record)
+            .div_ceil(self.interval)
+            .min(self.deltas.len());
+
+        for _ in 0..steps {
+            if let Some(delta) = self.deltas.pop_back() {
+                apply_delta(&mut state, &delta);
+            }
+        }
+
+        let rewound = self.frames_since_record + steps * self.interval;
+        let result = interpreter.load_state(&state);
+
+        self.latest = Some(state);
+        self.frames_since_record = 0;
+
+        result.map(|()| rewound)
+    }
+}
+
+fn push_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+
+    out.push(value as u8);
+}
+
+fn read_varint(bytes: &[u8], position: &mut usize) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+
+    while let Some(&byte) = bytes.get(*position) {
+        *position += 1;
+        value |= ((byte & 0x7F) as usize) << shift;
+        shift += 7;
+
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+
+    value
+}
+
+/// XOR `older` against `newer` and encode it as (zero run, literal count, literals) triples.
+fn encode_delta(older: &[u8], newer: &[u8]) -> Vec<u8> {
+    let xor: Vec<u8> = older.iter().zip(newer).map(|(a, b)| a ^ b).collect();
+    let mut delta = Vec::new();
+    let mut position = 0;
+
+    while position < xor.len() {
+        let zeros = xor[position..]
+            .iter()
+            .take_while(|&&byte| byte == 0)
+            .count();
+        position += zeros;
+        let literals = xor[position..]
+            .iter()
+            .take_while(|&&byte| byte != 0)
+            .count();
+
+        push_varint(&mut delta, zeros);
+        push_varint(&mut delta, literals);
+        delta.extend_from_slice(&xor[position..position + literals]);
+        position += literals;
+    }
+
+    delta
+}
+
+fn apply_delta(state: &mut [u8], delta: &[u8]) {
+    let (mut position, mut offset) = (0, 0);
+
+    while position < delta.len() {
+        offset += read_varint(delta, &mut position);
+        let literals = read_varint(delta, &mut position);
+
+        for (byte, xor) in state[offset..offset + literals]
+            .iter_mut()
+            .zip(&delta[position..position + literals])
+        {
+            *byte ^= xor;
+        }
+
+        position += literals;
+        offset += literals;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{apply_delta, encode_delta, Rewind};
+    use crate::interpreter::Interpreter;
+
+    #[test]
+    fn test_delta_roundtrip() {
+        let older = vec![0, 1, 2, 3, 4, 5, 6, 7, 8, 9];
+        let newer = vec![0, 1, 9, 3, 4, 5, 6, 7, 8, 0];
+        let delta = encode_delta(&older, &newer);
+
+        let mut state = newer.clone();
+        apply_delta(&mut state, &delta);
+
+        assert_eq!(state, older);
+    }
+
+    #[test]
+    fn test_rewind() {
+        let mut interpreter = Interpreter::new();
+        // ADD V0, 1 then JP 0x200
+        interpreter.load_rom(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+        let mut rewind = Rewind::new(10, 2);
+
+        for _ in 0..20 {
+            interpreter.cycle().unwrap();
+            interpreter.cycle().unwrap();
+            rewind.record(&interpreter);
+        }
+
+        // States were recorded on odd frames, the newest one a frame ago
+        assert_eq!(interpreter.v[0], 20);
+        assert_eq!(rewind.available_frames(), 19);
+
+        assert_eq!(rewind.rewind(&mut interpreter, 4), Ok(5));
+        assert_eq!(interpreter.v[0], 15);
+
+        // Only as far as the oldest state
+        assert_eq!(rewind.rewind(&mut interpreter, 100), Ok(14));
+        assert_eq!(interpreter.v[0], 1);
+    }
+}
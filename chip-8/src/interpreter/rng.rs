@@ -0,0 +1,66 @@
+/// A small xorshift64* generator, good enough for CXKK and fully deterministic
+/// from its seed so games can be replayed.
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub const DEFAULT_SEED: u64 = 0x2545_F491_4F6C_DD1D;
+
+    pub const fn new(seed: u64) -> Self {
+        // xorshift gets stuck on a zero state
+        let state = if seed == 0 { Rng::DEFAULT_SEED } else { seed };
+
+        Self { state }
+    }
+
+    pub fn set_seed(&mut self, seed: u64) {
+        *self = Rng::new(seed);
+    }
+
+    /// The current state, which given back to `set_seed()` resumes the same sequence.
+    pub fn state(&self) -> u64 {
+        self.state
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+
+        self.state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    pub fn next_u8(&mut self) -> u8 {
+        // The high bits are the best ones with xorshift*
+        (self.next_u64() >> 56) as u8
+    }
+}
+
+impl Default for Rng {
+    fn default() -> Self {
+        Self::new(Rng::DEFAULT_SEED)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Rng;
+
+    #[test]
+    fn test_same_seed_same_sequence() {
+        let mut a = Rng::new(42);
+        let mut b = Rng::new(42);
+
+        for _ in 0..100 {
+            assert_eq!(a.next_u8(), b.next_u8());
+        }
+    }
+
+    #[test]
+    fn test_zero_seed() {
+        let mut rng = Rng::new(0);
+
+        assert_ne!(rng.next_u64(), 0);
+    }
+}
@@ -1,5 +1,9 @@
+/// Each pixel is a bitmask of the planes it is lit on: bit 0 for the first plane,
+/// bit 1 for the second one which only XO-CHIP programs use.
 pub struct Screen {
-    pub pixels: [u8; 2048],
+    pub pixels: [u8; Screen::MAX_WIDTH * Screen::MAX_HEIGHT],
+    hires: bool,
+    planes: u8,
 }
 
 pub enum PixelState {
@@ -7,34 +11,288 @@ pub enum PixelState {
     Off,
 }
 
+/// RGBA colors indexed by pixel value: 0 when off, 1 and 2 for the first and second
+/// plane, 3 for both.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palette {
+    pub colors: [[u8; 4]; 4],
+}
+
+impl Palette {
+    pub const fn new(colors: [[u8; 4]; 4]) -> Self {
+        Self { colors }
+    }
+
+    /// Two colors, any lit plane being drawn in `on`.
+    pub const fn monochrome(off: [u8; 4], on: [u8; 4]) -> Self {
+        Self {
+            colors: [off, on, on, on],
+        }
+    }
+}
+
+impl Default for Palette {
+    /// White on black, like the web view always drew.
+    fn default() -> Self {
+        Palette::monochrome([0, 0, 0, 255], [255, 255, 255, 255])
+    }
+}
+
 impl Screen {
-    pub const WIDTH: usize = 64;
-    pub const HEIGHT: usize = 32;
+    pub const LORES_WIDTH: usize = 64;
+    pub const LORES_HEIGHT: usize = 32;
+    pub const HIRES_WIDTH: usize = 128;
+    pub const HIRES_HEIGHT: usize = 64;
+    pub const MAX_WIDTH: usize = Screen::HIRES_WIDTH;
+    pub const MAX_HEIGHT: usize = Screen::HIRES_HEIGHT;
 
     pub const fn new() -> Self {
-        Self { pixels: [0; 2048] }
+        Self {
+            pixels: [0; Screen::MAX_WIDTH * Screen::MAX_HEIGHT],
+            hires: false,
+            planes: 1,
+        }
+    }
+
+    /// Width of the active resolution, the first `width * height` pixels being the visible ones.
+    pub fn width(&self) -> usize {
+        if self.hires {
+            Screen::HIRES_WIDTH
+        } else {
+            Screen::LORES_WIDTH
+        }
+    }
+
+    pub fn height(&self) -> usize {
+        if self.hires {
+            Screen::HIRES_HEIGHT
+        } else {
+            Screen::LORES_HEIGHT
+        }
+    }
+
+    pub fn is_hires(&self) -> bool {
+        self.hires
+    }
+
+    /// Switch between 64x32 and 128x64, which clears every plane.
+    pub fn set_hires(&mut self, hires: bool) {
+        self.hires = hires;
+        self.pixels = [0; Screen::MAX_WIDTH * Screen::MAX_HEIGHT];
+    }
+
+    /// The planes affected by drawing, clearing and scrolling.
+    pub fn planes(&self) -> u8 {
+        self.planes
+    }
+
+    pub fn select_planes(&mut self, planes: u8) {
+        self.planes = planes & 0b11;
     }
 
     pub fn clear(&mut self) {
-        for x in 0..Screen::WIDTH {
-            for y in 0..Screen::HEIGHT {
-                self.update_pixel((x, y), PixelState::Off);
-            }
+        let mask = !self.planes;
+
+        for pixel in self.pixels.iter_mut() {
+            *pixel &= mask;
         }
     }
 
-    pub fn update_pixel(&mut self, (x, y): (usize, usize), state: PixelState) {
+    pub fn update_pixel(&mut self, (x, y): (usize, usize), plane: u8, state: PixelState) {
+        let width = self.width();
+
         match state {
-            PixelState::On => self.pixels[x + y * Screen::WIDTH] = 1,
-            PixelState::Off => self.pixels[x + y * Screen::WIDTH] = 0,
+            PixelState::On => self.pixels[x + y * width] |= plane,
+            PixelState::Off => self.pixels[x + y * width] &= !plane,
+        }
+    }
+
+    /// The visible pixels, a line of text per row: `.` when off, `#` when lit on the first
+    /// plane only, `+` on the second only and `@` on both.
+    pub fn render_text(&self) -> String {
+        let width = self.width();
+        let mut text = String::with_capacity((width + 1) * self.height());
+
+        for row in self.pixels[..width * self.height()].chunks(width) {
+            text.extend(row.iter().map(|pixel| match pixel & 0b11 {
+                0 => '.',
+                1 => '#',
+                2 => '+',
+                _ => '@',
+            }));
+            text.push('\n');
         }
+
+        text
+    }
+
+    /// Bytes needed by `render_rgba_scaled()`.
+    pub fn rgba_len(&self, scale: usize) -> usize {
+        self.width() * scale * self.height() * scale * 4
     }
 
-    pub fn get_pixel_state(&self, (x, y): (usize, usize)) -> PixelState {
-        match self.pixels[x + y * Screen::WIDTH] {
+    /// Write the visible pixels to `buffer` as RGBA, row by row.
+    pub fn render_rgba(&self, buffer: &mut [u8], palette: &Palette) {
+        self.render_rgba_scaled(buffer, palette, 1);
+    }
+
+    /// Like `render_rgba()`, each pixel becoming a `scale` by `scale` square.
+    ///
+    /// Panics if `buffer` is shorter than `rgba_len(scale)`.
+    pub fn render_rgba_scaled(&self, buffer: &mut [u8], palette: &Palette, scale: usize) {
+        let width = self.width();
+        let row_len = width * scale * 4;
+        let buffer = &mut buffer[..self.rgba_len(scale)];
+
+        for (pixels, rows) in self.pixels[..width * self.height()]
+            .chunks(width)
+            .zip(buffer.chunks_mut(row_len * scale))
+        {
+            let (row, copies) = rows.split_at_mut(row_len);
+
+            for (pixel, rgba) in pixels.iter().zip(row.chunks_mut(scale * 4)) {
+                let color = palette.colors[(pixel & 0b11) as usize];
+                for dot in rgba.chunks_mut(4) {
+                    dot.copy_from_slice(&color);
+                }
+            }
+
+            for copy in copies.chunks_mut(row_len) {
+                copy.copy_from_slice(row);
+            }
+        }
+    }
+
+    pub fn get_pixel_state(&self, (x, y): (usize, usize), plane: u8) -> PixelState {
+        match self.pixels[x + y * self.width()] & plane {
             0 => PixelState::Off,
             // It would probably be better to use a Result here...
             _ => PixelState::On,
         }
     }
+
+    pub fn scroll_down(&mut self, n: usize) {
+        let (width, height) = (self.width(), self.height());
+
+        for y in (0..height).rev() {
+            for x in 0..width {
+                let source = if y >= n {
+                    self.pixels[x + (y - n) * width]
+                } else {
+                    0
+                };
+                self.move_pixel(x + y * width, source);
+            }
+        }
+    }
+
+    pub fn scroll_up(&mut self, n: usize) {
+        let (width, height) = (self.width(), self.height());
+
+        for y in 0..height {
+            for x in 0..width {
+                let source = if y + n < height {
+                    self.pixels[x + (y + n) * width]
+                } else {
+                    0
+                };
+                self.move_pixel(x + y * width, source);
+            }
+        }
+    }
+
+    pub fn scroll_right(&mut self, n: usize) {
+        let (width, height) = (self.width(), self.height());
+
+        for y in 0..height {
+            for x in (0..width).rev() {
+                let source = if x >= n {
+                    self.pixels[x - n + y * width]
+                } else {
+                    0
+                };
+                self.move_pixel(x + y * width, source);
+            }
+        }
+    }
+
+    pub fn scroll_left(&mut self, n: usize) {
+        let (width, height) = (self.width(), self.height());
+
+        for y in 0..height {
+            for x in 0..width {
+                let source = if x + n < width {
+                    self.pixels[x + n + y * width]
+                } else {
+                    0
+                };
+                self.move_pixel(x + y * width, source);
+            }
+        }
+    }
+
+    // Scrolling only moves the selected planes, the others stay in place
+    fn move_pixel(&mut self, index: usize, source: u8) {
+        self.pixels[index] = (self.pixels[index] & !self.planes) | (source & self.planes);
+    }
+}
+
+impl Default for Screen {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Palette, PixelState, Screen};
+
+    #[test]
+    fn test_render_text() {
+        let mut screen = Screen::new();
+        screen.update_pixel((0, 0), 1, PixelState::On);
+        screen.update_pixel((63, 0), 2, PixelState::On);
+        screen.update_pixel((1, 31), 3, PixelState::On);
+
+        let text = screen.render_text();
+        let lines: Vec<_> = text.lines().collect();
+        assert_eq!(lines.len(), 32);
+        assert_eq!(lines[0], format!("#{}+", ".".repeat(62)));
+        assert_eq!(lines[31], format!(".@{}", ".".repeat(62)));
+
+        screen.set_hires(true);
+        assert_eq!(
+            screen.render_text(),
+            format!("{}\n", ".".repeat(128)).repeat(64)
+        );
+    }
+
+    #[test]
+    fn test_render_rgba() {
+        let mut screen = Screen::new();
+        screen.update_pixel((0, 0), 1, PixelState::On);
+        screen.update_pixel((1, 0), 2, PixelState::On);
+        screen.update_pixel((63, 31), 3, PixelState::On);
+        let palette = Palette::new([
+            [0, 0, 0, 255],
+            [1, 1, 1, 255],
+            [2, 2, 2, 255],
+            [3, 3, 3, 255],
+        ]);
+
+        let mut buffer = vec![0xAA; screen.rgba_len(1)];
+        screen.render_rgba(&mut buffer, &palette);
+        assert_eq!(buffer.len(), 64 * 32 * 4);
+        assert_eq!(buffer[..12], [1, 1, 1, 255, 2, 2, 2, 255, 0, 0, 0, 255]);
+        assert_eq!(buffer[buffer.len() - 4..], [3, 3, 3, 255]);
+
+        let mut buffer = vec![0xAA; screen.rgba_len(2)];
+        screen.render_rgba_scaled(&mut buffer, &Palette::default(), 2);
+        let white = [255, 255, 255, 255];
+        // The first pixel is the top-left 2x2 square, the second one too with a single color
+        assert_eq!(buffer[..16], [white, white, white, white].concat());
+        let second_row = 128 * 4;
+        assert_eq!(buffer[second_row..second_row + 8], [white, white].concat());
+        assert_eq!(buffer[second_row + 16..second_row + 20], [0, 0, 0, 255]);
+    }
 }
@@ -0,0 +1,268 @@
+use super::error::StateError;
+use super::quirks::{LoadStore, Quirks};
+use super::variant::Variant;
+use super::Interpreter;
+
+const MAGIC: &[u8; 4] = b"CH8S";
+const VERSION: u8 = 1;
+
+/// Little-endian reader over a save state, failing with `Truncated` past the end.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        let end = self.position + len;
+        let bytes = self
+            .bytes
+            .get(self.position..end)
+            .ok_or(StateError::Truncated)?;
+        self.position = end;
+
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn bool(&mut self) -> Result<bool, StateError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(StateError::Invalid),
+        }
+    }
+
+    fn u16(&mut self) -> Result<u16, StateError> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, StateError> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    fn u64(&mut self) -> Result<u64, StateError> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], StateError> {
+        let mut bytes = [0; N];
+        bytes.copy_from_slice(self.take(N)?);
+        Ok(bytes)
+    }
+}
+
+fn variant_to_u8(variant: Variant) -> u8 {
+    match variant {
+        Variant::Chip8 => 0,
+        Variant::SuperChip => 1,
+        Variant::XoChip => 2,
+    }
+}
+
+fn variant_from_u8(value: u8) -> Result<Variant, StateError> {
+    match value {
+        0 => Ok(Variant::Chip8),
+        1 => Ok(Variant::SuperChip),
+        2 => Ok(Variant::XoChip),
+        _ => Err(StateError::Invalid),
+    }
+}
+
+fn load_store_to_u8(load_store: LoadStore) -> u8 {
+    match load_store {
+        LoadStore::Unchanged => 0,
+        LoadStore::IncrementByX => 1,
+        LoadStore::IncrementByXPlusOne => 2,
+    }
+}
+
+fn load_store_from_u8(value: u8) -> Result<LoadStore, StateError> {
+    match value {
+        0 => Ok(LoadStore::Unchanged),
+        1 => Ok(LoadStore::IncrementByX),
+        2 => Ok(LoadStore::IncrementByXPlusOne),
+        _ => Err(StateError::Invalid),
+    }
+}
+
+impl Interpreter {
+    /// Snapshot the whole machine: memory, registers, timers, screen, keypad, quirks and RNG.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = Vec::with_capacity(self.memory.len() + self.screen.pixels.len() + 256);
+
+        state.extend_from_slice(MAGIC);
+        state.push(VERSION);
+        state.push(variant_to_u8(self.variant));
+
+        state.extend_from_slice(&(self.memory.len() as u32).to_le_bytes());
+        state.extend_from_slice(&self.memory);
+        state.extend_from_slice(&self.v);
+        state.extend_from_slice(&self.i.to_le_bytes());
+        state.extend_from_slice(&self.pc.to_le_bytes());
+        for address in self.stack.iter() {
+            state.extend_from_slice(&address.to_le_bytes());
+        }
+        state.push(self.sp);
+        state.push(self.dtimer);
+        state.push(self.stimer);
+
+        state.push(self.screen.is_hires() as u8);
+        state.push(self.screen.planes());
+        state.extend_from_slice(&self.screen.pixels);
+        state.extend(self.keypad.keys.iter().map(|&key| key as u8));
+
+        state.extend_from_slice(&self.rng.state().to_le_bytes());
+        state.push(self.quirks.shift as u8);
+        state.push(load_store_to_u8(self.quirks.load_store));
+        state.push(self.quirks.jump as u8);
+        state.push(self.quirks.vf_reset as u8);
+        state.push(self.quirks.clipping as u8);
+        state.push(self.quirks.display_wait as u8);
+
+        state.extend_from_slice(&self.rpl);
+        state.extend_from_slice(&self.audio_pattern);
+        state.push(self.pitch);
+        state.push(self.waiting_for_vblank as u8);
+        state.push(self.exited as u8);
+
+        state
+    }
+
+    /// Restore a snapshot from `save_state()`, leaving the interpreter untouched on error.
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), StateError> {
+        let mut reader = Reader {
+            bytes: state,
+            position: 0,
+        };
+
+        if reader.take(MAGIC.len()).map_err(|_| StateError::BadMagic)? != MAGIC {
+            return Err(StateError::BadMagic);
+        }
+
+        let version = reader.u8()?;
+        if version != VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+
+        let mut restored = Interpreter::new();
+        restored.variant = variant_from_u8(reader.u8()?)?;
+
+        let memory_size = reader.u32()? as usize;
+        if memory_size != restored.variant.memory_size() {
+            return Err(StateError::Invalid);
+        }
+        restored.memory = reader.take(memory_size)?.to_vec();
+        restored.v = reader.array()?;
+        restored.i = reader.u16()?;
+        restored.pc = reader.u16()?;
+        for address in restored.stack.iter_mut() {
+            *address = reader.u16()?;
+        }
+        restored.sp = reader.u8()?;
+        if restored.sp as usize > restored.stack.len() {
+            return Err(StateError::Invalid);
+        }
+        restored.dtimer = reader.u8()?;
+        restored.stimer = reader.u8()?;
+
+        // set_hires() clears the pixels, so they are read afterwards
+        restored.screen.set_hires(reader.bool()?);
+        restored.screen.select_planes(reader.u8()?);
+        restored.screen.pixels = reader.array()?;
+        for key in restored.keypad.keys.iter_mut() {
+            *key = reader.bool()?;
+        }
+
+        restored.rng.set_seed(reader.u64()?);
+        restored.quirks = Quirks {
+            shift: reader.bool()?,
+            load_store: load_store_from_u8(reader.u8()?)?,
+            jump: reader.bool()?,
+            vf_reset: reader.bool()?,
+            clipping: reader.bool()?,
+            display_wait: reader.bool()?,
+        };
+
+        restored.rpl = reader.array()?;
+        restored.audio_pattern = reader.array()?;
+        restored.pitch = reader.u8()?;
+        restored.waiting_for_vblank = reader.bool()?;
+        restored.exited = reader.bool()?;
+
+        if reader.position != state.len() {
+            return Err(StateError::Invalid);
+        }
+
+        restored.watchpoints = std::mem::take(&mut self.watchpoints);
+        restored.tracer = self.tracer.take();
+        restored.instructions_per_frame = self.instructions_per_frame;
+        restored.timing = self.timing;
+        *self = restored;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interpreter::error::StateError;
+    use crate::interpreter::quirks::Quirks;
+    use crate::interpreter::variant::Variant;
+    use crate::interpreter::Interpreter;
+
+    #[test]
+    fn test_save_load_roundtrip() {
+        let mut interpreter = Interpreter::new();
+        interpreter.variant = Variant::XoChip;
+        interpreter.quirks = Quirks::XO_CHIP;
+        interpreter
+            .load_rom(&[0x00, 0xFF, 0xC0, 0xFF, 0xD0, 0x15])
+            .unwrap();
+        interpreter.rng.set_seed(99);
+        interpreter.keypad.set_down(7);
+        for _ in 0..3 {
+            interpreter.cycle().unwrap();
+        }
+
+        let state = interpreter.save_state();
+        let mut restored = Interpreter::new();
+        restored.load_state(&state).unwrap();
+
+        assert_eq!(restored.save_state(), state);
+        assert_eq!(restored.memory, interpreter.memory);
+        assert_eq!(restored.pc, interpreter.pc);
+        assert_eq!(restored.v, interpreter.v);
+        assert!(restored.screen.is_hires());
+        assert_eq!(restored.screen.pixels[..], interpreter.screen.pixels[..]);
+        assert_eq!(restored.rng.next_u64(), interpreter.rng.next_u64());
+    }
+
+    #[test]
+    fn test_load_bad_state() {
+        let mut interpreter = Interpreter::new();
+        interpreter.init();
+        let state = interpreter.save_state();
+
+        assert_eq!(interpreter.load_state(b"nope"), Err(StateError::BadMagic));
+        assert_eq!(
+            interpreter.load_state(&state[..state.len() - 1]),
+            Err(StateError::Truncated)
+        );
+
+        let mut future = state.clone();
+        future[4] = 2;
+        assert_eq!(
+            interpreter.load_state(&future),
+            Err(StateError::UnsupportedVersion(2))
+        );
+    }
+}
@@ -0,0 +1,134 @@
+use super::Interpreter;
+use crate::disasm::Instruction;
+
+/// How `run_frame()` decides how many instructions make a frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Timing {
+    /// `instructions_per_frame` instructions, whatever they are.
+    Fixed,
+    /// As many instructions as the COSMAC VIP interpreter got through in a frame, each one
+    /// costing what it took on the 1802 so slow instructions such as DXYN take their time.
+    CosmacVip,
+}
+
+impl Timing {
+    /// `fixed` or `vip`.
+    pub fn from_name(name: &str) -> Option<Timing> {
+        match name {
+            "fixed" => Some(Timing::Fixed),
+            "vip" => Some(Timing::CosmacVip),
+            _ => None,
+        }
+    }
+}
+
+/// 1802 machine cycles (8 clock cycles each at 1.76 MHz) in a 60 Hz frame.
+pub const VIP_CYCLES_PER_FRAME: u32 = 3668;
+
+// Fetching an instruction and jumping to its handler
+const FETCH: u32 = 68;
+// Going over the next instruction
+const SKIP: u32 = 4;
+
+/// Approximate machine cycles the VIP took for `instruction`, read before it executes
+/// as the cost of skips, DXYN and FX33 depends on the registers.
+///
+/// Instructions the VIP doesn't have only cost the fetch.
+pub fn vip_cycles(instruction: Instruction, interpreter: &Interpreter) -> u32 {
+    let v = |x: u8| interpreter.v[x as usize];
+    let skip = |skipped: bool| if skipped { SKIP } else { 0 };
+    let key_pressed = |x: u8| interpreter.keypad.is_pressed((v(x) & 0xF) as usize);
+
+    let execute = match instruction {
+        // Clearing the 256 bytes of the display one by one
+        Instruction::Cls => 3078,
+        Instruction::Ret => 10,
+        Instruction::Jp(_) => 12,
+        Instruction::Call(_) => 26,
+        Instruction::SeVxKk(x, kk) => 10 + skip(v(x) == kk),
+        Instruction::SneVxKk(x, kk) => 10 + skip(v(x) != kk),
+        Instruction::SeVxVy(x, y) => 14 + skip(v(x) == v(y)),
+        Instruction::SneVxVy(x, y) => 14 + skip(v(x) != v(y)),
+        Instruction::LdVxKk(..) => 6,
+        Instruction::AddVxKk(..) => 10,
+        Instruction::LdVxVy(..) => 12,
+        // Executed from a routine written to the stack, with VF set afterwards
+        Instruction::OrVxVy(..)
+        | Instruction::AndVxVy(..)
+        | Instruction::XorVxVy(..)
+        | Instruction::AddVxVy(..)
+        | Instruction::SubVxVy(..)
+        | Instruction::ShrVxVy(..)
+        | Instruction::SubnVxVy(..)
+        | Instruction::ShlVxVy(..) => 44,
+        Instruction::LdINnn(_) => 12,
+        Instruction::JpV0Nnn(_) => 22,
+        Instruction::RndVxKk(..) => 36,
+        Instruction::DrwVxVyN(x, _, n) => drw_cycles(v(x), n),
+        Instruction::SkpVx(x) => 14 + skip(key_pressed(x)),
+        Instruction::SknpVx(x) => 14 + skip(!key_pressed(x)),
+        Instruction::LdVxDt(_) | Instruction::LdDtVx(_) | Instruction::LdStVx(_) => 10,
+        // Each time the key is polled
+        Instruction::LdVxK(_) => 10,
+        Instruction::AddIVx(_) | Instruction::LdFVx(_) => 16,
+        // Digits are found by repeated subtraction
+        Instruction::LdBVx(x) => {
+            let value = v(x) as u32;
+            80 + 16 * (value / 100 + value / 10 % 10 + value % 10)
+        }
+        Instruction::LdIVx(x) | Instruction::LdVxI(x) => 14 + 14 * (x as u32 + 1),
+        _ => 0,
+    };
+
+    FETCH + execute
+}
+
+/// Every row of the sprite is shifted into place across two bytes unless it starts on a
+/// byte boundary, one bit at a time.
+fn drw_cycles(x: u8, rows: u8) -> u32 {
+    let shift = (x % 8) as u32;
+    let per_row = match shift {
+        0 => 22,
+        _ => 34 + 4 * shift,
+    };
+
+    26 + rows as u32 * per_row
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{vip_cycles, Timing, FETCH, SKIP};
+    use crate::disasm::Instruction;
+    use crate::interpreter::Interpreter;
+
+    #[test]
+    fn test_vip_cycles() {
+        let mut interpreter = Interpreter::new();
+        interpreter.v[1] = 8;
+        interpreter.v[2] = 3;
+
+        assert_eq!(
+            vip_cycles(Instruction::LdVxKk(0, 1), &interpreter),
+            FETCH + 6
+        );
+        let not_skipped = vip_cycles(Instruction::SeVxKk(1, 7), &interpreter);
+        let skipped = vip_cycles(Instruction::SeVxKk(1, 8), &interpreter);
+        assert_eq!(skipped, not_skipped + SKIP);
+
+        // Byte aligned sprites are faster, and bigger ones slower
+        let aligned = vip_cycles(Instruction::DrwVxVyN(1, 0, 5), &interpreter);
+        let shifted = vip_cycles(Instruction::DrwVxVyN(2, 0, 5), &interpreter);
+        let taller = vip_cycles(Instruction::DrwVxVyN(2, 0, 10), &interpreter);
+        assert!(aligned < shifted && shifted < taller);
+
+        // 8 is 0, 0 and 8
+        assert_eq!(
+            vip_cycles(Instruction::LdBVx(1), &interpreter),
+            FETCH + 80 + 128
+        );
+        assert_eq!(vip_cycles(Instruction::Scr, &interpreter), FETCH);
+
+        assert_eq!(Timing::from_name("vip"), Some(Timing::CosmacVip));
+        assert_eq!(Timing::from_name("fast"), None);
+    }
+}
@@ -0,0 +1,272 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::Write;
+
+use super::debugger::Register;
+use super::Interpreter;
+use crate::disasm::Instruction;
+
+/// Registers compared before and after each instruction, PC aside since it always changes.
+const TRACED_REGISTERS: [Register; 20] = [
+    Register::V(0x0),
+    Register::V(0x1),
+    Register::V(0x2),
+    Register::V(0x3),
+    Register::V(0x4),
+    Register::V(0x5),
+    Register::V(0x6),
+    Register::V(0x7),
+    Register::V(0x8),
+    Register::V(0x9),
+    Register::V(0xA),
+    Register::V(0xB),
+    Register::V(0xC),
+    Register::V(0xD),
+    Register::V(0xE),
+    Register::V(0xF),
+    Register::I,
+    Register::Sp,
+    Register::Dt,
+    Register::St,
+];
+
+pub(super) type Registers = [u16; TRACED_REGISTERS.len()];
+
+pub(super) fn registers(interpreter: &Interpreter) -> Registers {
+    let mut values = [0; TRACED_REGISTERS.len()];
+
+    for (value, register) in values.iter_mut().zip(TRACED_REGISTERS.iter()) {
+        *value = register.read(interpreter);
+    }
+
+    values
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegisterDelta {
+    pub register: Register,
+    pub old: u16,
+    pub new: u16,
+}
+
+/// One executed instruction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceEntry {
+    /// Instructions executed since tracing started, this one excluded.
+    pub cycle: u64,
+    pub pc: u16,
+    pub opcode: u16,
+    pub instruction: Instruction,
+    pub deltas: Vec<RegisterDelta>,
+}
+
+impl fmt::Display for TraceEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let instruction = self.instruction.to_string();
+        write!(
+            f,
+            "{:>8} {:03X} {:04X}  {:<20}",
+            self.cycle, self.pc, self.opcode, instruction
+        )?;
+
+        for delta in self.deltas.iter() {
+            write!(f, " {}={:#X}", delta.register, delta.new)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Where a streaming tracer sends its entries.
+pub trait TraceSink {
+    fn record(&mut self, entry: TraceEntry);
+}
+
+/// Write each entry as a line of text, ready to be diffed against another trace.
+pub struct TraceWriter<W: Write>(pub W);
+
+impl<W: Write> TraceSink for TraceWriter<W> {
+    fn record(&mut self, entry: TraceEntry) {
+        // A trace is a debugging aid, losing a line isn't worth stopping the program
+        let _ = writeln!(self.0, "{}", entry);
+    }
+}
+
+enum Output {
+    Buffer {
+        capacity: usize,
+        entries: VecDeque<TraceEntry>,
+    },
+    Sink(Box<dyn TraceSink>),
+}
+
+/// Records every instruction the interpreter executes, either in a ring buffer
+/// keeping the most recent ones or streamed to a `TraceSink`.
+pub struct Tracer {
+    cycles: u64,
+    output: Output,
+}
+
+impl Tracer {
+    /// Keep the last `capacity` entries.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            cycles: 0,
+            output: Output::Buffer {
+                capacity: capacity.max(1),
+                entries: VecDeque::new(),
+            },
+        }
+    }
+
+    pub fn with_sink(sink: impl TraceSink + 'static) -> Self {
+        Self {
+            cycles: 0,
+            output: Output::Sink(Box::new(sink)),
+        }
+    }
+
+    /// How many instructions were traced so far.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// The buffered entries, oldest first, nothing for a streaming tracer.
+    pub fn entries(&self) -> impl Iterator<Item = &TraceEntry> {
+        let entries = match &self.output {
+            Output::Buffer { entries, .. } => Some(entries.iter()),
+            Output::Sink(_) => None,
+        };
+
+        entries.into_iter().flatten()
+    }
+
+    /// Take the buffered entries out, oldest first.
+    pub fn drain(&mut self) -> Vec<TraceEntry> {
+        match &mut self.output {
+            Output::Buffer { entries, .. } => entries.drain(..).collect(),
+            Output::Sink(_) => Vec::new(),
+        }
+    }
+
+    pub(super) fn record(
+        &mut self,
+        pc: u16,
+        opcode: u16,
+        instruction: Instruction,
+        before: &Registers,
+        after: &Registers,
+    ) {
+        let deltas = TRACED_REGISTERS
+            .iter()
+            .zip(before.iter().zip(after.iter()))
+            .filter(|(_, (old, new))| old != new)
+            .map(|(register, (old, new))| RegisterDelta {
+                register: *register,
+                old: *old,
+                new: *new,
+            })
+            .collect();
+        let entry = TraceEntry {
+            cycle: self.cycles,
+            pc,
+            opcode,
+            instruction,
+            deltas,
+        };
+        self.cycles += 1;
+
+        match &mut self.output {
+            Output::Buffer { capacity, entries } => {
+                if entries.len() == *capacity {
+                    entries.pop_front();
+                }
+                entries.push_back(entry);
+            }
+            Output::Sink(sink) => sink.record(entry),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RegisterDelta, TraceWriter, Tracer};
+    use crate::asm::assemble;
+    use crate::interpreter::debugger::Register;
+    use crate::interpreter::Interpreter;
+    use std::cell::RefCell;
+    use std::io::Write;
+    use std::rc::Rc;
+
+    #[test]
+    fn test_ring_buffer() {
+        let mut interpreter = Interpreter::new();
+        let rom = assemble(
+            "
+            LD V3, 5
+            LD I, 0x300
+            loop: ADD V0, 1
+            JP loop
+            ",
+        )
+        .unwrap();
+        interpreter.load_rom(&rom).unwrap();
+        interpreter.tracer = Some(Tracer::with_capacity(3));
+
+        for _ in 0..10 {
+            interpreter.cycle().unwrap();
+        }
+
+        let tracer = interpreter.tracer.as_ref().unwrap();
+        assert_eq!(tracer.cycles(), 10);
+        let entries: Vec<_> = tracer.entries().collect();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].cycle, 7);
+        assert_eq!((entries[2].pc, entries[2].opcode), (0x206, 0x1204));
+
+        interpreter
+            .load_rom(&assemble("LD V3, 5").unwrap())
+            .unwrap();
+        interpreter.tracer = Some(Tracer::with_capacity(3));
+        interpreter.cycle().unwrap();
+        assert_eq!(
+            interpreter.tracer.as_mut().unwrap().drain()[0].deltas,
+            [RegisterDelta {
+                register: Register::V(3),
+                old: 0,
+                new: 5,
+            }]
+        );
+    }
+
+    #[derive(Clone, Default)]
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(bytes)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_writer_sink() {
+        let mut interpreter = Interpreter::new();
+        let rom = assemble("LD V3, 0xAA\nLD I, 0x123").unwrap();
+        interpreter.load_rom(&rom).unwrap();
+        let output = Shared::default();
+        interpreter.tracer = Some(Tracer::with_sink(TraceWriter(output.clone())));
+
+        interpreter.cycle().unwrap();
+        interpreter.cycle().unwrap();
+
+        let text = String::from_utf8(output.0.borrow().clone()).unwrap();
+        assert_eq!(
+            text,
+            "       0 200 63AA  LD V3, 0xAA          V3=0xAA\n       1 202 A123  LD I, 0x123          I=0x123\n"
+        );
+    }
+}
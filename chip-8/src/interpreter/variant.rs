@@ -0,0 +1,28 @@
+/// The instruction set understood by the interpreter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Variant {
+    Chip8,
+    /// SUPER-CHIP 1.1: hi-res mode, scrolling, 16x16 sprites, big font and RPL flags.
+    SuperChip,
+    /// XO-CHIP: SUPER-CHIP plus 64 KiB of memory, two bitplanes and audio patterns.
+    XoChip,
+}
+
+impl Variant {
+    /// `chip8`, `schip` or `xochip`.
+    pub fn from_name(name: &str) -> Option<Variant> {
+        match name {
+            "chip8" => Some(Variant::Chip8),
+            "schip" => Some(Variant::SuperChip),
+            "xochip" => Some(Variant::XoChip),
+            _ => None,
+        }
+    }
+
+    pub fn memory_size(&self) -> usize {
+        match self {
+            Variant::Chip8 | Variant::SuperChip => 0x1000,
+            Variant::XoChip => 0x10000,
+        }
+    }
+}
@@ -0,0 +1,252 @@
+use std::fmt;
+
+use super::debugger::Register;
+use super::Interpreter;
+
+/// Which memory accesses a watchpoint catches.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl Access {
+    fn catches_reads(&self) -> bool {
+        *self != Access::Write
+    }
+
+    fn catches_writes(&self) -> bool {
+        *self != Access::Read
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct MemoryWatch {
+    start: usize,
+    end: usize,
+    access: Access,
+}
+
+/// What a watchpoint caught.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchTarget {
+    Read(u16),
+    Write(u16),
+    Register(Register),
+}
+
+/// The first access caught while executing an instruction. For a read, `old` and `new`
+/// are both the value read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchHit {
+    pub pc: u16,
+    pub opcode: u16,
+    pub target: WatchTarget,
+    pub old: u16,
+    pub new: u16,
+}
+
+impl fmt::Display for WatchHit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#05X} ({:04X}) ", self.pc, self.opcode)?;
+
+        match self.target {
+            WatchTarget::Read(address) => write!(f, "read {:#05X}: {:#04X}", address, self.new),
+            WatchTarget::Write(address) => write!(
+                f,
+                "wrote {:#05X}: {:#04X} -> {:#04X}",
+                address, self.old, self.new
+            ),
+            WatchTarget::Register(register) => write!(
+                f,
+                "changed {}: {:#X} -> {:#X}",
+                register, self.old, self.new
+            ),
+        }
+    }
+}
+
+/// Memory ranges and registers to watch, checked by the interpreter as it executes.
+///
+/// Registers are compared before and after each instruction, so timers counting
+/// down on `tick()` don't trigger their watch.
+#[derive(Debug, Clone, Default)]
+pub struct Watchpoints {
+    memory: Vec<MemoryWatch>,
+    registers: Vec<Register>,
+    // PC and opcode of the instruction being executed
+    current: (u16, u16),
+    hit: Option<WatchHit>,
+}
+
+impl Watchpoints {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.memory.is_empty() && self.registers.is_empty()
+    }
+
+    /// Watch `len` bytes from `start`, replacing the watch already starting there.
+    pub fn watch_memory(&mut self, start: u16, len: usize, access: Access) {
+        self.unwatch_memory(start);
+        self.memory.push(MemoryWatch {
+            start: start as usize,
+            end: (start as usize).saturating_add(len),
+            access,
+        });
+    }
+
+    /// Returns false if no watch started at `start`.
+    pub fn unwatch_memory(&mut self, start: u16) -> bool {
+        let len = self.memory.len();
+        self.memory.retain(|watch| watch.start != start as usize);
+
+        self.memory.len() != len
+    }
+
+    pub fn watch_register(&mut self, register: Register) {
+        if !self.registers.contains(&register) {
+            self.registers.push(register);
+        }
+    }
+
+    /// Returns false if `register` wasn't watched.
+    pub fn unwatch_register(&mut self, register: Register) -> bool {
+        let len = self.registers.len();
+        self.registers.retain(|watched| *watched != register);
+
+        self.registers.len() != len
+    }
+
+    pub fn clear(&mut self) {
+        self.memory.clear();
+        self.registers.clear();
+        self.hit = None;
+    }
+
+    /// The access caught since the last call, if any.
+    pub fn take_hit(&mut self) -> Option<WatchHit> {
+        self.hit.take()
+    }
+
+    fn record(&mut self, target: WatchTarget, old: u16, new: u16) {
+        if self.hit.is_none() {
+            let (pc, opcode) = self.current;
+            self.hit = Some(WatchHit {
+                pc,
+                opcode,
+                target,
+                old,
+                new,
+            });
+        }
+    }
+
+    pub(super) fn begin(&mut self, pc: u16, opcode: u16) {
+        self.current = (pc, opcode);
+    }
+
+    fn watching(&self, address: usize, catches: impl Fn(&Access) -> bool) -> bool {
+        self.memory
+            .iter()
+            .any(|watch| (watch.start..watch.end).contains(&address) && catches(&watch.access))
+    }
+
+    pub(super) fn on_read(&mut self, address: usize, value: u8) {
+        if self.watching(address, Access::catches_reads) {
+            self.record(
+                WatchTarget::Read(address as u16),
+                value as u16,
+                value as u16,
+            );
+        }
+    }
+
+    pub(super) fn on_write(&mut self, address: usize, old: u8, new: u8) {
+        if self.watching(address, Access::catches_writes) {
+            self.record(WatchTarget::Write(address as u16), old as u16, new as u16);
+        }
+    }
+
+    pub(super) fn register_values(&self, interpreter: &Interpreter) -> Vec<u16> {
+        self.registers
+            .iter()
+            .map(|register| register.read(interpreter))
+            .collect()
+    }
+
+    pub(super) fn on_registers(&mut self, before: &[u16], after: &[u16]) {
+        for index in 0..self.registers.len().min(before.len()).min(after.len()) {
+            if before[index] != after[index] {
+                let register = self.registers[index];
+                self.record(WatchTarget::Register(register), before[index], after[index]);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Access, WatchHit, WatchTarget};
+    use crate::asm::assemble;
+    use crate::interpreter::debugger::Register;
+    use crate::interpreter::Interpreter;
+
+    #[test]
+    fn test_memory_watch() {
+        let mut interpreter = Interpreter::new();
+        let rom = assemble(
+            "
+            LD I, 0x300
+            LD V0, 0x12
+            LD V1, 0x34
+            LD [I], V1
+            LD V2, [I]
+            ",
+        )
+        .unwrap();
+        interpreter.load_rom(&rom).unwrap();
+        interpreter
+            .watchpoints
+            .watch_memory(0x301, 1, Access::Write);
+
+        for _ in 0..3 {
+            interpreter.cycle().unwrap();
+        }
+        assert_eq!(interpreter.watchpoints.take_hit(), None);
+
+        interpreter.cycle().unwrap();
+        assert_eq!(
+            interpreter.watchpoints.take_hit(),
+            Some(WatchHit {
+                pc: 0x206,
+                opcode: 0xF155,
+                target: WatchTarget::Write(0x301),
+                old: 0x00,
+                new: 0x34,
+            })
+        );
+
+        // Reading isn't caught by a write watch
+        interpreter.cycle().unwrap();
+        assert_eq!(interpreter.watchpoints.take_hit(), None);
+    }
+
+    #[test]
+    fn test_register_watch() {
+        let mut interpreter = Interpreter::new();
+        interpreter.load_rom(&[0x63, 0x07, 0x63, 0x07]).unwrap();
+        interpreter.watchpoints.watch_register(Register::V(3));
+
+        interpreter.cycle().unwrap();
+        let hit = interpreter.watchpoints.take_hit().unwrap();
+        assert_eq!((hit.pc, hit.old, hit.new), (0x200, 0, 7));
+
+        // Writing the same value isn't a change
+        interpreter.cycle().unwrap();
+        assert_eq!(interpreter.watchpoints.take_hit(), None);
+    }
+}
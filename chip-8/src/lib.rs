@@ -1,2 +1,10 @@
+pub mod asm;
+pub mod audio;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod dap;
+pub mod disasm;
 pub mod exports;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod gdb;
 pub mod interpreter;
+pub mod octo;
@@ -0,0 +1,1046 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+
+use crate::disasm::{disassemble, Instruction};
+use crate::interpreter::variant::Variant;
+use crate::interpreter::Interpreter;
+
+// Macros expanding macros are fine, but not forever
+const MAX_MACRO_EXPANSIONS: usize = 10_000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OctoErrorKind {
+    UnexpectedEnd,
+    UnexpectedToken(String),
+    ExpectedRegister(String),
+    UnknownSymbol(String),
+    DuplicateSymbol(String),
+    OutOfRange(i64),
+    Unsupported(Instruction),
+    UnbalancedBlock(String),
+    MacroRecursion(String),
+    ProgramTooLarge(usize),
+}
+
+/// A compilation error and the line (starting at 1) it was found on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OctoError {
+    pub line: usize,
+    pub kind: OctoErrorKind,
+}
+
+impl fmt::Display for OctoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: ", self.line)?;
+
+        match &self.kind {
+            OctoErrorKind::UnexpectedEnd => write!(f, "unexpected end of the source"),
+            OctoErrorKind::UnexpectedToken(token) => write!(f, "unexpected {}", token),
+            OctoErrorKind::ExpectedRegister(token) => {
+                write!(f, "expected a register, found {}", token)
+            }
+            OctoErrorKind::UnknownSymbol(name) => write!(f, "unknown symbol {}", name),
+            OctoErrorKind::DuplicateSymbol(name) => write!(f, "{} is already defined", name),
+            OctoErrorKind::OutOfRange(value) => write!(f, "value {} is out of range", value),
+            OctoErrorKind::Unsupported(instruction) => {
+                write!(f, "{} isn't supported by this variant", instruction)
+            }
+            OctoErrorKind::UnbalancedBlock(keyword) => write!(f, "unbalanced {}", keyword),
+            OctoErrorKind::MacroRecursion(name) => write!(f, "macro {} never ends", name),
+            OctoErrorKind::ProgramTooLarge(size) => {
+                write!(f, "program is {} bytes, past the end of the memory", size)
+            }
+        }
+    }
+}
+
+impl std::error::Error for OctoError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Token {
+    text: String,
+    line: usize,
+}
+
+fn tokenize(source: &str) -> VecDeque<Token> {
+    let mut tokens = VecDeque::new();
+
+    for (index, line) in source.lines().enumerate() {
+        let code = line.split('#').next().unwrap_or("");
+
+        tokens.extend(code.split_whitespace().map(|text| Token {
+            text: text.to_string(),
+            line: index + 1,
+        }));
+    }
+
+    tokens
+}
+
+fn parse_number(text: &str) -> Option<i64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+
+    let value = if let Some(hex) = digits.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = digits.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()?
+    } else if digits.starts_with(|c: char| c.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+
+    Some(if negative { -value } else { value })
+}
+
+fn is_identifier(text: &str) -> bool {
+    text.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && text
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+/// The right side of a comparison.
+#[derive(Debug, Clone, Copy)]
+enum Operand {
+    Register(u8),
+    Byte(u8),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Condition {
+    Eq(u8, Operand),
+    Ne(u8, Operand),
+    Lt(u8, Operand),
+    Gt(u8, Operand),
+    Le(u8, Operand),
+    Ge(u8, Operand),
+    Key(u8),
+    NotKey(u8),
+}
+
+impl Condition {
+    fn negate(self) -> Condition {
+        match self {
+            Condition::Eq(x, operand) => Condition::Ne(x, operand),
+            Condition::Ne(x, operand) => Condition::Eq(x, operand),
+            Condition::Lt(x, operand) => Condition::Ge(x, operand),
+            Condition::Gt(x, operand) => Condition::Le(x, operand),
+            Condition::Le(x, operand) => Condition::Gt(x, operand),
+            Condition::Ge(x, operand) => Condition::Lt(x, operand),
+            Condition::Key(x) => Condition::NotKey(x),
+            Condition::NotKey(x) => Condition::Key(x),
+        }
+    }
+}
+
+/// An open `begin`, `else` or `loop`, waiting for its `end` or `again`.
+#[derive(Debug)]
+enum Block {
+    // Offset of the jump over the block, patched once its end is known
+    If {
+        jump: usize,
+        line: usize,
+    },
+    Else {
+        jump: usize,
+        line: usize,
+    },
+    Loop {
+        start: u16,
+        breaks: Vec<usize>,
+        line: usize,
+    },
+}
+
+#[derive(Debug)]
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Token>,
+}
+
+/// A label used before being defined, patched at the end.
+#[derive(Debug)]
+struct Fixup {
+    offset: usize,
+    name: String,
+    line: usize,
+    long: bool,
+}
+
+struct Compiler {
+    variant: Variant,
+    tokens: VecDeque<Token>,
+    line: usize,
+    rom: Vec<u8>,
+    labels: HashMap<String, u16>,
+    constants: HashMap<String, i64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    fixups: Vec<Fixup>,
+    blocks: Vec<Block>,
+    // Whether the first 2 bytes are a `jump main` to patch
+    main_jump: bool,
+    expansions: usize,
+}
+
+impl Compiler {
+    fn here(&self) -> u16 {
+        Interpreter::PROGRAM_START.wrapping_add(self.rom.len() as u16)
+    }
+
+    fn next(&mut self) -> Result<Token, OctoErrorKind> {
+        let token = self
+            .tokens
+            .pop_front()
+            .ok_or(OctoErrorKind::UnexpectedEnd)?;
+        self.line = token.line;
+
+        Ok(token)
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.front().map(|token| token.text.as_str())
+    }
+
+    fn expect(&mut self, text: &str) -> Result<(), OctoErrorKind> {
+        let token = self.next()?;
+
+        match token.text == text {
+            true => Ok(()),
+            false => Err(OctoErrorKind::UnexpectedToken(token.text)),
+        }
+    }
+
+    fn emit(&mut self, instruction: Instruction) -> Result<(), OctoErrorKind> {
+        match instruction {
+            Instruction::Jp(nnn)
+            | Instruction::Call(nnn)
+            | Instruction::LdINnn(nnn)
+            | Instruction::JpV0Nnn(nnn)
+                if nnn > 0xFFF =>
+            {
+                return Err(OctoErrorKind::OutOfRange(nnn as i64));
+            }
+            _ => {}
+        }
+
+        let opcode = instruction.encode();
+
+        if let Instruction::Unknown(_) = disassemble(opcode, self.variant) {
+            return Err(OctoErrorKind::Unsupported(instruction));
+        }
+
+        self.rom.extend_from_slice(&opcode.to_be_bytes());
+        Ok(())
+    }
+
+    fn patch_jump(&mut self, offset: usize, target: u16) -> Result<(), OctoErrorKind> {
+        if target > 0xFFF {
+            return Err(OctoErrorKind::OutOfRange(target as i64));
+        }
+
+        let opcode = Instruction::Jp(target).encode().to_be_bytes();
+        self.rom[offset..offset + 2].copy_from_slice(&opcode);
+        Ok(())
+    }
+
+    fn define(&mut self, name: &str) -> Result<(), OctoErrorKind> {
+        if !is_identifier(name) || self.parse_register(name).is_some() {
+            return Err(OctoErrorKind::UnexpectedToken(name.to_string()));
+        }
+
+        if self.labels.contains_key(name)
+            || self.constants.contains_key(name)
+            || self.aliases.contains_key(name)
+            || self.macros.contains_key(name)
+        {
+            return Err(OctoErrorKind::DuplicateSymbol(name.to_string()));
+        }
+
+        Ok(())
+    }
+
+    fn parse_register(&self, text: &str) -> Option<u8> {
+        if let Some(&register) = self.aliases.get(text) {
+            return Some(register);
+        }
+
+        let digit = text.strip_prefix('v').or_else(|| text.strip_prefix('V'))?;
+        match digit.len() {
+            1 => u8::from_str_radix(digit, 16).ok(),
+            _ => None,
+        }
+    }
+
+    fn peek_register(&self) -> Option<u8> {
+        self.peek().and_then(|text| self.parse_register(text))
+    }
+
+    fn register(&mut self) -> Result<u8, OctoErrorKind> {
+        let token = self.next()?;
+
+        self.parse_register(&token.text)
+            .ok_or(OctoErrorKind::ExpectedRegister(token.text))
+    }
+
+    fn parse_value(&self, text: &str) -> Option<i64> {
+        parse_number(text).or_else(|| self.constants.get(text).copied())
+    }
+
+    fn value(&mut self, min: i64, max: i64) -> Result<i64, OctoErrorKind> {
+        let token = self.next()?;
+        let value = match self.parse_value(&token.text) {
+            Some(value) => value,
+            None if is_identifier(&token.text) => {
+                return Err(OctoErrorKind::UnknownSymbol(token.text))
+            }
+            None => return Err(OctoErrorKind::UnexpectedToken(token.text)),
+        };
+
+        match (min..=max).contains(&value) {
+            true => Ok(value),
+            false => Err(OctoErrorKind::OutOfRange(value)),
+        }
+    }
+
+    // Bytes may be written signed, -1 being 0xFF
+    fn byte(&mut self) -> Result<u8, OctoErrorKind> {
+        Ok(self.value(-0x80, 0xFF)? as u8)
+    }
+
+    fn nibble(&mut self) -> Result<u8, OctoErrorKind> {
+        Ok(self.value(0, 0xF)? as u8)
+    }
+
+    /// Read an address for the instruction about to be emitted, 12 bits or a
+    /// whole word for `long`. Labels not defined yet are patched at the end.
+    fn address(&mut self, long: bool) -> Result<u16, OctoErrorKind> {
+        let max = if long { 0xFFFF } else { 0xFFF };
+        let token = self.next()?;
+
+        let value = match self.parse_value(&token.text) {
+            Some(value) => value,
+            None => match self.labels.get(&token.text) {
+                Some(&address) => address as i64,
+                None if is_identifier(&token.text) => {
+                    self.fixups.push(Fixup {
+                        offset: self.rom.len() + if long { 2 } else { 0 },
+                        name: token.text,
+                        line: token.line,
+                        long,
+                    });
+                    0
+                }
+                None => return Err(OctoErrorKind::UnexpectedToken(token.text)),
+            },
+        };
+
+        match (0..=max).contains(&value) {
+            true => Ok(value as u16),
+            false => Err(OctoErrorKind::OutOfRange(value)),
+        }
+    }
+
+    fn operand(&mut self) -> Result<Operand, OctoErrorKind> {
+        match self.peek_register() {
+            Some(register) => {
+                self.next()?;
+                Ok(Operand::Register(register))
+            }
+            None => Ok(Operand::Byte(self.byte()?)),
+        }
+    }
+
+    fn condition(&mut self) -> Result<Condition, OctoErrorKind> {
+        let x = self.register()?;
+        let operator = self.next()?;
+
+        match operator.text.as_str() {
+            "key" => Ok(Condition::Key(x)),
+            "-key" => Ok(Condition::NotKey(x)),
+            "==" => Ok(Condition::Eq(x, self.operand()?)),
+            "!=" => Ok(Condition::Ne(x, self.operand()?)),
+            "<" => Ok(Condition::Lt(x, self.operand()?)),
+            ">" => Ok(Condition::Gt(x, self.operand()?)),
+            "<=" => Ok(Condition::Le(x, self.operand()?)),
+            ">=" => Ok(Condition::Ge(x, self.operand()?)),
+            _ => Err(OctoErrorKind::UnexpectedToken(operator.text)),
+        }
+    }
+
+    /// Leave in VF whether `a >= b`, the flag of `a - b`.
+    fn emit_greater_or_equal(&mut self, a: Operand, b: Operand) -> Result<(), OctoErrorKind> {
+        match (a, b) {
+            (Operand::Register(a), Operand::Register(b)) => {
+                self.emit(Instruction::LdVxVy(0xF, a))?;
+                self.emit(Instruction::SubVxVy(0xF, b))
+            }
+            (Operand::Register(a), Operand::Byte(b)) => {
+                self.emit(Instruction::LdVxKk(0xF, b))?;
+                self.emit(Instruction::SubnVxVy(0xF, a))
+            }
+            (Operand::Byte(a), Operand::Register(b)) => {
+                self.emit(Instruction::LdVxKk(0xF, a))?;
+                self.emit(Instruction::SubVxVy(0xF, b))
+            }
+            (Operand::Byte(_), Operand::Byte(_)) => {
+                unreachable!("comparisons start with a register")
+            }
+        }
+    }
+
+    /// Emit the instructions skipping the next one when `condition` is false.
+    fn emit_skip_unless(&mut self, condition: Condition) -> Result<(), OctoErrorKind> {
+        match condition {
+            Condition::Eq(x, Operand::Register(y)) => self.emit(Instruction::SneVxVy(x, y)),
+            Condition::Eq(x, Operand::Byte(kk)) => self.emit(Instruction::SneVxKk(x, kk)),
+            Condition::Ne(x, Operand::Register(y)) => self.emit(Instruction::SeVxVy(x, y)),
+            Condition::Ne(x, Operand::Byte(kk)) => self.emit(Instruction::SeVxKk(x, kk)),
+            Condition::Key(x) => self.emit(Instruction::SknpVx(x)),
+            Condition::NotKey(x) => self.emit(Instruction::SkpVx(x)),
+            Condition::Lt(x, operand) => {
+                self.emit_greater_or_equal(Operand::Register(x), operand)?;
+                self.emit(Instruction::SeVxKk(0xF, 1))
+            }
+            Condition::Ge(x, operand) => {
+                self.emit_greater_or_equal(Operand::Register(x), operand)?;
+                self.emit(Instruction::SeVxKk(0xF, 0))
+            }
+            Condition::Gt(x, operand) => {
+                self.emit_greater_or_equal(operand, Operand::Register(x))?;
+                self.emit(Instruction::SeVxKk(0xF, 1))
+            }
+            Condition::Le(x, operand) => {
+                self.emit_greater_or_equal(operand, Operand::Register(x))?;
+                self.emit(Instruction::SeVxKk(0xF, 0))
+            }
+        }
+    }
+
+    /// Emit a jump to be patched later, returning its offset.
+    fn emit_jump_placeholder(&mut self) -> Result<usize, OctoErrorKind> {
+        let offset = self.rom.len();
+        self.emit(Instruction::Jp(0))?;
+
+        Ok(offset)
+    }
+
+    fn label(&mut self) -> Result<(), OctoErrorKind> {
+        let name = self.next()?.text;
+        self.define(&name)?;
+
+        // Nothing before main, no need to jump to it. Labels already defined are at
+        // main too and move back with it.
+        if name == "main" && self.main_jump && self.rom.len() == 2 {
+            self.rom.clear();
+            self.main_jump = false;
+            let main = self.here();
+            for address in self.labels.values_mut() {
+                *address = main;
+            }
+        }
+
+        self.labels.insert(name, self.here());
+        Ok(())
+    }
+
+    fn macro_definition(&mut self) -> Result<(), OctoErrorKind> {
+        let name = self.next()?.text;
+        self.define(&name)?;
+
+        let mut params = Vec::new();
+        loop {
+            let token = self.next()?;
+            match token.text.as_str() {
+                "{" => break,
+                _ => params.push(token.text),
+            }
+        }
+
+        let mut body = Vec::new();
+        let mut depth = 1;
+        loop {
+            let token = self.next()?;
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" if depth == 1 => break,
+                "}" => depth -= 1,
+                _ => {}
+            }
+            body.push(token);
+        }
+
+        self.macros.insert(name, Macro { params, body });
+        Ok(())
+    }
+
+    fn expand_macro(&mut self, name: &str) -> Result<(), OctoErrorKind> {
+        self.expansions += 1;
+        if self.expansions > MAX_MACRO_EXPANSIONS {
+            return Err(OctoErrorKind::MacroRecursion(name.to_string()));
+        }
+
+        let line = self.line;
+        let mut arguments = HashMap::new();
+        for param in self.macros[name].params.clone() {
+            arguments.insert(param, self.next()?.text);
+        }
+
+        let expansion: Vec<Token> = self.macros[name]
+            .body
+            .iter()
+            .map(|token| Token {
+                text: arguments.get(&token.text).unwrap_or(&token.text).clone(),
+                line,
+            })
+            .collect();
+
+        for token in expansion.into_iter().rev() {
+            self.tokens.push_front(token);
+        }
+
+        Ok(())
+    }
+
+    fn register_statement(&mut self, x: u8) -> Result<(), OctoErrorKind> {
+        let operator = self.next()?.text;
+        let y = self.peek_register();
+        if y.is_some() {
+            self.next()?;
+        }
+
+        let instruction = match (operator.as_str(), y) {
+            (":=", Some(y)) => Instruction::LdVxVy(x, y),
+            (":=", None) => match self.peek() {
+                Some("random") => {
+                    self.next()?;
+                    Instruction::RndVxKk(x, self.byte()?)
+                }
+                Some("delay") => {
+                    self.next()?;
+                    Instruction::LdVxDt(x)
+                }
+                Some("key") => {
+                    self.next()?;
+                    Instruction::LdVxK(x)
+                }
+                _ => Instruction::LdVxKk(x, self.byte()?),
+            },
+            ("+=", Some(y)) => Instruction::AddVxVy(x, y),
+            ("+=", None) => Instruction::AddVxKk(x, self.byte()?),
+            ("-=", Some(y)) => Instruction::SubVxVy(x, y),
+            ("-=", None) => Instruction::AddVxKk(x, self.byte()?.wrapping_neg()),
+            ("=-", Some(y)) => Instruction::SubnVxVy(x, y),
+            ("|=", Some(y)) => Instruction::OrVxVy(x, y),
+            ("&=", Some(y)) => Instruction::AndVxVy(x, y),
+            ("^=", Some(y)) => Instruction::XorVxVy(x, y),
+            (">>=", Some(y)) => Instruction::ShrVxVy(x, y),
+            ("<<=", Some(y)) => Instruction::ShlVxVy(x, y),
+            (_, None) if ["=-", "|=", "&=", "^=", ">>=", "<<="].contains(&operator.as_str()) => {
+                let token = self.next()?.text;
+                return Err(OctoErrorKind::ExpectedRegister(token));
+            }
+            _ => return Err(OctoErrorKind::UnexpectedToken(operator)),
+        };
+
+        self.emit(instruction)
+    }
+
+    fn i_statement(&mut self) -> Result<(), OctoErrorKind> {
+        let operator = self.next()?.text;
+
+        match operator.as_str() {
+            ":=" => match self.peek() {
+                Some("hex") => {
+                    self.next()?;
+                    let x = self.register()?;
+                    self.emit(Instruction::LdFVx(x))
+                }
+                Some("bighex") => {
+                    self.next()?;
+                    let x = self.register()?;
+                    self.emit(Instruction::LdHfVx(x))
+                }
+                Some("long") => {
+                    self.next()?;
+                    let address = self.address(true)?;
+                    self.emit(Instruction::LdILong)?;
+                    self.rom.extend_from_slice(&address.to_be_bytes());
+                    Ok(())
+                }
+                _ => {
+                    let address = self.address(false)?;
+                    self.emit(Instruction::LdINnn(address))
+                }
+            },
+            "+=" => {
+                let x = self.register()?;
+                self.emit(Instruction::AddIVx(x))
+            }
+            _ => Err(OctoErrorKind::UnexpectedToken(operator)),
+        }
+    }
+
+    fn statement(&mut self) -> Result<(), OctoErrorKind> {
+        let token = self.next()?;
+
+        match token.text.as_str() {
+            ":" => self.label(),
+            ":const" => {
+                let name = self.next()?.text;
+                self.define(&name)?;
+                let value = self.value(i64::MIN, i64::MAX)?;
+                self.constants.insert(name, value);
+                Ok(())
+            }
+            ":alias" => {
+                let name = self.next()?.text;
+                self.define(&name)?;
+                let register = self.register()?;
+                self.aliases.insert(name, register);
+                Ok(())
+            }
+            ":macro" => self.macro_definition(),
+            ":byte" => {
+                let byte = self.byte()?;
+                self.rom.push(byte);
+                Ok(())
+            }
+            ":call" => {
+                let address = self.address(false)?;
+                self.emit(Instruction::Call(address))
+            }
+            "return" | ";" => self.emit(Instruction::Ret),
+            "clear" => self.emit(Instruction::Cls),
+            "hires" => self.emit(Instruction::High),
+            "lores" => self.emit(Instruction::Low),
+            "exit" => self.emit(Instruction::Exit),
+            "scroll-left" => self.emit(Instruction::Scl),
+            "scroll-right" => self.emit(Instruction::Scr),
+            "scroll-down" => {
+                let n = self.nibble()?;
+                self.emit(Instruction::Scd(n))
+            }
+            "scroll-up" => {
+                let n = self.nibble()?;
+                self.emit(Instruction::Scu(n))
+            }
+            "audio" => self.emit(Instruction::Audio),
+            "plane" => {
+                let n = self.nibble()?;
+                self.emit(Instruction::Plane(n))
+            }
+            "bcd" => {
+                let x = self.register()?;
+                self.emit(Instruction::LdBVx(x))
+            }
+            "save" | "load" => {
+                let x = self.register()?;
+                let save = token.text == "save";
+                let instruction = match self.peek() {
+                    Some("-") => {
+                        self.next()?;
+                        let y = self.register()?;
+                        match save {
+                            true => Instruction::LdIVxVy(x, y),
+                            false => Instruction::LdVxVyI(x, y),
+                        }
+                    }
+                    _ => match save {
+                        true => Instruction::LdIVx(x),
+                        false => Instruction::LdVxI(x),
+                    },
+                };
+                self.emit(instruction)
+            }
+            "saveflags" => {
+                let x = self.register()?;
+                self.emit(Instruction::LdRVx(x))
+            }
+            "loadflags" => {
+                let x = self.register()?;
+                self.emit(Instruction::LdVxR(x))
+            }
+            "sprite" => {
+                let x = self.register()?;
+                let y = self.register()?;
+                let n = self.nibble()?;
+                self.emit(Instruction::DrwVxVyN(x, y, n))
+            }
+            "jump" => {
+                let address = self.address(false)?;
+                self.emit(Instruction::Jp(address))
+            }
+            "jump0" => {
+                let address = self.address(false)?;
+                self.emit(Instruction::JpV0Nnn(address))
+            }
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let x = self.register()?;
+                self.emit(match token.text.as_str() {
+                    "delay" => Instruction::LdDtVx(x),
+                    "buzzer" => Instruction::LdStVx(x),
+                    _ => Instruction::PitchVx(x),
+                })
+            }
+            "i" => self.i_statement(),
+            "if" => {
+                let condition = self.condition()?;
+                let keyword = self.next()?;
+
+                match keyword.text.as_str() {
+                    "then" => self.emit_skip_unless(condition),
+                    "begin" => {
+                        self.emit_skip_unless(condition.negate())?;
+                        let jump = self.emit_jump_placeholder()?;
+                        self.blocks.push(Block::If {
+                            jump,
+                            line: token.line,
+                        });
+                        Ok(())
+                    }
+                    _ => Err(OctoErrorKind::UnexpectedToken(keyword.text)),
+                }
+            }
+            "else" => match self.blocks.pop() {
+                Some(Block::If { jump, line }) => {
+                    let end_jump = self.emit_jump_placeholder()?;
+                    self.patch_jump(jump, self.here())?;
+                    self.blocks.push(Block::Else {
+                        jump: end_jump,
+                        line,
+                    });
+                    Ok(())
+                }
+                _ => Err(OctoErrorKind::UnbalancedBlock(token.text)),
+            },
+            "end" => match self.blocks.pop() {
+                Some(Block::If { jump, .. }) | Some(Block::Else { jump, .. }) => {
+                    self.patch_jump(jump, self.here())
+                }
+                _ => Err(OctoErrorKind::UnbalancedBlock(token.text)),
+            },
+            "loop" => {
+                self.blocks.push(Block::Loop {
+                    start: self.here(),
+                    breaks: Vec::new(),
+                    line: token.line,
+                });
+                Ok(())
+            }
+            "while" => {
+                let condition = self.condition()?;
+                self.emit_skip_unless(condition.negate())?;
+                let jump = self.emit_jump_placeholder()?;
+
+                let innermost = self.blocks.iter_mut().rev().find_map(|block| match block {
+                    Block::Loop { breaks, .. } => Some(breaks),
+                    _ => None,
+                });
+                match innermost {
+                    Some(breaks) => {
+                        breaks.push(jump);
+                        Ok(())
+                    }
+                    None => Err(OctoErrorKind::UnbalancedBlock(token.text)),
+                }
+            }
+            "again" => match self.blocks.pop() {
+                Some(Block::Loop { start, breaks, .. }) => {
+                    self.emit(Instruction::Jp(start))?;
+                    for jump in breaks {
+                        self.patch_jump(jump, self.here())?;
+                    }
+                    Ok(())
+                }
+                _ => Err(OctoErrorKind::UnbalancedBlock(token.text)),
+            },
+            text => {
+                if let Some(x) = self.parse_register(text) {
+                    return self.register_statement(x);
+                }
+
+                if self.macros.contains_key(text) {
+                    return self.expand_macro(text);
+                }
+
+                // A bare number is data
+                if let Some(value) = self.parse_value(text) {
+                    if !(-0x80..=0xFF).contains(&value) {
+                        return Err(OctoErrorKind::OutOfRange(value));
+                    }
+                    self.rom.push(value as u8);
+                    return Ok(());
+                }
+
+                // Anything else names a subroutine, maybe defined further down
+                if is_identifier(text) {
+                    self.tokens.push_front(token);
+                    let address = self.address(false)?;
+                    return self.emit(Instruction::Call(address));
+                }
+
+                Err(OctoErrorKind::UnexpectedToken(token.text))
+            }
+        }
+    }
+
+    fn finish(&mut self) -> Result<(), OctoError> {
+        if let Some(block) = self.blocks.last() {
+            let (keyword, line) = match block {
+                Block::If { line, .. } | Block::Else { line, .. } => ("begin", *line),
+                Block::Loop { line, .. } => ("loop", *line),
+            };
+            return Err(OctoError {
+                line,
+                kind: OctoErrorKind::UnbalancedBlock(keyword.to_string()),
+            });
+        }
+
+        if self.main_jump {
+            let error = |kind| OctoError { line: 1, kind };
+            let main = *self
+                .labels
+                .get("main")
+                .ok_or_else(|| error(OctoErrorKind::UnknownSymbol("main".to_string())))?;
+            self.patch_jump(0, main).map_err(error)?;
+        }
+
+        for fixup in std::mem::take(&mut self.fixups) {
+            let error = |kind| OctoError {
+                line: fixup.line,
+                kind,
+            };
+            let address = *self
+                .labels
+                .get(&fixup.name)
+                .ok_or_else(|| error(OctoErrorKind::UnknownSymbol(fixup.name.clone())))?;
+
+            if fixup.long {
+                self.rom[fixup.offset..fixup.offset + 2].copy_from_slice(&address.to_be_bytes());
+            } else if address > 0xFFF {
+                return Err(error(OctoErrorKind::OutOfRange(address as i64)));
+            } else {
+                self.rom[fixup.offset] |= (address >> 8) as u8;
+                self.rom[fixup.offset + 1] = address as u8;
+            }
+        }
+
+        let max = self.variant.memory_size() - Interpreter::PROGRAM_START as usize;
+        if self.rom.len() > max {
+            return Err(OctoError {
+                line: self.line,
+                kind: OctoErrorKind::ProgramTooLarge(self.rom.len()),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// Whether `: main` appears outside of a macro body, where it is sure to be defined.
+fn defines_main(tokens: &VecDeque<Token>) -> bool {
+    let mut depth = 0;
+    let mut in_macro = false;
+
+    for (token, next) in tokens.iter().zip(tokens.iter().skip(1)) {
+        match token.text.as_str() {
+            ":macro" => in_macro = true,
+            "{" if in_macro => depth += 1,
+            "}" if in_macro => {
+                depth -= 1;
+                in_macro = depth > 0;
+            }
+            ":" if !in_macro && next.text == "main" => return true,
+            _ => {}
+        }
+    }
+
+    false
+}
+
+/// Compile Octo source into a ROM loaded at 0x200, using only the
+/// instructions `variant` knows.
+///
+/// Execution starts at the `main` label when there is one, else at the top.
+/// Comparisons other than `==`, `!=`, `key` and `-key` go through VF like in Octo.
+pub fn compile(source: &str, variant: Variant) -> Result<Vec<u8>, OctoError> {
+    let tokens = tokenize(source);
+    let main_jump = defines_main(&tokens);
+
+    let mut compiler = Compiler {
+        variant,
+        tokens,
+        line: 1,
+        rom: Vec::new(),
+        labels: HashMap::new(),
+        constants: HashMap::new(),
+        aliases: HashMap::new(),
+        macros: HashMap::new(),
+        fixups: Vec::new(),
+        blocks: Vec::new(),
+        main_jump,
+        expansions: 0,
+    };
+
+    if main_jump {
+        compiler.rom.extend_from_slice(&[0x00, 0x00]);
+    }
+
+    while !compiler.tokens.is_empty() {
+        compiler.statement().map_err(|kind| OctoError {
+            line: compiler.line,
+            kind,
+        })?;
+    }
+
+    compiler.finish()?;
+
+    Ok(compiler.rom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compile, OctoError, OctoErrorKind};
+    use crate::disasm::Instruction;
+    use crate::interpreter::variant::Variant;
+    use crate::interpreter::Interpreter;
+
+    #[test]
+    fn test_compile() {
+        let rom = compile(
+            "
+            :alias counter v3
+            :const SPEED 5
+
+            : main
+                counter := 0xAA
+                v1 += SPEED
+                if v1 == 5 then counter -= 1
+                i := sprite
+                sprite v0 v1 2
+                loop again
+
+            : sprite 0b11110000 -1
+            ",
+            Variant::Chip8,
+        )
+        .unwrap();
+
+        assert_eq!(
+            rom,
+            [
+                0x63, 0xAA, 0x71, 0x05, 0x41, 0x05, 0x73, 0xFF, 0xA2, 0x0E, 0xD0, 0x12, 0x12, 0x0C,
+                0xF0, 0xFF,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_main_jump() {
+        let rom = compile(": helper return : main helper", Variant::Chip8).unwrap();
+
+        assert_eq!(rom, [0x12, 0x04, 0x00, 0xEE, 0x22, 0x02]);
+
+        // A main that only a macro never expanded would define isn't one
+        let rom = compile(":macro start { : main } v0 := 1", Variant::Chip8).unwrap();
+        assert_eq!(rom, [0x60, 0x01]);
+
+        let rom = compile(": foo : main v0 += 1 jump foo", Variant::Chip8).unwrap();
+        assert_eq!(rom, [0x70, 0x01, 0x12, 0x00]);
+    }
+
+    #[test]
+    fn test_run_compiled() {
+        let rom = compile(
+            "
+            :macro add-twice reg amount { reg += amount reg += amount }
+
+            : main
+                v0 := 0
+                loop
+                    add-twice v0 2
+                    if v0 >= 20 begin
+                        v1 := 1
+                    else
+                        v2 += 1
+                    end
+                    while v0 < 40
+                again
+                v3 := v0
+            : halt
+                jump halt
+            ",
+            Variant::Chip8,
+        )
+        .unwrap();
+
+        let mut interpreter = Interpreter::new();
+        interpreter.load_rom(&rom).unwrap();
+        for _ in 0..500 {
+            interpreter.cycle().unwrap();
+        }
+
+        assert_eq!(interpreter.v[0], 40);
+        assert_eq!(interpreter.v[1], 1);
+        assert_eq!(interpreter.v[2], 4);
+        assert_eq!(interpreter.v[3], 40);
+    }
+
+    #[test]
+    fn test_variants() {
+        assert_eq!(
+            compile("hires", Variant::Chip8),
+            Err(OctoError {
+                line: 1,
+                kind: OctoErrorKind::Unsupported(Instruction::High)
+            })
+        );
+        assert_eq!(compile("hires", Variant::SuperChip), Ok(vec![0x00, 0xFF]));
+        assert_eq!(
+            compile("i := long data : data", Variant::XoChip),
+            Ok(vec![0xF0, 0x00, 0x02, 0x04])
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |line, kind| Err(OctoError { line, kind });
+
+        assert_eq!(
+            compile("v0 := 1\nv1 := 300", Variant::Chip8),
+            error(2, OctoErrorKind::OutOfRange(300))
+        );
+        // A loop jumping back above 0xFFF
+        let source = format!("{}\nloop v0 += 1 again", "0 ".repeat(4000));
+        assert_eq!(
+            compile(&source, Variant::XoChip),
+            error(2, OctoErrorKind::OutOfRange(0x11A0))
+        );
+        assert_eq!(
+            compile("\njump nowhere", Variant::Chip8),
+            error(2, OctoErrorKind::UnknownSymbol("nowhere".to_string()))
+        );
+        assert_eq!(
+            compile("loop\nv0 += 1", Variant::Chip8),
+            error(1, OctoErrorKind::UnbalancedBlock("loop".to_string()))
+        );
+        assert_eq!(
+            compile("end", Variant::Chip8),
+            error(1, OctoErrorKind::UnbalancedBlock("end".to_string()))
+        );
+        assert_eq!(
+            compile(": a : a", Variant::Chip8),
+            error(1, OctoErrorKind::DuplicateSymbol("a".to_string()))
+        );
+        assert_eq!(
+            compile(":macro forever { forever } forever", Variant::Chip8),
+            error(1, OctoErrorKind::MacroRecursion("forever".to_string()))
+        );
+    }
+}
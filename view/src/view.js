@@ -1,6 +1,11 @@
 import '../style/stylesheet.css';
 
-const INSTRUCTIONS_PER_STEP = 10;
+const INSTRUCTIONS_PER_FRAME = 10;
+// Longest time run in one go, so coming back to a background tab doesn't fast-forward
+const MAX_STEP_MICROS = 100000;
+const REWIND_FRAMES_PER_PRESS = 30;
+// Written for the VIP, where a sprite drawn waits for the next frame
+const DISPLAY_WAIT_GAMES = ['Breakout', 'SpaceInvaders'];
 
 function mapCodeToKeypadKey(code) {
     return {
@@ -38,46 +43,59 @@ async function start() {
     const instanceExports = interpreter.instance.exports;
 
     let requestAnimationFrameID = null;
-
-    const interpreterMemory = new Uint8Array(
-        instanceExports.memory.buffer,
-        instanceExports.get_memory(),
-        4096
-    );
-
-    const pixelsMemory = new Uint8Array(
-        instanceExports.memory.buffer,
-        instanceExports.get_pixels(),
-        2048
-    );
-
-    const width = instanceExports.get_width();
-    const height = instanceExports.get_height();
+    const handle = instanceExports.create();
+    // About 10 seconds of gameplay, a state every other frame
+    instanceExports.instance_enable_rewind(handle, 300, 2);
+    instanceExports.instance_set_instructions_per_frame(handle, INSTRUCTIONS_PER_FRAME);
+    // Timestamp of the previous animation frame, null until the first one
+    let lastTimestamp = null;
 
     const canvas = document.getElementById('chip-8-canvas');
     const ctx = canvas.getContext('2d');
     ctx.fillStyle = 'black';
-    ctx.fillRect(0, 0, width, height);
+    ctx.fillRect(0, 0, canvas.width, canvas.height);
 
     const loadButton = document.getElementById('btn-load-game');
     loadButton.addEventListener('click', async () => {
-        await loadGame(`${document.getElementById('slct-game').value}.ch8`);
+        const game = document.getElementById('slct-game').value;
+        await loadGame(`${game}.ch8`);
+        instanceExports.instance_set_display_wait(handle, DISPLAY_WAIT_GAMES.includes(game));
 
         if (requestAnimationFrameID) {
             cancelAnimationFrame(requestAnimationFrameID);
         }
 
-        loop();
+        lastTimestamp = null;
+        requestAnimationFrameID = window.requestAnimationFrame(loop);
+    });
+
+    document.getElementById('btn-save-state').addEventListener('click', () => {
+        const slot = Number(document.getElementById('slct-state-slot').value);
+        instanceExports.instance_save_state(handle, slot);
+    });
+
+    document.getElementById('btn-load-state').addEventListener('click', () => {
+        const slot = Number(document.getElementById('slct-state-slot').value);
+        const error = instanceExports.instance_load_state(handle, slot);
+
+        if (error) {
+            console.log(`Cannot restore slot ${slot + 1}, error code ${error}`);
+        }
     });
 
     document.addEventListener('keydown', (event) => {
+        if (event.code === 'Backspace') {
+            instanceExports.instance_rewind(handle, REWIND_FRAMES_PER_PRESS);
+            return;
+        }
+
         const keypadKey = mapCodeToKeypadKey(event.code);
-        instanceExports.set_key_down(keypadKey);
+        instanceExports.instance_set_key_down(handle, keypadKey);
     });
 
     document.addEventListener('keyup', (event) => {
         const keypadKey = mapCodeToKeypadKey(event.code);
-        instanceExports.set_key_up(keypadKey);
+        instanceExports.instance_set_key_up(handle, keypadKey);
     });
 
     [
@@ -106,7 +124,7 @@ async function start() {
 
     [
         'Breakout',
-        // 'Danm8ku', need rnd
+        'Danm8ku',
         'IBMLogo',
         'KeypadTest',
         'Maze',
@@ -126,12 +144,19 @@ async function start() {
         document.getElementById('slct-game').appendChild(option);
     });
 
-    function loop() {
-        for (let i = 0; i < INSTRUCTIONS_PER_STEP; i++) {
-            instanceExports.cycle();
+    function loop(timestamp) {
+        // The interpreter keeps 60 frames per second whatever the refresh rate
+        if (lastTimestamp !== null) {
+            const micros = Math.min((timestamp - lastTimestamp) * 1000, MAX_STEP_MICROS);
+            const error = instanceExports.instance_run_for(handle, Math.round(micros));
+
+            if (error) {
+                console.log(`Interpreter halted with error code ${error}`);
+                requestAnimationFrameID = null;
+                return;
+            }
         }
-
-        instanceExports.tick();
+        lastTimestamp = timestamp;
 
         render();
 
@@ -139,16 +164,24 @@ async function start() {
     }
 
     function render() {
-        const imageData = ctx.createImageData(width, height);
-
-        for (let i = 0; i < pixelsMemory.length; i++) {
-            // See: https://developer.mozilla.org/en-US/docs/Web/API/Canvas_API/Tutorial/Pixel_manipulation_with_canvas
-            imageData.data[i * 4] = pixelsMemory[i] === 1 ? 255 : 0;
-            imageData.data[i * 4 + 1] = pixelsMemory[i] === 1 ? 255 : 0;
-            imageData.data[i * 4 + 2] = pixelsMemory[i] === 1 ? 255 : 0;
-            imageData.data[i * 4 + 3] = 255;
+        const width = instanceExports.instance_get_width(handle);
+        const height = instanceExports.instance_get_height(handle);
+
+        if (canvas.width !== width || canvas.height !== height) {
+            canvas.width = width;
+            canvas.height = height;
         }
 
+        // Rendering may grow the WASM memory, so only look at its buffer afterwards
+        const rgbaPointer = instanceExports.instance_render_rgba(handle);
+        // Created every frame since growing the WASM memory detaches older views
+        const rgba = new Uint8ClampedArray(
+            instanceExports.memory.buffer,
+            rgbaPointer,
+            width * height * 4
+        );
+        const imageData = new ImageData(rgba, width, height);
+
         ctx.putImageData(imageData, 0, 0);
     }
 
@@ -158,12 +191,20 @@ async function start() {
         await fetch(`games/${filename}`)
             .then((f) => f.arrayBuffer())
             .then((buffer) => {
-                const game = new DataView(buffer, 0, buffer.byteLength);
-                instanceExports.init();
+                const length = buffer.byteLength;
+                const pointer = instanceExports.alloc_rom_buffer(length);
+                new Uint8Array(instanceExports.memory.buffer, pointer, length).set(
+                    new Uint8Array(buffer)
+                );
 
-                for (let byte = 0; byte < game.byteLength; byte++) {
-                    interpreterMemory[0x200 + byte] = game.getUint8(byte);
+                const error = instanceExports.instance_load_rom(handle, pointer, length);
+                instanceExports.free_rom_buffer(pointer, length);
+
+                if (error) {
+                    throw new Error(`Cannot load ${filename}, error code ${error}`);
                 }
+
+                instanceExports.instance_set_seed(handle, BigInt(Date.now()));
             });
     }
 }